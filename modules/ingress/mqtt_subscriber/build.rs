@@ -0,0 +1,5 @@
+//! Generate the protobuf message types from the schemas in `proto/`
+
+fn main() -> std::io::Result<()> {
+    prost_build::compile_protos(&["proto/sensor_data.proto"], &["proto/"])
+}
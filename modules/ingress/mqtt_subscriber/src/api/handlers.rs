@@ -1,26 +1,66 @@
 //! API request handlers
 
+use arc_swap::ArcSwap;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::Json,
 };
 use chrono;
 use log::{error, info};
+use std::borrow::Cow;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, SystemTime};
 
 use super::models::{
-    ApiResponse, HealthResponse, MetricsResponse, SubscribeRequest, TopicsResponse,
+    AllocResponse, ApiResponse, CapturedPacketResponse, ClassMetricsResponse,
+    DiscoveredTopicsResponse, ErrorResponse, FreshnessQuery, FreshnessResponse, HealthResponse,
+    LifetimeMetricsResponse, MetricsQuery, MetricsResponse, PacketsResponse, ReadyResponse,
+    ScalingHintResponse, SubscribeRequest, SubscribeResponse, TopicCountsResponse,
+    TopicMetricsResponse, TopicsResponse, UnsubscribeQuery, VersionResponse, WindowResponse,
+    WindowsResponse,
 };
-use crate::mqtt::subscriber::MqttSubscriber;
-use crate::{kafka::producer::KafkaProducer, metrics::MessageMetrics};
+use crate::error::SpineError;
+use crate::kafka::producer::KafkaProducer;
+use crate::metrics::{
+    LifetimeMetrics, LoadGauges, MetricsSnapshot, QueueDepth, TopicMetrics, WindowedMetrics,
+    WINDOW_DURATION,
+};
+use crate::mqtt::subscriber::{validate_filter, MqttSubscriber};
+use crate::sink::http::HttpSink;
 
 /// State type for API handlers
 pub struct AppState {
     pub subscriber: Arc<MqttSubscriber>,
     pub kafka_producer: Arc<KafkaProducer>,
-    pub metrics: Arc<RwLock<MessageMetrics>>,
+    pub metrics: Arc<ArcSwap<MetricsSnapshot>>,
+    pub lifetime_metrics: Arc<LifetimeMetrics>,
+    pub topic_metrics: Arc<TopicMetrics>,
+    pub queue_depth: Arc<QueueDepth>,
+    pub load: Arc<LoadGauges>,
+    pub webhook: Option<Arc<HttpSink>>,
+    /// Whether the debugging endpoints enabled with `DEBUG_ENDPOINTS` are available
+    pub debug_endpoints: bool,
+    /// Name of this instance, reported by `/version` and `/metrics`
+    pub instance_id: String,
+}
+
+/// Map a service error to an HTTP status with a JSON error body
+fn error_response(error: SpineError) -> (StatusCode, Json<ErrorResponse>) {
+    let status = match error {
+        SpineError::InvalidTopic(_) => StatusCode::BAD_REQUEST,
+        SpineError::NotAuthorized(_) => StatusCode::FORBIDDEN,
+        SpineError::Disconnected | SpineError::Connecting => StatusCode::SERVICE_UNAVAILABLE,
+        SpineError::AckTimeout => StatusCode::GATEWAY_TIMEOUT,
+    };
+
+    (
+        status,
+        Json(ErrorResponse {
+            code: error.code().to_string(),
+            message: error.to_string(),
+        }),
+    )
 }
 
 /// Health check endpoint
@@ -28,16 +68,158 @@ pub struct AppState {
     get,
     path = "/health",
     responses(
-        (status = 200, description = "Service is healthy", body = HealthResponse)
+        (status = 200, description = "Service is healthy", body = HealthResponse),
+        (status = 503, description = "MQTT connection failed with auto-reconnect disabled", body = HealthResponse)
     ),
     tag = "MQTT Subscriber"
 )]
-pub async fn health_check(State(state): State<Arc<AppState>>) -> Json<HealthResponse> {
+pub async fn health_check(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<HealthResponse>) {
+    let mqtt_stopped = state.subscriber.is_stopped();
     let health_response = HealthResponse {
+        mqtt_stopped,
         mqtt_connected: state.subscriber.is_connected(),
         kafka_connected: state.kafka_producer.is_connected(),
+        webhook_success_rate: state
+            .webhook
+            .as_ref()
+            .and_then(|webhook| webhook.success_rate()),
+    };
+
+    // The service will not recover on its own once the MQTT client gave up
+    let status = if mqtt_stopped {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(health_response))
+}
+
+/// Version endpoint
+#[utoipa::path(
+    get,
+    path = "/version",
+    responses(
+        (status = 200, description = "Version and identity of this instance", body = VersionResponse)
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn get_version(State(state): State<Arc<AppState>>) -> Json<VersionResponse> {
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        instance_id: state.instance_id.clone(),
+    })
+}
+
+/// Readiness check endpoint
+///
+/// Not ready while the MQTT client is disconnected or the processing queue stayed above
+/// `QUEUE_HIGH_WATERMARK` for `QUEUE_HIGH_WATERMARK_SECS`, so the orchestrator stops routing
+/// new load to an instance that is falling behind.
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "Service is ready to take on load", body = ReadyResponse),
+        (status = 503, description = "Service is disconnected or falling behind", body = ReadyResponse)
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn readiness_check(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<ReadyResponse>) {
+    let mqtt_connected = state.subscriber.is_connected();
+    let queue_backlogged = state.queue_depth.is_backlogged();
+    let ready = mqtt_connected && !queue_backlogged;
+
+    let ready_response = ReadyResponse {
+        ready,
+        mqtt_connected,
+        queue_depth: state.queue_depth.depth(),
+        queue_backlogged,
+    };
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(ready_response))
+}
+
+/// Scaling hint endpoint
+///
+/// Combines throughput, queue depth and in-flight count into a single load figure for
+/// autoscalers: the highest ratio of an input to its configured target.
+#[utoipa::path(
+    get,
+    path = "/scaling-hint",
+    responses(
+        (status = 200, description = "Normalized load of the service", body = ScalingHintResponse)
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn get_scaling_hint(State(state): State<Arc<AppState>>) -> Json<ScalingHintResponse> {
+    let hint = state.load.scaling_hint(state.queue_depth.depth());
+    let (target_throughput, target_queue_depth, target_in_flight) = state.load.targets();
+
+    Json(ScalingHintResponse {
+        load: hint.load,
+        throughput: hint.throughput,
+        queue_depth: hint.queue_depth,
+        in_flight: hint.in_flight,
+        target_throughput,
+        target_queue_depth,
+        target_in_flight,
+    })
+}
+
+/// Freshness check endpoint
+///
+/// Healthy only if messages are flowing, regardless of the connection status. The last
+/// message time is updated with every received message, not only on window rotations.
+#[utoipa::path(
+    get,
+    path = "/health/freshness",
+    params(FreshnessQuery),
+    responses(
+        (status = 200, description = "A message was received within max_age_secs", body = FreshnessResponse),
+        (status = 503, description = "No message was received within max_age_secs", body = FreshnessResponse)
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn freshness_check(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FreshnessQuery>,
+) -> (StatusCode, Json<FreshnessResponse>) {
+    let last_message_time = state.lifetime_metrics.last_message_time();
+
+    // A message time in the future (clock adjustment) counts as just received
+    let age_secs = last_message_time.map(|time| {
+        SystemTime::now()
+            .duration_since(time)
+            .unwrap_or_default()
+            .as_secs()
+    });
+    let fresh = age_secs.is_some_and(|age| age <= query.max_age_secs);
+
+    let freshness_response = FreshnessResponse {
+        fresh,
+        last_message_time: last_message_time.map(|time| {
+            let datetime = chrono::DateTime::<chrono::Utc>::from(time);
+            datetime.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+        }),
+        age_secs,
+        max_age_secs: query.max_age_secs,
     };
-    Json(health_response)
+
+    let status = if fresh {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(freshness_response))
 }
 
 /// Get a list of all subscribed topics
@@ -60,28 +242,38 @@ pub async fn get_topics(State(state): State<Arc<AppState>>) -> Json<TopicsRespon
     path = "/subscribe",
     request_body = SubscribeRequest,
     responses(
-        (status = 200, description = "Successfully subscribed to topic", body = ApiResponse),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Subscription acknowledged by the broker", body = SubscribeResponse),
+        (status = 400, description = "Invalid topic filter", body = ErrorResponse),
+        (status = 403, description = "Broker refused the subscription", body = ErrorResponse),
+        (status = 503, description = "MQTT client is disconnected or still connecting", body = ErrorResponse),
+        (status = 504, description = "Broker did not acknowledge the subscription in time", body = ErrorResponse)
     ),
     tag = "MQTT Subscriber"
 )]
 pub async fn subscribe_to_topic(
     State(state): State<Arc<AppState>>,
     Json(req): Json<SubscribeRequest>,
-) -> Result<Json<ApiResponse>, StatusCode> {
+) -> Result<Json<SubscribeResponse>, (StatusCode, Json<ErrorResponse>)> {
     let topic = req.topic;
+    validate_filter(&topic).map_err(error_response)?;
+    state
+        .subscriber
+        .wait_for_first_connection()
+        .await
+        .map_err(error_response)?;
 
-    match state.subscriber.subscribe(&topic).await {
-        Ok(_) => {
+    match state.subscriber.subscribe_acked(&topic).await {
+        Ok(granted) => {
             info!("API: Subscribed to topic: {}", topic);
-            Ok(Json(ApiResponse {
-                success: true,
-                message: format!("Subscribed to topic: {}", topic),
+            Ok(Json(SubscribeResponse {
+                topic,
+                granted_qos: granted.map(|granted| granted.qos as u8),
+                subscription_id: granted.map(|granted| granted.packet_id),
             }))
         }
         Err(e) => {
             error!("API: Failed to subscribe to topic {}: {}", topic, e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            Err(error_response(e))
         }
     }
 }
@@ -95,17 +287,24 @@ pub async fn subscribe_to_topic(
     ),
     responses(
         (status = 200, description = "Successfully unsubscribed from topic", body = ApiResponse),
-        (status = 500, description = "Internal server error")
+        (status = 503, description = "MQTT client is disconnected or still connecting", body = ErrorResponse)
     ),
     tag = "MQTT Subscriber"
 )]
 pub async fn unsubscribe_from_topic(
     State(state): State<Arc<AppState>>,
     Path(topic): Path<String>,
-) -> Result<Json<ApiResponse>, StatusCode> {
+) -> Result<Json<ApiResponse>, (StatusCode, Json<ErrorResponse>)> {
+    state
+        .subscriber
+        .wait_for_first_connection()
+        .await
+        .map_err(error_response)?;
     match state.subscriber.unsubscribe(&topic).await {
         Ok(_) => {
             info!("API: Unsubscribed from topic: {}", topic);
+            let subscriptions = state.subscriber.get_topics().await;
+            state.topic_metrics.retain_subscribed(&subscriptions);
             Ok(Json(ApiResponse {
                 success: true,
                 message: format!("Unsubscribed from topic: {}", topic),
@@ -113,47 +312,519 @@ pub async fn unsubscribe_from_topic(
         }
         Err(e) => {
             error!("API: Failed to unsubscribe from topic {}: {}", topic, e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            Err(error_response(e))
         }
     }
 }
 
+/// Unsubscribe from all tracked topics matching an MQTT filter
+#[utoipa::path(
+    delete,
+    path = "/unsubscribe",
+    params(UnsubscribeQuery),
+    responses(
+        (status = 200, description = "Successfully unsubscribed from the matching topics", body = ApiResponse),
+        (status = 400, description = "Invalid topic filter", body = ErrorResponse),
+        (status = 503, description = "MQTT client is disconnected or still connecting", body = ErrorResponse)
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn unsubscribe_matching_topics(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> Result<Json<ApiResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let pattern = query.pattern;
+    validate_filter(&pattern).map_err(error_response)?;
+    state
+        .subscriber
+        .wait_for_first_connection()
+        .await
+        .map_err(error_response)?;
+
+    match state.subscriber.unsubscribe_matching(&pattern).await {
+        Ok(removed) => {
+            info!(
+                "API: Unsubscribed from {} topics matching {}",
+                removed.len(),
+                pattern
+            );
+            let subscriptions = state.subscriber.get_topics().await;
+            state.topic_metrics.retain_subscribed(&subscriptions);
+            Ok(Json(ApiResponse {
+                success: true,
+                message: format!(
+                    "Unsubscribed from {} topics matching {}",
+                    removed.len(),
+                    pattern
+                ),
+            }))
+        }
+        Err(e) => {
+            error!(
+                "API: Failed to unsubscribe from topics matching {}: {}",
+                pattern, e
+            );
+            Err(error_response(e))
+        }
+    }
+}
+
+/// Parse a metrics time range like `5m` or `1h` into a number of windows (at least one)
+fn parse_metrics_window(window: &str) -> Option<usize> {
+    let secs = if let Some(minutes) = window.strip_suffix('m') {
+        minutes.parse::<u64>().ok()?.checked_mul(60)?
+    } else if let Some(hours) = window.strip_suffix('h') {
+        hours.parse::<u64>().ok()?.checked_mul(3600)?
+    } else {
+        return None;
+    };
+    let windows = (secs / WINDOW_DURATION.as_secs()).max(1);
+    Some(usize::try_from(windows).unwrap_or(usize::MAX))
+}
+
 /// Get service metrics
 ///
 /// Note that throughput and other calculations are based only on completed windows,
-/// so data is at most one minute old.
+/// so data is at most one minute old. `window` limits the metrics to the most recent
+/// windows, clamped to the windows kept.
 #[utoipa::path(
     get,
     path = "/metrics",
+    params(MetricsQuery),
     responses(
         (status = 200, description = "Service metrics from the last completed minute", body = MetricsResponse)
     ),
     tag = "MQTT Subscriber"
 )]
-pub async fn get_metrics(State(state): State<Arc<AppState>>) -> Json<MetricsResponse> {
-    let metrics_read = state.metrics.read().await;
+pub async fn get_metrics(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<MetricsQuery>,
+) -> Json<MetricsResponse> {
+    // Read the latest published snapshot without locking the metrics
+    let snapshot = state.metrics.load();
+    let snapshot = match query.window.as_deref().and_then(parse_metrics_window) {
+        Some(windows) => snapshot.for_windows(windows),
+        None => Cow::Borrowed(&**snapshot),
+    };
     let topics = state.subscriber.get_topics().await;
 
     // Format the last message time as ISO 8601 string if available
-    let last_message_time = metrics_read.window_last_message_time().map(|time| {
+    let last_message_time = snapshot.last_message_time.map(|time| {
         // Convert SystemTime to a proper ISO 8601 date time format
         let datetime = chrono::DateTime::<chrono::Utc>::from(time);
         datetime.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
     });
 
+    let uptime_window = Duration::from_secs(snapshot.window_time_sec);
+
     Json(MetricsResponse {
-        window_time_sec: metrics_read.window_time_sec,
-        messages_received: metrics_read.window_messages_received(),
-        messages_processed: metrics_read.window_messages_processed(),
-        messages_dropped: metrics_read.window_messages_dropped(),
-        processing_errors: metrics_read.window_processing_errors(),
+        window_time_sec: snapshot.window_time_sec,
+        warming_up: snapshot.warming_up,
+        sample_rate: snapshot.sample_rate,
+        messages_received: snapshot.messages_received,
+        messages_processed: snapshot.messages_processed,
+        messages_dropped: snapshot.messages_dropped,
+        messages_delivery_failed: snapshot.messages_delivery_failed,
+        processing_errors: snapshot.processing_errors,
+        queue_dropped: snapshot.queue_dropped,
+        queued_by_lane: snapshot.queued_by_lane.clone(),
+        queue_dropped_by_lane: snapshot.queue_dropped_by_lane.clone(),
+        received_by_qos: snapshot.received_by_qos.clone(),
+        qos2_inflight: state.subscriber.qos2_inflight(),
+        qos2_completed: snapshot.qos2_completed,
+        detected_by_format: snapshot.detected_by_format.clone(),
+        delivered_by_guarantee: snapshot.delivered_by_guarantee.clone(),
+        failed_by_guarantee: snapshot.failed_by_guarantee.clone(),
+        delivery_retries: snapshot.delivery_retries,
+        messages_empty: snapshot.messages_empty,
+        messages_too_deep: snapshot.messages_too_deep,
+        messages_late: snapshot.messages_late,
+        messages_bad_topic: snapshot.messages_bad_topic,
+        dead_lettered_by_reason: snapshot.dead_lettered_by_reason.clone(),
+        by_class: snapshot
+            .by_class
+            .iter()
+            .map(|(class, counts)| {
+                let rate = |count: usize| match counts.messages_received {
+                    0 => 0.0,
+                    received => count as f64 / received as f64,
+                };
+                let class_metrics = ClassMetricsResponse {
+                    messages_received: counts.messages_received,
+                    messages_dropped: counts.messages_dropped,
+                    processing_errors: counts.processing_errors,
+                    drop_rate: rate(counts.messages_dropped),
+                    error_rate: rate(counts.processing_errors),
+                };
+                (class.clone(), class_metrics)
+            })
+            .collect(),
         active_topics: topics.len(),
-        throughput: metrics_read.window_throughput(),
-        average_message_size: metrics_read.window_average_message_size(),
-        max_message_size: metrics_read.window_max_message_size(),
-        average_processing_time_ms: metrics_read.window_average_processing_time().as_secs_f64()
-            * 1000.0,
-        max_processing_time_ms: metrics_read.window_max_processing_time().as_secs_f64() * 1000.0,
+        throughput: snapshot.throughput,
+        average_message_size: snapshot.average_message_size,
+        max_message_size: snapshot.max_message_size,
+        average_processing_time_ms: snapshot.average_processing_time.as_secs_f64() * 1000.0,
+        max_processing_time_ms: snapshot.max_processing_time.as_secs_f64() * 1000.0,
+        end_to_end_latency_ms: snapshot
+            .average_end_to_end_latency
+            .map(|latency| latency.as_secs_f64() * 1000.0),
+        end_to_end_latency_p95_ms: snapshot
+            .p95_end_to_end_latency
+            .map(|latency| latency.as_secs_f64() * 1000.0),
         last_message_time,
+        mqtt_uptime_ratio: state.subscriber.uptime_ratio(uptime_window),
+        kafka_uptime_ratio: state.kafka_producer.uptime_ratio(uptime_window),
+        kafka_throttled: state.kafka_producer.is_throttled(),
+        instance_id: state.instance_id.clone(),
+        mqtt_ping_latency_ms: snapshot
+            .last_ping_latency
+            .map(|latency| latency.as_secs_f64() * 1000.0),
+        mqtt_ping_timeouts: snapshot.ping_timeouts,
+        mqtt_fresh_clients: snapshot.fresh_clients,
+        mqtt_client_id_conflicts: snapshot.client_id_conflicts,
+    })
+}
+
+/// Get cumulative metrics since process start or the last reset
+#[utoipa::path(
+    get,
+    path = "/metrics/lifetime",
+    responses(
+        (status = 200, description = "Cumulative service metrics", body = LifetimeMetricsResponse)
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn get_lifetime_metrics(
+    State(state): State<Arc<AppState>>,
+) -> Json<LifetimeMetricsResponse> {
+    let totals = state.lifetime_metrics.totals();
+    let since = chrono::DateTime::<chrono::Utc>::from(totals.since);
+
+    Json(LifetimeMetricsResponse {
+        since: since.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        messages_received: totals.messages_received,
+        messages_processed: totals.messages_processed,
+        messages_dropped: totals.messages_dropped,
+        messages_delivery_failed: totals.messages_delivery_failed,
+        processing_errors: totals.processing_errors,
+        bytes_received: totals.bytes_received,
+    })
+}
+
+/// Get cumulative metrics per topic since process start
+#[utoipa::path(
+    get,
+    path = "/metrics/topics",
+    responses(
+        (status = 200, description = "Cumulative metrics per topic", body = TopicMetricsResponse)
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn get_topic_metrics(State(state): State<Arc<AppState>>) -> Json<TopicMetricsResponse> {
+    let totals = state.topic_metrics.totals();
+
+    let topics = totals
+        .topics
+        .into_iter()
+        .map(|(topic, counts)| {
+            let last_message_time = counts.last_message_time.map(|time| {
+                let datetime = chrono::DateTime::<chrono::Utc>::from(time);
+                datetime.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+            });
+            let topic_counts = TopicCountsResponse {
+                messages_received: counts.messages_received,
+                messages_dropped: counts.messages_dropped,
+                processing_errors: counts.processing_errors,
+                last_message_time,
+            };
+            (topic, topic_counts)
+        })
+        .collect();
+
+    Json(TopicMetricsResponse {
+        topics,
+        other_topics: totals.other_topics,
+        max_topics: totals.max_topics,
     })
 }
+
+/// Reset the cumulative metrics
+///
+/// The windowed metrics are not affected.
+#[utoipa::path(
+    post,
+    path = "/metrics/reset",
+    responses(
+        (status = 200, description = "Cumulative metrics were reset", body = ApiResponse)
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn reset_lifetime_metrics(State(state): State<Arc<AppState>>) -> Json<ApiResponse> {
+    state.lifetime_metrics.reset();
+    info!("API: Reset lifetime metrics");
+
+    Json(ApiResponse {
+        success: true,
+        message: "Lifetime metrics reset".to_string(),
+    })
+}
+
+/// Get the statistics of the jemalloc allocator
+///
+/// Only available when built with the `jemalloc` feature.
+#[utoipa::path(
+    get,
+    path = "/debug/alloc",
+    responses(
+        (status = 200, description = "Allocator statistics in bytes", body = AllocResponse),
+        (status = 404, description = "Built without the jemalloc feature"),
+        (status = 500, description = "The statistics could not be read")
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn get_allocator_stats() -> Result<Json<AllocResponse>, StatusCode> {
+    #[cfg(feature = "jemalloc")]
+    {
+        let stats = crate::metrics::allocator_stats().map_err(|e| {
+            error!("API: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        Ok(Json(AllocResponse {
+            allocated: stats.allocated,
+            active: stats.active,
+            resident: stats.resident,
+            mapped: stats.mapped,
+            retained: stats.retained,
+        }))
+    }
+    #[cfg(not(feature = "jemalloc"))]
+    Err(StatusCode::NOT_FOUND)
+}
+
+/// Get the individual completed metrics windows the aggregates are calculated from
+///
+/// Only available with `DEBUG_ENDPOINTS` enabled.
+#[utoipa::path(
+    get,
+    path = "/debug/windows",
+    responses(
+        (status = 200, description = "Completed metrics windows, oldest first", body = WindowsResponse),
+        (status = 404, description = "Debug endpoints are disabled")
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn get_metrics_windows(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<WindowsResponse>, StatusCode> {
+    if !state.debug_endpoints {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let windows = state
+        .metrics
+        .load()
+        .windows
+        .iter()
+        .map(Arc::as_ref)
+        .map(window_response)
+        .collect();
+    Ok(Json(WindowsResponse { windows }))
+}
+
+/// Get the series of completed metrics windows kept for backfilling
+///
+/// Holds up to `METRICS_HISTORY_WINDOWS` windows, so a scraper that missed polls can fetch
+/// the windows it has not seen yet.
+#[utoipa::path(
+    get,
+    path = "/metrics/history",
+    responses(
+        (status = 200, description = "Completed metrics windows kept in the history, oldest first", body = WindowsResponse)
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn get_metrics_history(State(state): State<Arc<AppState>>) -> Json<WindowsResponse> {
+    let windows = state
+        .metrics
+        .load()
+        .history
+        .iter()
+        .map(Arc::as_ref)
+        .map(window_response)
+        .collect();
+    Json(WindowsResponse { windows })
+}
+
+/// Report the raw counts of a completed metrics window
+fn window_response(window: &WindowedMetrics) -> WindowResponse {
+    let format_time = |time| {
+        chrono::DateTime::<chrono::Utc>::from(time)
+            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            .to_string()
+    };
+    WindowResponse {
+        start_time: format_time(window.start_time),
+        end_time: format_time(window.end_time),
+        messages_received: window.messages_received,
+        messages_processed: window.messages_processed,
+        messages_dropped: window.messages_dropped,
+        messages_delivery_failed: window.messages_delivery_failed,
+        processing_errors: window.processing_errors,
+        queue_dropped: window.queue_dropped,
+        messages_empty: window.messages_empty,
+        messages_too_deep: window.messages_too_deep,
+        messages_late: window.messages_late,
+        dead_lettered_by_reason: window
+            .dead_lettered_by_reason
+            .iter()
+            .map(|(reason, count)| (reason.as_str().to_string(), *count))
+            .collect(),
+        total_message_size: window.total_message_size,
+        max_message_size: window.max_message_size,
+        total_processing_time_ms: window.total_processing_time.as_secs_f64() * 1000.0,
+        max_processing_time_ms: window.max_processing_time.as_secs_f64() * 1000.0,
+        end_to_end_latency_count: window.end_to_end_latency_count,
+        ping_timeouts: window.ping_timeouts,
+        fresh_clients: window.fresh_clients,
+        client_id_conflicts: window.client_id_conflicts,
+    }
+}
+
+/// Get the last received raw MQTT publish packets
+///
+/// Only available with `CAPTURE_RAW_PACKETS` enabled.
+#[utoipa::path(
+    get,
+    path = "/debug/packets",
+    responses(
+        (status = 200, description = "Captured publish packets, oldest first", body = PacketsResponse),
+        (status = 404, description = "Packet capture is disabled")
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn get_captured_packets(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<PacketsResponse>, StatusCode> {
+    let packet_capture = state
+        .subscriber
+        .packet_capture()
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let packets = packet_capture
+        .packets()
+        .into_iter()
+        .map(|packet| {
+            let captured_at = chrono::DateTime::<chrono::Utc>::from(packet.captured_at);
+            CapturedPacketResponse {
+                captured_at: captured_at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+                topic: packet.topic,
+                pkid: packet.pkid,
+                qos: packet.qos,
+                dup: packet.dup,
+                retain: packet.retain,
+                payload_size: packet.payload.len(),
+                payload_hex: packet
+                    .payload
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect(),
+            }
+        })
+        .collect();
+
+    Ok(Json(PacketsResponse { packets }))
+}
+
+/// Get the topics observed by the automatic topic discovery
+///
+/// Only available with `DISCOVERY_MODE` enabled.
+#[utoipa::path(
+    get,
+    path = "/topics/discovered",
+    responses(
+        (status = 200, description = "Discovered topics with their message counts", body = DiscoveredTopicsResponse),
+        (status = 404, description = "Topic discovery is disabled")
+    ),
+    tag = "MQTT Subscriber"
+)]
+pub async fn get_discovered_topics(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<DiscoveredTopicsResponse>, StatusCode> {
+    let topic_discovery = state
+        .subscriber
+        .topic_discovery()
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(DiscoveredTopicsResponse {
+        pattern: topic_discovery.pattern().to_string(),
+        in_progress: topic_discovery.is_observing(),
+        topics: topic_discovery.topics(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{load_kafka_configs, load_metrics_configs, load_mqtt_configs};
+    use crate::metrics::MessageMetrics;
+
+    /// Create the API state serving the snapshots of `metrics`, without broker connections
+    fn state(metrics: &MessageMetrics) -> Arc<AppState> {
+        let (subscriber, _event_loop) = MqttSubscriber::new(&load_mqtt_configs());
+        Arc::new(AppState {
+            subscriber: Arc::new(subscriber),
+            kafka_producer: Arc::new(KafkaProducer::unconnected(
+                &load_kafka_configs(),
+                Vec::new(),
+            )),
+            metrics: metrics.snapshot(),
+            lifetime_metrics: metrics.lifetime(),
+            topic_metrics: metrics.topics(),
+            queue_depth: metrics.queue_depth(),
+            load: metrics.load(),
+            webhook: None,
+            debug_endpoints: false,
+            instance_id: "test-instance".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn metrics_history_returns_kept_windows_oldest_first() {
+        let mut config = load_metrics_configs();
+        config.windows = 2;
+        config.history_windows = 3;
+        config.sample_rate = 1;
+        let mut metrics = MessageMetrics::new(&config);
+        let start = SystemTime::now();
+        // Window `n` receives `n + 1` messages, five windows complete
+        for minute in 0..6u64 {
+            let window_start = start + Duration::from_secs(minute * 60);
+            for _ in 0..=minute {
+                metrics.record_message_received(10, window_start);
+            }
+        }
+        let state = state(&metrics);
+
+        let Json(history) = get_metrics_history(State(Arc::clone(&state))).await;
+
+        let received: Vec<usize> = history
+            .windows
+            .iter()
+            .map(|window| window.messages_received)
+            .collect();
+        assert_eq!(received, vec![3, 4, 5]);
+        assert_eq!(history.windows[0].total_message_size, 30);
+        // The aggregated windows are only the most recent ones
+        assert_eq!(state.metrics.load().windows.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn metrics_history_is_empty_before_the_first_window_completes() {
+        let mut metrics = MessageMetrics::new(&load_metrics_configs());
+        metrics.record_message_received(10, SystemTime::now());
+
+        let Json(history) = get_metrics_history(State(state(&metrics))).await;
+
+        assert!(history.windows.is_empty());
+    }
+}
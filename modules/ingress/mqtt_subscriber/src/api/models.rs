@@ -1,15 +1,201 @@
 //! API data models
 
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use std::collections::BTreeMap;
+use utoipa::{IntoParams, ToSchema};
 
 /// Health response
 #[derive(Serialize, ToSchema)]
 pub struct HealthResponse {
     /// Whether the MQTT client is connected
     pub mqtt_connected: bool,
+    /// Whether the MQTT client stopped reconnecting after a failure (auto-reconnect disabled)
+    pub mqtt_stopped: bool,
     /// Whether the Kafka producer is connected
     pub kafka_connected: bool,
+    /// Fraction of the recent webhook deliveries that succeeded (0.0 - 1.0), if the webhook sink is used
+    pub webhook_success_rate: Option<f64>,
+}
+
+/// Version response
+#[derive(Serialize, ToSchema)]
+pub struct VersionResponse {
+    /// Version of the service
+    pub version: String,
+    /// Name of this instance (`INSTANCE_ID`, the host name by default)
+    pub instance_id: String,
+}
+
+/// Response for readiness check endpoint
+#[derive(Serialize, ToSchema)]
+pub struct ReadyResponse {
+    /// Whether the service can take on more load
+    pub ready: bool,
+    /// Whether the MQTT client is connected
+    pub mqtt_connected: bool,
+    /// Number of messages in the processing queue at the last sample (0 without workers)
+    pub queue_depth: usize,
+    /// Whether the queue depth stayed above the high watermark for the configured duration
+    pub queue_backlogged: bool,
+}
+
+/// Response for the scaling hint endpoint
+#[derive(Serialize, ToSchema)]
+pub struct ScalingHintResponse {
+    /// Highest ratio of an input to its target, above 1.0 when saturated
+    pub load: f64,
+    /// Messages received per second over the last second
+    pub throughput: f64,
+    /// Number of messages in the processing queue at the last sample (0 without workers)
+    pub queue_depth: usize,
+    /// Number of messages being processed
+    pub in_flight: usize,
+    /// Throughput at which the load reaches 1.0 (0 if ignored)
+    pub target_throughput: f64,
+    /// Queue depth at which the load reaches 1.0 (0 if ignored)
+    pub target_queue_depth: usize,
+    /// In-flight count at which the load reaches 1.0 (0 if ignored)
+    pub target_in_flight: usize,
+}
+
+/// Query parameters for the freshness check
+#[derive(Deserialize, IntoParams)]
+pub struct FreshnessQuery {
+    /// Maximum age of the last received message in seconds
+    pub max_age_secs: u64,
+}
+
+/// Query parameters for unsubscribing by pattern
+#[derive(Deserialize, IntoParams)]
+pub struct UnsubscribeQuery {
+    /// MQTT filter matching the tracked topics to unsubscribe from, e.g. `lab/#`
+    pub pattern: String,
+}
+
+/// Query parameters for the metrics endpoint
+#[derive(Deserialize, IntoParams)]
+pub struct MetricsQuery {
+    /// Time range to aggregate, e.g. `1m` or `5m`; all kept windows if omitted or invalid
+    pub window: Option<String>,
+}
+
+/// Freshness check response
+#[derive(Serialize, ToSchema)]
+pub struct FreshnessResponse {
+    /// Whether the last message was received within `max_age_secs`
+    pub fresh: bool,
+    /// Time of the last received message (ISO 8601), if any
+    pub last_message_time: Option<String>,
+    /// Seconds since the last received message, if any
+    pub age_secs: Option<u64>,
+    /// The requested maximum age in seconds
+    pub max_age_secs: u64,
+}
+
+/// A captured MQTT publish packet
+#[derive(Serialize, ToSchema)]
+pub struct CapturedPacketResponse {
+    /// Time the packet was received in ISO 8601 format
+    pub captured_at: String,
+    /// Topic the packet was published on
+    pub topic: String,
+    /// Packet identifier (0 for QoS 0)
+    pub pkid: u16,
+    /// Quality of service (0, 1 or 2)
+    pub qos: u8,
+    /// Whether the packet is a redelivery
+    pub dup: bool,
+    /// Whether the packet is a retained message
+    pub retain: bool,
+    /// Payload size in bytes
+    pub payload_size: usize,
+    /// Payload as hex string
+    pub payload_hex: String,
+}
+
+/// Response for the packet capture endpoint
+#[derive(Serialize, ToSchema)]
+pub struct PacketsResponse {
+    /// Captured packets, oldest first
+    pub packets: Vec<CapturedPacketResponse>,
+}
+
+/// Raw counts of a single completed metrics window
+#[derive(Serialize, ToSchema)]
+pub struct WindowResponse {
+    /// Start time of the window in ISO 8601 format
+    pub start_time: String,
+    /// End time of the window in ISO 8601 format
+    pub end_time: String,
+    /// Number of messages received in the window
+    pub messages_received: usize,
+    /// Number of messages processed in the window
+    pub messages_processed: usize,
+    /// Number of messages dropped in the window
+    pub messages_dropped: usize,
+    /// Number of processed messages the sinks failed to deliver in the window
+    pub messages_delivery_failed: usize,
+    /// Number of processing errors in the window
+    pub processing_errors: usize,
+    /// Number of messages dropped because the processing queue was full in the window
+    pub queue_dropped: usize,
+    /// Number of empty-payload messages dropped in the window
+    pub messages_empty: usize,
+    /// Number of messages rejected for exceeding `MAX_JSON_DEPTH` in the window
+    pub messages_too_deep: usize,
+    /// Number of messages that arrived after their reorder window in the window
+    pub messages_late: usize,
+    /// Number of dead-lettered messages in the window, by reason
+    pub dead_lettered_by_reason: BTreeMap<String, usize>,
+    /// Total size of the messages received in the window in bytes
+    pub total_message_size: usize,
+    /// Maximum message size seen in the window
+    pub max_message_size: usize,
+    /// Total processing time of the messages in the window in milliseconds
+    pub total_processing_time_ms: f64,
+    /// Maximum processing time seen in the window in milliseconds
+    pub max_processing_time_ms: f64,
+    /// Number of messages with a known end-to-end latency in the window
+    pub end_to_end_latency_count: usize,
+    /// Number of MQTT pings without a response in the window
+    pub ping_timeouts: usize,
+    /// Number of reconnects with a new MQTT client ID in the window
+    pub fresh_clients: usize,
+    /// Number of detected MQTT client ID conflicts in the window
+    pub client_id_conflicts: usize,
+}
+
+/// Response for the metrics windows endpoint
+#[derive(Serialize, ToSchema)]
+pub struct WindowsResponse {
+    /// Completed windows the metrics are aggregated from, oldest first
+    pub windows: Vec<WindowResponse>,
+}
+
+/// Response for the allocator statistics endpoint
+#[derive(Serialize, ToSchema)]
+pub struct AllocResponse {
+    /// Bytes allocated by the application
+    pub allocated: usize,
+    /// Bytes in active pages, a multiple of the page size
+    pub active: usize,
+    /// Bytes in physically resident data pages mapped by the allocator
+    pub resident: usize,
+    /// Bytes in virtual memory mappings kept by the allocator
+    pub mapped: usize,
+    /// Bytes retained for reuse instead of being returned to the operating system
+    pub retained: usize,
+}
+
+/// Response for the topic discovery endpoint
+#[derive(Serialize, ToSchema)]
+pub struct DiscoveredTopicsResponse {
+    /// Topic filter subscribed during discovery
+    pub pattern: String,
+    /// Whether the discovery window is still open
+    pub in_progress: bool,
+    /// Topics that received messages during discovery, with their message counts
+    pub topics: BTreeMap<String, u64>,
 }
 
 /// Request for subscribing to a topic
@@ -19,6 +205,18 @@ pub struct SubscribeRequest {
     pub topic: String,
 }
 
+/// Response to a subscribe request, confirmed by the broker's SubAck
+#[derive(Serialize, ToSchema)]
+pub struct SubscribeResponse {
+    /// Subscribed MQTT topic filter
+    pub topic: String,
+    /// QoS granted by the broker (0-2), null if no SUBSCRIBE packet was sent because the
+    /// topic is already subscribed or covered by a wildcard subscription
+    pub granted_qos: Option<u8>,
+    /// Packet ID of the acknowledged SUBSCRIBE packet, null if none was sent
+    pub subscription_id: Option<u16>,
+}
+
 /// Standard API response
 #[derive(Serialize, ToSchema)]
 pub struct ApiResponse {
@@ -28,6 +226,35 @@ pub struct ApiResponse {
     pub message: String,
 }
 
+/// Cumulative metrics response
+#[derive(Serialize, ToSchema)]
+pub struct LifetimeMetricsResponse {
+    /// When counting started (process start or the last reset), ISO 8601
+    pub since: String,
+    /// Total number of messages received
+    pub messages_received: u64,
+    /// Total number of messages processed
+    pub messages_processed: u64,
+    /// Total number of messages dropped before a delivery attempt
+    pub messages_dropped: u64,
+    /// Total number of processed messages the sinks failed to deliver
+    pub messages_delivery_failed: u64,
+    /// Total number of processing errors
+    pub processing_errors: u64,
+    /// Total size of all received messages in bytes
+    pub bytes_received: u64,
+}
+
+/// Error response with a machine-readable code
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Error code (`invalid_topic`, `not_authorized`, `disconnected`, `connecting` or
+    /// `ack_timeout`)
+    pub code: String,
+    /// Human-readable error message
+    pub message: String,
+}
+
 /// Response for topics endpoint
 #[derive(Serialize, ToSchema)]
 pub struct TopicsResponse {
@@ -35,19 +262,98 @@ pub struct TopicsResponse {
     pub topics: Vec<String>,
 }
 
+/// Metrics of a single topic class
+#[derive(Serialize, ToSchema)]
+pub struct ClassMetricsResponse {
+    /// Number of messages received on topics of this class
+    pub messages_received: usize,
+    /// Number of messages of this class that were dropped
+    pub messages_dropped: usize,
+    /// Number of processing errors for messages of this class
+    pub processing_errors: usize,
+    /// Fraction of the received messages that were dropped (0.0 - 1.0)
+    pub drop_rate: f64,
+    /// Fraction of the received messages that failed processing (0.0 - 1.0)
+    pub error_rate: f64,
+}
+
+/// Cumulative metrics of a single topic
+#[derive(Serialize, ToSchema)]
+pub struct TopicCountsResponse {
+    /// Number of messages received on the topic
+    pub messages_received: u64,
+    /// Number of messages on the topic that were dropped
+    pub messages_dropped: u64,
+    /// Number of processing errors for messages on the topic
+    pub processing_errors: u64,
+    /// Last message time in ISO 8601 format
+    pub last_message_time: Option<String>,
+}
+
+/// Response for the per-topic metrics endpoint
+#[derive(Serialize, ToSchema)]
+pub struct TopicMetricsResponse {
+    /// Metrics by concrete topic, with evicted topics aggregated under `__other__`
+    pub topics: BTreeMap<String, TopicCountsResponse>,
+    /// Number of topics evicted into `__other__` (a topic evicted twice counts twice)
+    pub other_topics: u64,
+    /// Maximum number of individually tracked topics (`METRICS_MAX_TOPICS`)
+    pub max_topics: usize,
+}
+
 /// Response for metrics endpoint
 #[derive(Serialize, ToSchema)]
 pub struct MetricsResponse {
-    /// Time window in seconds (currently 60 seconds/1 minute)
+    /// Time window in seconds (60 seconds per aggregated window)
     pub window_time_sec: u64,
+    /// Whether no window has completed yet, so the metrics below cover only the current
+    /// window, or carry no data with `METRICS_WARMUP_CURRENT_WINDOW=false`
+    pub warming_up: bool,
+    /// One in this many messages is recorded, above 1 the counts below are estimates
+    pub sample_rate: usize,
     /// Total number of messages received in completed windows
     pub messages_received: usize,
     /// Total number of messages processed in completed windows
     pub messages_processed: usize,
-    /// Number of messages dropped due to errors in completed windows
+    /// Number of messages dropped before a delivery attempt in completed windows
     pub messages_dropped: usize,
+    /// Number of processed messages the sinks failed to deliver in completed windows
+    pub messages_delivery_failed: usize,
     /// Number of processing errors in completed windows
     pub processing_errors: usize,
+    /// Number of messages dropped because the processing queue was full in completed windows
+    pub queue_dropped: usize,
+    /// Number of messages added to the processing queue in completed windows, by priority lane
+    pub queued_by_lane: BTreeMap<String, usize>,
+    /// Number of messages dropped by the processing queue in completed windows, by priority lane
+    pub queue_dropped_by_lane: BTreeMap<String, usize>,
+    /// Number of messages received in completed windows, by QoS level
+    pub received_by_qos: BTreeMap<String, usize>,
+    /// Number of QoS 2 messages currently between receipt and the completing PubComp
+    pub qos2_inflight: usize,
+    /// Number of QoS 2 handshakes completed with a PubComp in completed windows
+    pub qos2_completed: usize,
+    /// Number of payloads detected in completed windows, by format (with `DETECT_PAYLOAD_FORMAT`)
+    pub detected_by_format: BTreeMap<String, usize>,
+    /// Number of messages delivered in completed windows, by delivery guarantee
+    pub delivered_by_guarantee: BTreeMap<String, usize>,
+    /// Number of messages that failed in completed windows, by delivery guarantee
+    pub failed_by_guarantee: BTreeMap<String, usize>,
+    /// Number of delivery retries of at-least-once messages in completed windows
+    pub delivery_retries: usize,
+    /// Number of messages with an empty payload that were dropped in completed windows
+    pub messages_empty: usize,
+    /// Number of messages rejected for exceeding `MAX_JSON_DEPTH` in completed windows
+    pub messages_too_deep: usize,
+    /// Number of messages that arrived after their reorder window was flushed in completed windows
+    pub messages_late: usize,
+    /// Number of messages with a malformed topic name in completed windows, processed as
+    /// `__malformed__`
+    pub messages_bad_topic: usize,
+    /// Number of dead-lettered messages in completed windows, by reason
+    pub dead_lettered_by_reason: BTreeMap<String, usize>,
+    /// Message counts and rates in completed windows, by topic class
+    pub by_class: BTreeMap<String, ClassMetricsResponse>,
     /// Number of active topics
     pub active_topics: usize,
     /// Messages per second (throughput calculated from completed windows)
@@ -60,6 +366,28 @@ pub struct MetricsResponse {
     pub average_processing_time_ms: f64,
     /// Maximum processing time seen in milliseconds from completed windows
     pub max_processing_time_ms: f64,
+    /// Average time from the sensor's measurement to delivery in milliseconds from completed
+    /// windows, only for messages with a sensor timestamp
+    pub end_to_end_latency_ms: Option<f64>,
+    /// 95th percentile of the time from the sensor's measurement to delivery in milliseconds
+    pub end_to_end_latency_p95_ms: Option<f64>,
     /// Last message time in ISO 8601 format
     pub last_message_time: Option<String>,
+    /// Fraction of the metrics window the MQTT client was connected (0.0 - 1.0)
+    pub mqtt_uptime_ratio: f64,
+    /// Fraction of the metrics window the Kafka producer was connected (0.0 - 1.0)
+    pub kafka_uptime_ratio: f64,
+    /// Whether Kafka sends are currently being paced or dropped by `KAFKA_MAX_SEND_RATE`
+    pub kafka_throttled: bool,
+    /// Name of the instance reporting these metrics
+    pub instance_id: String,
+    /// Round trip time of the last answered MQTT ping in milliseconds
+    pub mqtt_ping_latency_ms: Option<f64>,
+    /// Number of MQTT pings without a response in completed windows
+    pub mqtt_ping_timeouts: usize,
+    /// Number of reconnects with a new MQTT client ID in completed windows
+    pub mqtt_fresh_clients: usize,
+    /// Number of detected MQTT client ID conflicts (connections repeatedly taken over) in
+    /// completed windows
+    pub mqtt_client_id_conflicts: usize,
 }
@@ -1,16 +1,25 @@
 //! API route definitions
 
 use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{delete, get, post},
     Router,
 };
 use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tower_http::cors::{Any, CorsLayer};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 use super::handlers::{
-    get_metrics, get_topics, health_check, subscribe_to_topic, unsubscribe_from_topic, AppState,
+    freshness_check, get_allocator_stats, get_captured_packets, get_discovered_topics,
+    get_lifetime_metrics, get_metrics, get_metrics_history, get_metrics_windows, get_scaling_hint,
+    get_topic_metrics, get_topics, get_version, health_check, readiness_check,
+    reset_lifetime_metrics, subscribe_to_topic, unsubscribe_from_topic,
+    unsubscribe_matching_topics, AppState,
 };
 
 /// Define API documentation
@@ -18,13 +27,26 @@ use super::handlers::{
 #[openapi(
     paths(
         super::handlers::health_check,
+        super::handlers::get_version,
+        super::handlers::readiness_check,
+        super::handlers::freshness_check,
+        super::handlers::get_scaling_hint,
         super::handlers::get_topics,
         super::handlers::subscribe_to_topic,
         super::handlers::unsubscribe_from_topic,
-        super::handlers::get_metrics
+        super::handlers::unsubscribe_matching_topics,
+        super::handlers::get_metrics,
+        super::handlers::get_lifetime_metrics,
+        super::handlers::get_metrics_history,
+        super::handlers::get_topic_metrics,
+        super::handlers::reset_lifetime_metrics,
+        super::handlers::get_captured_packets,
+        super::handlers::get_metrics_windows,
+        super::handlers::get_allocator_stats,
+        super::handlers::get_discovered_topics
     ),
     components(
-        schemas(super::models::SubscribeRequest, super::models::ApiResponse, super::models::TopicsResponse, super::models::MetricsResponse)
+        schemas(super::models::SubscribeRequest, super::models::SubscribeResponse, super::models::VersionResponse, super::models::ApiResponse, super::models::ErrorResponse, super::models::ReadyResponse, super::models::ScalingHintResponse, super::models::ClassMetricsResponse, super::models::FreshnessResponse, super::models::TopicsResponse, super::models::MetricsResponse, super::models::LifetimeMetricsResponse, super::models::TopicCountsResponse, super::models::TopicMetricsResponse, super::models::CapturedPacketResponse, super::models::PacketsResponse, super::models::WindowResponse, super::models::WindowsResponse, super::models::AllocResponse, super::models::DiscoveredTopicsResponse)
     ),
     tags(
         (name = "MQTT Subscriber", description = "MQTT Subscriber API endpoints")
@@ -43,10 +65,69 @@ use super::handlers::{
         )
     )
 )]
-struct ApiDoc;
+pub struct ApiDoc;
+
+/// Write the OpenAPI specification as JSON to the given file
+pub fn export_openapi(path: &str) -> Result<(), String> {
+    let spec = ApiDoc::openapi()
+        .to_pretty_json()
+        .map_err(|e| format!("Failed to serialize OpenAPI spec: {}", e))?;
+    std::fs::write(path, spec)
+        .map_err(|e| format!("Failed to write OpenAPI spec to {}: {}", path, e))
+}
+
+/// Reject requests with 503 while all permits are taken by requests in flight
+async fn limit_connections(
+    State(permits): State<Arc<Semaphore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(_permit) = permits.try_acquire() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many concurrent API requests",
+        )
+            .into_response();
+    };
+    next.run(request).await
+}
+
+/// Reject requests with 401 unless they carry the metrics API key as a bearer token
+async fn require_metrics_key(
+    State(key): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), key.as_bytes()));
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            "Missing or invalid metrics API key",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Compare two byte strings in time independent of where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
 
 /// Create and configure the API router
-pub fn create_router(state: Arc<AppState>) -> Router {
+///
+/// `max_connections` limits the number of requests handled at once, 0 for no limit.
+/// With a `metrics_api_key`, the metrics and topic listing endpoints require it.
+pub fn create_router(
+    state: Arc<AppState>,
+    max_connections: usize,
+    metrics_api_key: Option<String>,
+) -> Router {
     // Configure CORS
     let cors = CorsLayer::new()
         .allow_origin(Any)
@@ -56,14 +137,47 @@ pub fn create_router(state: Arc<AppState>) -> Router {
     // API documentation
     let openapi = ApiDoc::openapi();
 
-    // Create API router
-    Router::new()
-        .route("/health", get(health_check))
+    // Endpoints exposing topic names, which may be sensitive in multi-tenant setups
+    let metrics_routes = Router::new()
         .route("/topics", get(get_topics))
+        .route("/topics/discovered", get(get_discovered_topics))
         .route("/metrics", get(get_metrics))
+        .route("/metrics/lifetime", get(get_lifetime_metrics))
+        .route("/metrics/history", get(get_metrics_history))
+        .route("/metrics/topics", get(get_topic_metrics))
+        .route("/metrics/reset", post(reset_lifetime_metrics));
+    let metrics_routes = match metrics_api_key {
+        Some(key) => metrics_routes.route_layer(middleware::from_fn_with_state(
+            Arc::<str>::from(key),
+            require_metrics_key,
+        )),
+        None => metrics_routes,
+    };
+
+    // Create API router
+    let router = Router::new()
+        .route("/health", get(health_check))
+        .route("/version", get(get_version))
+        .route("/ready", get(readiness_check))
+        .route("/health/freshness", get(freshness_check))
+        .route("/scaling-hint", get(get_scaling_hint))
+        .merge(metrics_routes)
         .route("/subscribe", post(subscribe_to_topic))
+        .route("/unsubscribe", delete(unsubscribe_matching_topics))
         .route("/unsubscribe/{topic}", delete(unsubscribe_from_topic))
+        .route("/debug/packets", get(get_captured_packets))
+        .route("/debug/alloc", get(get_allocator_stats))
+        .route("/debug/windows", get(get_metrics_windows))
         .merge(SwaggerUi::new("/docs").url("/api-docs/openapi.json", openapi))
         .layer(cors)
-        .with_state(state)
+        .with_state(state);
+
+    // Protect the API from connection exhaustion by misbehaving clients
+    if max_connections == 0 {
+        return router;
+    }
+    router.layer(middleware::from_fn_with_state(
+        Arc::new(Semaphore::new(max_connections)),
+        limit_connections,
+    ))
 }
@@ -0,0 +1,127 @@
+//! Validation of the configuration without starting the service
+
+use log::error;
+
+use crate::config::{load_config, Config};
+use crate::logging;
+use crate::processor::enrichment::EnrichmentTable;
+use crate::processor::rules::ProcessingRules;
+#[cfg(feature = "wasm")]
+use crate::processor::wasm::WasmTransform;
+use crate::sink::validate_sinks;
+
+/// Load and validate the configuration, then print a summary of the effective settings
+///
+/// Only local files (the enrichment table and WASM module) are read, no network connections
+/// are opened. Returns false if any error or warning was logged, including invalid values
+/// that were replaced by their defaults.
+pub fn check_config() -> bool {
+    let warnings = logging::warning_count();
+    let configs = load_config();
+
+    if let Err(e) = ProcessingRules::new(&configs.processor, &configs.kafka) {
+        error!("{}", e);
+    }
+    if let Err(e) = validate_sinks(&configs.sinks) {
+        error!("{}", e);
+    }
+    if let Some(Err(e)) = configs
+        .processor
+        .enrichment_table
+        .as_deref()
+        .map(EnrichmentTable::load)
+    {
+        error!("{}", e);
+    }
+    #[cfg(feature = "wasm")]
+    if let Some(Err(e)) = configs.processor.wasm.as_ref().map(WasmTransform::load) {
+        error!("{}", e);
+    }
+    #[cfg(not(feature = "wasm"))]
+    if configs.processor.wasm.is_some() {
+        log::warn!(
+            "WASM_TRANSFORM_PATH is set, but the service was built without the `wasm` feature"
+        );
+    }
+
+    print_summary(&configs);
+
+    let failed = logging::warning_count() - warnings;
+    if failed == 0 {
+        println!("Configuration is valid");
+        true
+    } else {
+        println!(
+            "Configuration check failed with {} errors or warnings",
+            failed
+        );
+        false
+    }
+}
+
+/// Print the effective settings, leaving out credentials
+fn print_summary(configs: &Config) {
+    let (host, port) = configs.mqtt.mqtt_options.broker_address();
+    let outputs: Vec<&str> = configs
+        .sinks
+        .outputs
+        .iter()
+        .map(|output| output.name.as_str())
+        .collect();
+    let serialization_rules: Vec<String> = configs
+        .processor
+        .serialization_rules
+        .iter()
+        .map(|(filter, format)| format!("{}={}", filter, format.as_str()))
+        .collect();
+
+    println!("Instance ID:         {}", configs.instance_id);
+    println!("MQTT broker:         {}:{}", host, port);
+    println!(
+        "MQTT client ID:      {}",
+        configs.mqtt.mqtt_options.client_id()
+    );
+    println!(
+        "MQTT proxy:          {}",
+        configs
+            .mqtt
+            .proxy
+            .as_ref()
+            .map_or("-".to_string(), |proxy| format!(
+                "{}:{}",
+                proxy.host, proxy.port
+            ))
+    );
+    println!("MQTT QoS:            {:?}", configs.mqtt.mqtt_qos);
+    println!("MQTT topics:         {}", configs.mqtt.topics.join(", "));
+    println!("Kafka broker:        {}", configs.kafka.broker);
+    println!("Sensor data topic:   {}", configs.kafka.topic_sensor_data);
+    println!(
+        "Metrics topic:       {}",
+        configs.kafka.topic_service_metrics
+    );
+    println!(
+        "Dead-letter topic:   {}",
+        configs.kafka.topic_dead_letter.as_deref().unwrap_or("-")
+    );
+    println!(
+        "Serialization:       {}",
+        configs.kafka.serialization.as_str()
+    );
+    println!(
+        "Serialization rules: {}",
+        if serialization_rules.is_empty() {
+            "-".to_string()
+        } else {
+            serialization_rules.join(", ")
+        }
+    );
+    println!(
+        "Topic template:      {}",
+        configs.kafka.topic_template.as_deref().unwrap_or("-")
+    );
+    println!("Outputs:             {}", outputs.join(", "));
+    println!("Processor workers:   {}", configs.processor.workers);
+    println!("Queue capacity:      {}", configs.processor.queue_capacity);
+    println!("API port:            {}", configs.api.port);
+}
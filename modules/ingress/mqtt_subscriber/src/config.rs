@@ -1,29 +1,237 @@
 //! Configuration handling for the MQTT subscriber service
 
-use rumqttc::{MqttOptions, QoS};
+use log::{error, warn};
+use rumqttc::{MqttOptions, QoS, Transport};
+
+use crate::metrics::PerTopicPublish;
+use crate::models::{DeliveryGuarantee, SerializationFormat, TopicClass};
+use crate::mqtt::proxy::ProxyConfig;
+use crate::mqtt::tls::{insecure_tls_config, proxied_tls_config};
+use crate::processor::correlation_id::CorrelationIdSource;
+use crate::processor::message_id::MessageIdStrategy;
+use crate::processor::queue::DropPolicy;
+use crate::processor::sensor_id::SensorIdTemplate;
+use crate::processor::units::UnitConversion;
 use std::env;
+use std::str::FromStr;
 use std::time::{Duration, SystemTime};
 
 /// Service configuration
 pub struct MqttConfig {
     pub mqtt_options: MqttOptions,
     pub mqtt_qos: QoS,
+    pub client_capacity: usize,
+    pub auto_reconnect: bool,
+    pub fresh_client_after: u32,
+    /// Dropped connections within `client_id_conflict_window` reported as a client ID
+    /// conflict, 0 to disable the detection
+    pub client_id_conflict_threshold: u32,
+    pub client_id_conflict_window: Duration,
+    pub topics: Vec<String>,
+    pub capture_raw_packets: usize,
+    pub discovery: Option<DiscoveryConfig>,
+    pub subscribe_batch_size: usize,
+    /// Topics resubscribed per second after a reconnect, 0 for no limit
+    pub resubscribe_rate: usize,
+    /// Whether unsubscribing a wildcard also unsubscribes the tracked topics it covers
+    pub unsubscribe_covered: bool,
+    /// SOCKS5 proxy the broker connection is established through, if any
+    pub proxy: Option<ProxyConfig>,
+    /// How long subscription requests wait for the first connection after startup
+    pub startup_wait: Duration,
+    /// How long API subscribe requests wait for the broker's SubAck
+    pub suback_timeout: Duration,
+}
+
+/// Settings of the automatic topic discovery
+#[derive(Clone)]
+pub struct DiscoveryConfig {
+    pub pattern: String,
+    pub window: Duration,
+    pub narrow: bool,
 }
 
 pub struct ApiConfig {
     pub port: u16,
+    pub max_connections: usize,
+    /// Bearer token required by the metrics and topic listing endpoints, if set
+    pub metrics_api_key: Option<String>,
+    /// Whether the debugging endpoints showing internal state are enabled
+    pub debug_endpoints: bool,
 }
 
 pub struct KafkaConfig {
     pub broker: String,
     pub topic_sensor_data: String,
     pub topic_service_metrics: String,
+    pub topic_dead_letter: Option<String>,
+    pub topic_errors: Option<String>,
+    pub use_sensor_timestamp: bool,
+    pub serialization: SerializationFormat,
+    pub topic_template: Option<String>,
+    pub topic_from_mqtt: bool,
+    pub topic_prefix: String,
+    pub topic_separator: String,
+    pub routing_topics: Vec<String>,
+    pub large_payload_threshold: usize,
+    pub topic_large_payloads: Option<String>,
+    pub auto_create_topics: bool,
+    pub throttle: Option<KafkaThrottleConfig>,
+}
+
+/// Rate limit of the messages sent to Kafka
+pub struct KafkaThrottleConfig {
+    /// Messages per second
+    pub rate: f64,
+    /// Drop sends above the rate instead of waiting for them
+    pub drop_excess: bool,
+}
+
+pub struct ProcessorConfig {
+    pub workers: usize,
+    pub queue_capacity: usize,
+    pub queue_drop_policy: DropPolicy,
+    pub drop_empty_payloads: bool,
+    pub serialization_rules: Vec<(String, SerializationFormat)>,
+    pub topic_classes: Vec<(String, TopicClass)>,
+    pub delivery_guarantees: Vec<(String, DeliveryGuarantee)>,
+    pub delivery_retries: u32,
+    pub delivery_retry_backoff: Duration,
+    pub priority_topics: Vec<String>,
+    pub qos_lanes: bool,
+    pub sensor_timestamp_field: Option<String>,
+    pub sensor_id_from_topic: Option<SensorIdTemplate>,
+    pub flatten_json: bool,
+    pub detect_payload_format: bool,
+    pub unit_conversions: Vec<(String, UnitConversion)>,
+    pub transform_pipeline: String,
+    pub message_id_strategy: MessageIdStrategy,
+    pub correlation_id_source: Option<CorrelationIdSource>,
+    pub payload_sample_log_rate: f64,
+    pub max_json_depth: usize,
+    pub max_redeliveries: u32,
+    pub reorder_window: Option<Duration>,
+    pub wasm: Option<WasmConfig>,
+    pub enrichment_table: Option<String>,
+    pub validation: Option<ValidationConfig>,
+    pub feature_flags: Option<FeatureFlagConfig>,
+}
+
+/// Settings of the central feature-flag service
+pub struct FeatureFlagConfig {
+    pub url: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+/// Settings of the external validation service
+pub struct ValidationConfig {
+    pub url: String,
+    pub timeout: Duration,
+    pub cache_ttl: Duration,
+    /// Whether messages are forwarded unvalidated while the service is unavailable
+    pub fail_open: bool,
+}
+
+/// WASM transformation module settings (only used with the `wasm` feature)
+#[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+pub struct WasmConfig {
+    pub path: String,
+    pub memory_limit_bytes: usize,
+    pub timeout: Duration,
+}
+
+pub struct StatsdConfig {
+    pub addr: Option<String>,
+    pub prefix: String,
+    pub interval: Duration,
+}
+
+pub struct InfluxdbConfig {
+    pub url: Option<String>,
+    pub token: Option<String>,
+    pub measurement: String,
+    pub instance: String,
+    pub interval: Duration,
+}
+
+pub struct MetricsConfig {
+    pub windows: usize,
+    pub history_windows: usize,
+    pub max_topics: usize,
+    pub queue_high_watermark: usize,
+    pub queue_high_watermark_duration: Duration,
+    pub scaling_target_throughput: f64,
+    pub scaling_target_queue_depth: usize,
+    pub scaling_target_in_flight: usize,
+    /// Interval of the records sent to the service metrics topic, if enabled
+    pub publish_interval: Option<Duration>,
+    pub publish_per_topic: PerTopicPublish,
+    /// Whether the metrics report the current window until the first one completes
+    pub warmup_current_window: bool,
+    /// One in this many messages is recorded in the windowed metrics
+    pub sample_rate: usize,
+    /// Interval of removing long-silent topics from the per-topic metrics, if enabled
+    pub topic_compaction_interval: Option<Duration>,
+}
+
+/// An output sink messages are delivered to
+pub struct OutputSinkConfig {
+    pub name: String,
+    /// Whether a failure of this sink fails the delivery
+    pub required: bool,
+}
+
+pub struct SinkConfig {
+    pub outputs: Vec<OutputSinkConfig>,
+    pub webhook: Option<WebhookConfig>,
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    pub s3: Option<S3Config>,
+}
+
+pub struct WebhookConfig {
+    pub url: String,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub concurrency: usize,
+}
+
+/// S3 archival sink settings (only used with the `s3` feature)
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+pub struct S3Config {
+    pub bucket: String,
+    pub prefix: String,
+    pub region: String,
+    /// Endpoint of an S3-compatible store, AWS if `None`
+    pub endpoint: Option<String>,
+    pub batch_max_bytes: usize,
+    pub batch_interval: Duration,
+    pub retries: u32,
 }
 
 pub struct Config {
+    pub instance_id: String,
     pub mqtt: MqttConfig,
     pub api: ApiConfig,
     pub kafka: KafkaConfig,
+    pub processor: ProcessorConfig,
+    pub statsd: StatsdConfig,
+    pub influxdb: InfluxdbConfig,
+    pub metrics: MetricsConfig,
+    pub sinks: SinkConfig,
+}
+
+/// Apply the `.env` file over the current environment, so changed values are picked up
+/// when the configuration is loaded again
+pub fn reload_env_file() -> Result<(), String> {
+    // The non-deprecated loaders never override variables that are already set
+    #[allow(deprecated)]
+    let entries = dotenv::dotenv_iter().map_err(|e| format!("Failed to read .env file: {}", e))?;
+    for entry in entries {
+        let (key, value) = entry.map_err(|e| format!("Failed to parse .env file: {}", e))?;
+        env::set_var(key, value);
+    }
+    Ok(())
 }
 
 /// Get an environment variable or return a default value
@@ -31,37 +239,126 @@ fn get_env_or_default(key: &str, default: &str) -> String {
     env::var(key).unwrap_or_else(|_| default.to_string())
 }
 
+/// Parse an environment variable, warning and using `fallback` if its value is invalid
+fn parse_env<T: FromStr>(key: &str, default: &str, fallback: T) -> T {
+    let value = get_env_or_default(key, default);
+    value.parse::<T>().unwrap_or_else(|_| {
+        warn!("Invalid {} {:?}, using {}", key, value, default);
+        fallback
+    })
+}
+
 /// Load configuration from environment variables
 pub fn load_mqtt_configs() -> MqttConfig {
     // Load MQTT configuration
     let mqtt_broker = get_env_or_default("MQTT_BROKER", "xrdevmqtt.edu.metropolia.fi");
-    let mqtt_port = get_env_or_default("MQTT_PORT", "1883")
-        .parse::<u16>()
-        .unwrap_or(1883);
+    let mqtt_port = parse_env::<u16>("MQTT_PORT", "1883", 1883);
     let mqtt_username = get_env_or_default("MQTT_USERNAME", "");
     let mqtt_password = get_env_or_default("MQTT_PASSWORD", "");
     let mqtt_qos = match get_env_or_default("MQTT_QOS", "0").as_str() {
+        "0" => QoS::AtMostOnce,
         "1" => QoS::AtLeastOnce,
         "2" => QoS::ExactlyOnce,
-        _ => QoS::AtMostOnce,
+        qos => {
+            warn!("Invalid MQTT_QOS {:?}, using 0", qos);
+            QoS::AtMostOnce
+        }
     };
-    let mqtt_keep_alive = get_env_or_default("MQTT_KEEP_ALIVE", "60")
-        .parse::<u64>()
-        .unwrap_or(60);
+    let mqtt_keep_alive = parse_env::<u64>("MQTT_KEEP_ALIVE", "60", 60);
+    let mqtt_manual_ack = parse_env::<bool>("MQTT_MANUAL_ACK", "false", false);
+    let mqtt_client_id = get_env_or_default("MQTT_CLIENT_ID", "");
+    let mqtt_auto_reconnect = parse_env::<bool>("MQTT_AUTO_RECONNECT", "true", true);
+    let mqtt_tls = parse_env::<bool>("MQTT_TLS", "false", false);
+    let mqtt_tls_insecure = parse_env::<bool>("MQTT_TLS_INSECURE", "false", false);
+    // Comma-separated topics subscribed on startup, next to the ones added through the API
+    let mqtt_topics = get_env_or_default("MQTT_TOPICS", "")
+        .split(',')
+        .map(str::trim)
+        .filter(|topic| !topic.is_empty())
+        .map(str::to_string)
+        .collect();
+    // Keep the last received publish packets for `/debug/packets`, disabled by default
+    let mqtt_capture_raw_packets = parse_env::<bool>("CAPTURE_RAW_PACKETS", "false", false);
+    let mqtt_capture_raw_packets_limit =
+        parse_env::<usize>("CAPTURE_RAW_PACKETS_LIMIT", "100", 100);
+    // Subscribe to a broad pattern on startup and record the topics that produce data
+    let mqtt_discovery_mode = parse_env::<bool>("DISCOVERY_MODE", "false", false);
+    let mqtt_discovery_pattern = get_env_or_default("DISCOVERY_PATTERN", "#");
+    let mqtt_discovery_window_secs = parse_env::<u64>("DISCOVERY_WINDOW_SECS", "300", 300);
+    let mqtt_discovery_narrow = parse_env::<bool>("DISCOVERY_NARROW", "true", true);
+    // Consecutive connection failures before retrying with a new client ID, 0 to disable
+    let mqtt_fresh_client_after = parse_env::<u32>("RECONNECT_FRESH_CLIENT_AFTER", "0", 0);
+    // Established connections dropped within the window before a client ID conflict is
+    // reported, 0 to disable
+    let mqtt_client_id_conflict_threshold =
+        parse_env::<u32>("MQTT_CLIENT_ID_CONFLICT_THRESHOLD", "3", 3);
+    let mqtt_client_id_conflict_window_secs =
+        parse_env::<u64>("MQTT_CLIENT_ID_CONFLICT_WINDOW_SECS", "60", 60);
+    // Topics sent per SUBSCRIBE packet when subscribing to many topics at once
+    let mqtt_subscribe_batch_size =
+        parse_env::<usize>("MQTT_SUBSCRIBE_BATCH_SIZE", "100", 100).max(1);
+    // Topics resubscribed per second after a reconnect, 0 to resubscribe all at once
+    let mqtt_resubscribe_rate = parse_env::<usize>("RESUBSCRIBE_RATE_PER_SEC", "0", 0);
+    // Topics covered by an unsubscribed wildcard keep their own subscription by default
+    let mqtt_unsubscribe_covered =
+        get_env_or_default("MQTT_WILDCARD_UNSUBSCRIBE", "keep_covered") == "remove_covered";
+    let mqtt_client_cap = parse_env::<usize>("MQTT_CLIENT_CAP", "10", 10).max(1);
+    // SOCKS5 proxy for the broker connection, falling back to the one for all connections
+    let mqtt_proxy = load_mqtt_proxy();
+    // API subscription requests before the first connection wait this long, then fail
+    let mqtt_startup_wait_secs = parse_env::<u64>("MQTT_STARTUP_WAIT_SECS", "10", 10);
+    // API subscribe requests wait this long for the SubAck reporting the granted QoS
+    let mqtt_suback_timeout_secs = parse_env::<u64>("MQTT_SUBACK_TIMEOUT_SECS", "10", 10);
 
-    // Generate a random client ID
-    let timestamp = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    let random_client_id = format!("mqtt-subscriber-{}", timestamp);
+    // Use the configured client ID, or generate a random one
+    let client_id = if mqtt_client_id.is_empty() {
+        generate_client_id()
+    } else {
+        mqtt_client_id
+    };
+
+    // Through a proxy the client connects to the local end of the tunnel, so the broker
+    // certificate has to be verified against the configured hostname instead
+    let mqtt_tls_config = if mqtt_proxy.is_some() && mqtt_tls && !mqtt_tls_insecure {
+        match proxied_tls_config(&mqtt_broker) {
+            Ok(tls_config) => Some(tls_config),
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     // Create MQTT options
-    let mut mqtt_options = MqttOptions::new(random_client_id, mqtt_broker, mqtt_port);
+    let mut mqtt_options = MqttOptions::new(client_id, mqtt_broker, mqtt_port);
 
     // Configure MQTT connection (send ping if no message is received for mqtt_keep_alive seconds)
     mqtt_options.set_keep_alive(Duration::from_secs(mqtt_keep_alive));
 
+    // In manual ack mode the broker only considers a message delivered once we ack it, so
+    // keep the session across reconnects to let the broker redeliver unacked messages
+    if mqtt_manual_ack {
+        mqtt_options.set_manual_acks(true);
+        mqtt_options.set_clean_session(false);
+    }
+
+    // Connect over TLS, verifying the broker certificate against the system roots unless
+    // verification is explicitly disabled
+    if mqtt_tls {
+        if mqtt_tls_insecure {
+            warn!("!!! MQTT_TLS_INSECURE is set: the MQTT broker certificate and hostname are NOT verified. Never use this in production !!!");
+            mqtt_options.set_transport(Transport::tls_with_config(insecure_tls_config()));
+        } else if let Some(tls_config) = mqtt_tls_config {
+            mqtt_options.set_transport(Transport::tls_with_config(tls_config));
+        } else {
+            mqtt_options.set_transport(Transport::tls_with_default_config());
+        }
+    } else if mqtt_tls_insecure {
+        warn!("MQTT_TLS_INSECURE is set without MQTT_TLS, ignoring it");
+    }
+
     // Add credentials if provided
     if !mqtt_username.is_empty() {
         mqtt_options.set_credentials(mqtt_username, mqtt_password);
@@ -70,15 +367,110 @@ pub fn load_mqtt_configs() -> MqttConfig {
     MqttConfig {
         mqtt_options,
         mqtt_qos,
+        client_capacity: mqtt_client_cap,
+        auto_reconnect: mqtt_auto_reconnect,
+        fresh_client_after: mqtt_fresh_client_after,
+        client_id_conflict_threshold: mqtt_client_id_conflict_threshold,
+        client_id_conflict_window: Duration::from_secs(mqtt_client_id_conflict_window_secs),
+        topics: mqtt_topics,
+        capture_raw_packets: if mqtt_capture_raw_packets {
+            mqtt_capture_raw_packets_limit
+        } else {
+            0
+        },
+        discovery: mqtt_discovery_mode.then(|| DiscoveryConfig {
+            pattern: mqtt_discovery_pattern,
+            window: Duration::from_secs(mqtt_discovery_window_secs),
+            narrow: mqtt_discovery_narrow,
+        }),
+        subscribe_batch_size: mqtt_subscribe_batch_size,
+        resubscribe_rate: mqtt_resubscribe_rate,
+        unsubscribe_covered: mqtt_unsubscribe_covered,
+        proxy: mqtt_proxy,
+        startup_wait: Duration::from_secs(mqtt_startup_wait_secs),
+        suback_timeout: Duration::from_secs(mqtt_suback_timeout_secs),
+    }
+}
+
+/// Load the SOCKS5 proxy for the MQTT connection from `MQTT_PROXY`, or else `ALL_PROXY`
+///
+/// An invalid or unsupported proxy URL is logged and the broker is connected to directly.
+fn load_mqtt_proxy() -> Option<ProxyConfig> {
+    let (key, url) = ["MQTT_PROXY", "ALL_PROXY"]
+        .into_iter()
+        .map(|key| (key, get_env_or_default(key, "")))
+        .find(|(_, url)| !url.is_empty())?;
+
+    ProxyConfig::parse(&url)
+        .map_err(|e| {
+            warn!(
+                "{} is not a usable MQTT proxy, connecting to the broker directly: {}",
+                key, e
+            )
+        })
+        .ok()
+}
+
+/// Generate a timestamp-based MQTT client ID
+pub fn generate_client_id() -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!("mqtt-subscriber-{}", timestamp)
+}
+
+/// Rebuild MQTT options with a different client ID, keeping all other settings
+pub fn with_client_id(mqtt_options: &MqttOptions, client_id: String) -> MqttOptions {
+    let (broker, port) = mqtt_options.broker_address();
+    rebuild_options(mqtt_options, client_id, broker, port)
+}
+
+/// Rebuild MQTT options with a different broker address, keeping all other settings
+pub fn with_broker_address(mqtt_options: &MqttOptions, broker: String, port: u16) -> MqttOptions {
+    let client_id = mqtt_options.client_id();
+    rebuild_options(mqtt_options, client_id, broker, port)
+}
+
+fn rebuild_options(
+    mqtt_options: &MqttOptions,
+    client_id: String,
+    broker: String,
+    port: u16,
+) -> MqttOptions {
+    let mut rebuilt = MqttOptions::new(client_id, broker, port);
+    rebuilt
+        .set_transport(mqtt_options.transport())
+        .set_keep_alive(mqtt_options.keep_alive())
+        .set_clean_session(mqtt_options.clean_session())
+        .set_manual_acks(mqtt_options.manual_acks())
+        .set_inflight(mqtt_options.inflight())
+        .set_request_channel_capacity(mqtt_options.request_channel_capacity())
+        .set_pending_throttle(mqtt_options.pending_throttle());
+    if let Some((username, password)) = mqtt_options.credentials() {
+        rebuilt.set_credentials(username, password);
+    }
+    if let Some(last_will) = mqtt_options.last_will() {
+        rebuilt.set_last_will(last_will);
     }
+    rebuilt
 }
 
 pub fn load_api_configs() -> ApiConfig {
-    let api_port = get_env_or_default("API_PORT", "3000")
-        .parse::<u16>()
-        .unwrap_or(3000);
+    let api_port = parse_env::<u16>("API_PORT", "3000", 3000);
+    // Concurrent API requests before new ones are rejected, 0 for no limit
+    let api_max_connections = parse_env::<usize>("MAX_API_CONNECTIONS", "256", 256);
 
-    ApiConfig { port: api_port }
+    // The metrics and topic listing endpoints are open unless a key is set
+    let metrics_api_key = get_env_or_default("METRICS_API_KEY", "");
+    let api_debug_endpoints = parse_env::<bool>("DEBUG_ENDPOINTS", "false", false);
+
+    ApiConfig {
+        port: api_port,
+        max_connections: api_max_connections,
+        metrics_api_key: (!metrics_api_key.is_empty()).then_some(metrics_api_key),
+        debug_endpoints: api_debug_endpoints,
+    }
 }
 
 pub fn load_kafka_configs() -> KafkaConfig {
@@ -86,18 +478,481 @@ pub fn load_kafka_configs() -> KafkaConfig {
     let kafka_topic_sensor_data = get_env_or_default("KAFKA_TOPIC_SENSOR_DATA", "smartlab-data");
     let kafka_topic_service_metrics =
         get_env_or_default("KAFKA_TOPIC_SERVICE_METRICS", "smartlab-subscriber-metrics");
+    // Dead-lettering is disabled unless a topic is set
+    let kafka_topic_dead_letter = get_env_or_default("KAFKA_TOPIC_DEAD_LETTER", "");
+    // Error events are not published unless a topic is set
+    let kafka_topic_errors = get_env_or_default("KAFKA_TOPIC_ERRORS", "");
+    let kafka_use_sensor_timestamp =
+        parse_env::<bool>("KAFKA_USE_SENSOR_TIMESTAMP", "false", false);
+    // Format of the sensor data records of topics without a serialization rule
+    let kafka_serialization = get_env_or_default("KAFKA_SERIALIZATION", "json");
+    let kafka_serialization = match SerializationFormat::parse(&kafka_serialization) {
+        Some(format @ (SerializationFormat::Json | SerializationFormat::Protobuf)) => format,
+        _ => {
+            warn!(
+                "Invalid KAFKA_SERIALIZATION {}, using json",
+                kafka_serialization
+            );
+            SerializationFormat::Json
+        }
+    };
+    // Topics are not derived from the payload unless a template is set
+    let kafka_topic_template = get_env_or_default("KAFKA_TOPIC_TEMPLATE", "");
+    // Mirror the MQTT topic hierarchy into Kafka topic names instead of the sensor data topic
+    let kafka_topic_from_mqtt = parse_env::<bool>("KAFKA_TOPIC_FROM_MQTT", "false", false);
+    let kafka_topic_prefix = get_env_or_default("KAFKA_TOPIC_PREFIX", "");
+    let kafka_topic_separator = get_env_or_default("KAFKA_TOPIC_SEPARATOR", ".");
+    // Payloads above the threshold go to the large payload topic, 0 to disable
+    let kafka_large_payload_threshold = parse_env::<usize>("SIZE_ROUTING_THRESHOLD_BYTES", "0", 0);
+    let kafka_topic_large_payloads = get_env_or_default("KAFKA_TOPIC_LARGE_PAYLOADS", "");
+    if kafka_large_payload_threshold > 0 && kafka_topic_large_payloads.is_empty() {
+        warn!("SIZE_ROUTING_THRESHOLD_BYTES is set without KAFKA_TOPIC_LARGE_PAYLOADS, not routing by size");
+    }
+    // Comma-separated destinations of the template or mapping, checked on startup
+    let kafka_routing_topics = get_env_or_default("KAFKA_ROUTING_TOPICS", "")
+        .split(',')
+        .map(str::trim)
+        .filter(|topic| !topic.is_empty())
+        .map(str::to_string)
+        .collect();
+    let kafka_auto_create_topics = parse_env::<bool>("KAFKA_AUTO_CREATE_TOPICS", "false", false);
+    // Cap on the messages sent to Kafka per second, 0 for no limit
+    let kafka_max_send_rate = parse_env::<f64>("KAFKA_MAX_SEND_RATE", "0", 0.0);
+    let kafka_throttle_drop = match get_env_or_default("KAFKA_THROTTLE_POLICY", "wait").as_str() {
+        "wait" => false,
+        "drop" => true,
+        policy => {
+            warn!("Invalid KAFKA_THROTTLE_POLICY {:?}, using wait", policy);
+            false
+        }
+    };
 
     KafkaConfig {
         broker: kafka_broker,
         topic_sensor_data: kafka_topic_sensor_data,
         topic_service_metrics: kafka_topic_service_metrics,
+        topic_dead_letter: (!kafka_topic_dead_letter.is_empty()).then_some(kafka_topic_dead_letter),
+        topic_errors: (!kafka_topic_errors.is_empty()).then_some(kafka_topic_errors),
+        use_sensor_timestamp: kafka_use_sensor_timestamp,
+        serialization: kafka_serialization,
+        topic_template: (!kafka_topic_template.is_empty()).then_some(kafka_topic_template),
+        topic_from_mqtt: kafka_topic_from_mqtt,
+        topic_prefix: kafka_topic_prefix,
+        topic_separator: kafka_topic_separator,
+        routing_topics: kafka_routing_topics,
+        large_payload_threshold: kafka_large_payload_threshold,
+        topic_large_payloads: (!kafka_topic_large_payloads.is_empty())
+            .then_some(kafka_topic_large_payloads),
+        auto_create_topics: kafka_auto_create_topics,
+        throttle: (kafka_max_send_rate > 0.0).then_some(KafkaThrottleConfig {
+            rate: kafka_max_send_rate,
+            drop_excess: kafka_throttle_drop,
+        }),
+    }
+}
+
+pub fn load_processor_configs() -> ProcessorConfig {
+    // 0 workers keeps the task-per-message model
+    let processor_workers = parse_env::<usize>("PROCESSOR_WORKERS", "0", 0);
+    let processor_queue_capacity =
+        parse_env::<usize>("PROCESSOR_QUEUE_CAPACITY", "1000", 1000).max(1);
+    let processor_queue_drop_policy =
+        match get_env_or_default("CHANNEL_DROP_POLICY", "block").as_str() {
+            "block" => DropPolicy::Block,
+            "drop_oldest" => DropPolicy::DropOldest,
+            "drop_newest" => DropPolicy::DropNewest,
+            policy => {
+                warn!("Invalid CHANNEL_DROP_POLICY {:?}, using block", policy);
+                DropPolicy::Block
+            }
+        };
+
+    // Empty payloads are forwarded unless explicitly dropped
+    let drop_empty_payloads = parse_env::<bool>("DROP_EMPTY_PAYLOADS", "false", false);
+
+    // Comma-separated `topic filter=format` rules, unmatched topics are sent as JSON
+    let serialization_rules = get_env_or_default("SERIALIZATION_RULES", "")
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .filter_map(|rule| {
+            let parsed = rule.split_once('=').and_then(|(filter, format)| {
+                Some((
+                    filter.trim().to_string(),
+                    SerializationFormat::parse(format.trim())?,
+                ))
+            });
+            if parsed.is_none() {
+                warn!("Ignoring invalid serialization rule: {}", rule);
+            }
+            parsed
+        })
+        .collect();
+
+    // Comma-separated `topic filter=class` rules, unmatched topics are in the normal class
+    let topic_classes = get_env_or_default("TOPIC_CLASSES", "")
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .filter_map(|rule| {
+            let parsed = rule.split_once('=').and_then(|(filter, class)| {
+                Some((filter.trim().to_string(), TopicClass::parse(class.trim())?))
+            });
+            if parsed.is_none() {
+                warn!("Ignoring invalid topic class rule: {}", rule);
+            }
+            parsed
+        })
+        .collect();
+
+    // Comma-separated `topic filter=guarantee` rules, unmatched topics are best effort
+    let delivery_guarantees = get_env_or_default("DELIVERY_GUARANTEES", "")
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .filter_map(|rule| {
+            let parsed = rule.split_once('=').and_then(|(filter, guarantee)| {
+                Some((
+                    filter.trim().to_string(),
+                    DeliveryGuarantee::parse(guarantee.trim())?,
+                ))
+            });
+            if parsed.is_none() {
+                warn!("Ignoring invalid delivery guarantee rule: {}", rule);
+            }
+            parsed
+        })
+        .collect();
+
+    // Retries of failed deliveries for at-least-once topics, with a doubling backoff
+    let delivery_retries = parse_env::<u32>("DELIVERY_RETRIES", "3", 3);
+    let delivery_retry_backoff_ms = parse_env::<u64>("DELIVERY_RETRY_BACKOFF_MS", "500", 500);
+
+    // Comma-separated topic filters routed to the high priority lane
+    let priority_topics = get_env_or_default("PRIORITY_TOPICS", "")
+        .split(',')
+        .map(str::trim)
+        .filter(|filter| !filter.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    // Route QoS 1 and 2 messages to their own lanes ahead of QoS 0
+    let qos_lanes = parse_env::<bool>("QOS_LANES", "false", false);
+
+    // Payload field holding the measurement time, not parsed if empty
+    let sensor_timestamp_field = get_env_or_default("SENSOR_TIMESTAMP_FIELD", "");
+
+    // Topic levels the sensor ID is built from, the whole topic is used if empty
+    let sensor_id_from_topic = get_env_or_default("SENSOR_ID_FROM_TOPIC", "");
+    let sensor_id_from_topic = (!sensor_id_from_topic.is_empty())
+        .then(|| SensorIdTemplate::parse(&sensor_id_from_topic))
+        .and_then(|template| {
+            template
+                .map_err(|e| {
+                    warn!(
+                        "Invalid SENSOR_ID_FROM_TOPIC, using the whole topic as sensor ID: {}",
+                        e
+                    )
+                })
+                .ok()
+        });
+
+    let flatten_json = parse_env::<bool>("FLATTEN_JSON", "false", false);
+    // Sniff the payload encoding instead of relying on the per-topic formats alone
+    let detect_payload_format = parse_env::<bool>("DETECT_PAYLOAD_FORMAT", "false", false);
+
+    // Comma-separated `payload field=conversion` rules, e.g. `temperature=f_to_c`
+    let unit_conversions = get_env_or_default("UNIT_CONVERSIONS", "")
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .filter_map(|rule| {
+            let parsed = rule.split_once('=').and_then(|(field, conversion)| {
+                Some((
+                    field.trim().to_string(),
+                    UnitConversion::parse(conversion.trim())?,
+                ))
+            });
+            if parsed.is_none() {
+                warn!("Ignoring invalid unit conversion: {}", rule);
+            }
+            parsed
+        })
+        .collect();
+
+    // JSON array of transformation steps, validated when the processing rules are built
+    let transform_pipeline = get_env_or_default("TRANSFORM_PIPELINE", "");
+
+    let message_id_strategy = get_env_or_default("MESSAGE_ID_STRATEGY", "none");
+    let message_id_strategy = MessageIdStrategy::parse(&message_id_strategy).unwrap_or_else(|| {
+        warn!(
+            "Invalid MESSAGE_ID_STRATEGY {}, not generating message IDs",
+            message_id_strategy
+        );
+        MessageIdStrategy::None
+    });
+
+    // Payload field holding the correlation ID, no `correlation-id` header if empty
+    let correlation_id_field = get_env_or_default("CORRELATION_ID_FIELD", "");
+
+    // Fraction of messages whose payload is logged at info level, 0 to disable
+    let payload_sample_log_rate = get_env_or_default("PAYLOAD_SAMPLE_LOG_RATE", "0")
+        .parse::<f64>()
+        .ok()
+        .filter(|rate| (0.0..=1.0).contains(rate))
+        .unwrap_or_else(|| {
+            warn!("PAYLOAD_SAMPLE_LOG_RATE must be between 0 and 1, not sampling payloads");
+            0.0
+        });
+
+    // Nesting limit for payloads parsed as JSON, 0 to disable
+    let max_json_depth = parse_env::<usize>("MAX_JSON_DEPTH", "64", 64);
+
+    // Redeliveries of a failing message in manual ack mode before it is dead-lettered and
+    // acked, 0 to let the broker redeliver it indefinitely
+    let max_redeliveries = parse_env::<u32>("MAX_REDELIVERIES", "0", 0);
+
+    // Time records are held to deliver them in timestamp order, 0 to deliver right away
+    let reorder_window_ms = parse_env::<u64>("REORDER_WINDOW_MS", "0", 0);
+    let reorder_window = (reorder_window_ms > 0).then(|| Duration::from_millis(reorder_window_ms));
+
+    // WASM transformation is disabled unless a module path is set
+    let wasm_transform_path = get_env_or_default("WASM_TRANSFORM_PATH", "");
+    let wasm_memory_limit =
+        parse_env::<usize>("WASM_MEMORY_LIMIT_BYTES", "16777216", 16 * 1024 * 1024);
+    let wasm_timeout_ms = parse_env::<u64>("WASM_TIMEOUT_MS", "100", 100);
+
+    // Enrichment is disabled unless a table is set
+    let enrichment_table = get_env_or_default("ENRICHMENT_TABLE", "");
+
+    // Validation by an external service is disabled unless its URL is set
+    let validation_service_url = get_env_or_default("VALIDATION_SERVICE_URL", "");
+    let validation_timeout_ms = parse_env::<u64>("VALIDATION_TIMEOUT_MS", "1000", 1000);
+    let validation_cache_ttl_secs = parse_env::<u64>("VALIDATION_CACHE_TTL_SECS", "10", 10);
+    let validation_fail_open = get_env_or_default("VALIDATION_FAIL_MODE", "closed") == "open";
+
+    // Feature-flag service polled for runtime toggles, disabled if empty
+    let feature_flag_url = get_env_or_default("FEATURE_FLAG_URL", "");
+    let feature_flag_interval_secs =
+        parse_env::<u64>("FEATURE_FLAG_INTERVAL_SECS", "30", 30).max(1);
+    let feature_flag_timeout_ms = parse_env::<u64>("FEATURE_FLAG_TIMEOUT_MS", "2000", 2000);
+
+    ProcessorConfig {
+        workers: processor_workers,
+        queue_capacity: processor_queue_capacity,
+        queue_drop_policy: processor_queue_drop_policy,
+        drop_empty_payloads,
+        serialization_rules,
+        topic_classes,
+        delivery_guarantees,
+        delivery_retries,
+        delivery_retry_backoff: Duration::from_millis(delivery_retry_backoff_ms),
+        priority_topics,
+        qos_lanes,
+        sensor_timestamp_field: (!sensor_timestamp_field.is_empty())
+            .then_some(sensor_timestamp_field),
+        sensor_id_from_topic,
+        flatten_json,
+        detect_payload_format,
+        unit_conversions,
+        transform_pipeline,
+        message_id_strategy,
+        correlation_id_source: (!correlation_id_field.is_empty())
+            .then_some(CorrelationIdSource::PayloadField(correlation_id_field)),
+        payload_sample_log_rate,
+        max_json_depth,
+        max_redeliveries,
+        reorder_window,
+        wasm: (!wasm_transform_path.is_empty()).then(|| WasmConfig {
+            path: wasm_transform_path,
+            memory_limit_bytes: wasm_memory_limit,
+            timeout: Duration::from_millis(wasm_timeout_ms),
+        }),
+        enrichment_table: (!enrichment_table.is_empty()).then_some(enrichment_table),
+        validation: (!validation_service_url.is_empty()).then(|| ValidationConfig {
+            url: validation_service_url,
+            timeout: Duration::from_millis(validation_timeout_ms),
+            cache_ttl: Duration::from_secs(validation_cache_ttl_secs),
+            fail_open: validation_fail_open,
+        }),
+        feature_flags: (!feature_flag_url.is_empty()).then(|| FeatureFlagConfig {
+            url: feature_flag_url,
+            interval: Duration::from_secs(feature_flag_interval_secs),
+            timeout: Duration::from_millis(feature_flag_timeout_ms),
+        }),
+    }
+}
+
+pub fn load_statsd_configs() -> StatsdConfig {
+    // StatsD export is disabled unless an address is set
+    let statsd_addr = get_env_or_default("STATSD_ADDR", "");
+    let statsd_prefix = get_env_or_default("STATSD_PREFIX", "mqtt_subscriber");
+    let statsd_interval = parse_env::<u64>("STATSD_INTERVAL_SECS", "10", 10).max(1);
+
+    StatsdConfig {
+        addr: (!statsd_addr.is_empty()).then_some(statsd_addr),
+        prefix: statsd_prefix,
+        interval: Duration::from_secs(statsd_interval),
+    }
+}
+
+/// Get the name of this instance, attached to everything it outputs
+///
+/// Defaults to the host name, which is the pod name on Kubernetes.
+pub fn load_instance_id() -> String {
+    match get_env_or_default("INSTANCE_ID", "") {
+        instance_id if instance_id.is_empty() => get_env_or_default("HOSTNAME", "mqtt_subscriber"),
+        instance_id => instance_id,
+    }
+}
+
+pub fn load_influxdb_configs() -> InfluxdbConfig {
+    // InfluxDB export is disabled unless a write URL is set
+    let influxdb_url = get_env_or_default("INFLUXDB_URL", "");
+    let influxdb_token = get_env_or_default("INFLUXDB_TOKEN", "");
+    let influxdb_measurement = get_env_or_default("INFLUXDB_MEASUREMENT", "mqtt_subscriber");
+    // Instances are told apart by their instance ID unless named explicitly
+    let influxdb_instance = match get_env_or_default("INFLUXDB_INSTANCE", "") {
+        instance if instance.is_empty() => load_instance_id(),
+        instance => instance,
+    };
+    let influxdb_interval = parse_env::<u64>("INFLUXDB_INTERVAL_SECS", "10", 10).max(1);
+
+    InfluxdbConfig {
+        url: (!influxdb_url.is_empty()).then_some(influxdb_url),
+        token: (!influxdb_token.is_empty()).then_some(influxdb_token),
+        measurement: influxdb_measurement,
+        instance: influxdb_instance,
+        interval: Duration::from_secs(influxdb_interval),
+    }
+}
+
+pub fn load_metrics_configs() -> MetricsConfig {
+    // Completed one-minute windows kept and aggregated by `/metrics`
+    let metrics_windows = parse_env::<usize>("METRICS_WINDOWS", "1", 1).max(1);
+    // Completed windows kept for `/metrics/history`, at least the aggregated ones
+    let metrics_history_windows =
+        parse_env::<usize>("METRICS_HISTORY_WINDOWS", "0", 0).max(metrics_windows);
+    // Topics tracked individually in the per-topic metrics, 0 to disable them
+    let metrics_max_topics = parse_env::<usize>("METRICS_MAX_TOPICS", "1000", 1000);
+    // Removal of topics silent for longer than the kept windows, 0 to disable
+    let metrics_topic_compaction_secs =
+        parse_env::<u64>("METRICS_TOPIC_COMPACTION_INTERVAL_SECS", "0", 0);
+    // Report the in-progress window until the first window completes, instead of zeros
+    let metrics_warmup_current_window =
+        parse_env::<bool>("METRICS_WARMUP_CURRENT_WINDOW", "true", true);
+    // Record only one in this many messages in the windowed metrics, scaling up their counts
+    let metrics_sample_rate = parse_env::<usize>("METRICS_SAMPLE_RATE", "1", 1).max(1);
+
+    // Processing queue depth above which `/ready` fails once sustained, 0 to disable
+    let queue_high_watermark = parse_env::<usize>("QUEUE_HIGH_WATERMARK", "0", 0);
+    let queue_high_watermark_secs = parse_env::<u64>("QUEUE_HIGH_WATERMARK_SECS", "30", 30);
+
+    // Per-replica targets the scaling hint is normalized to, 0 to ignore an input
+    let scaling_target_throughput = get_env_or_default("SCALING_TARGET_THROUGHPUT", "1000")
+        .parse::<f64>()
+        .ok()
+        .filter(|target| target.is_finite() && *target >= 0.0)
+        .unwrap_or(1000.0);
+    let scaling_target_queue_depth = parse_env::<usize>("SCALING_TARGET_QUEUE_DEPTH", "1000", 1000);
+    let scaling_target_in_flight = parse_env::<usize>("SCALING_TARGET_IN_FLIGHT", "100", 100);
+
+    // Metrics published to the service metrics topic, 0 to disable
+    let metrics_publish_interval = parse_env::<u64>("METRICS_PUBLISH_INTERVAL_SECS", "0", 0);
+    let metrics_publish_per_topic = get_env_or_default("METRICS_PUBLISH_PER_TOPIC", "false");
+    let metrics_publish_per_topic = PerTopicPublish::parse(&metrics_publish_per_topic)
+        .unwrap_or_else(|| {
+            warn!(
+                "Invalid METRICS_PUBLISH_PER_TOPIC {:?}, only publishing the aggregate metrics",
+                metrics_publish_per_topic
+            );
+            PerTopicPublish::Off
+        });
+
+    MetricsConfig {
+        windows: metrics_windows,
+        history_windows: metrics_history_windows,
+        max_topics: metrics_max_topics,
+        queue_high_watermark,
+        queue_high_watermark_duration: Duration::from_secs(queue_high_watermark_secs),
+        scaling_target_throughput,
+        scaling_target_queue_depth,
+        scaling_target_in_flight,
+        publish_interval: (metrics_publish_interval > 0)
+            .then(|| Duration::from_secs(metrics_publish_interval)),
+        publish_per_topic: metrics_publish_per_topic,
+        warmup_current_window: metrics_warmup_current_window,
+        sample_rate: metrics_sample_rate,
+        topic_compaction_interval: (metrics_topic_compaction_secs > 0)
+            .then(|| Duration::from_secs(metrics_topic_compaction_secs)),
+    }
+}
+
+pub fn load_sink_configs() -> SinkConfig {
+    // Comma-separated sink names, a trailing `?` marks a sink as optional
+    let output_sinks = get_env_or_default("OUTPUT_SINKS", "kafka");
+    let outputs = output_sinks
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| match name.strip_suffix('?') {
+            Some(name) => OutputSinkConfig {
+                name: name.to_string(),
+                required: false,
+            },
+            None => OutputSinkConfig {
+                name: name.to_string(),
+                required: true,
+            },
+        })
+        .collect();
+
+    // The webhook sink needs a URL to be usable
+    let webhook_url = get_env_or_default("WEBHOOK_URL", "");
+    let webhook_timeout_ms = parse_env::<u64>("WEBHOOK_TIMEOUT_MS", "5000", 5000);
+    let webhook_retries = parse_env::<u32>("WEBHOOK_RETRIES", "2", 2);
+    let webhook_concurrency = parse_env::<usize>("WEBHOOK_CONCURRENCY", "16", 16).max(1);
+
+    // The S3 sink needs a bucket to be usable, credentials come from the usual AWS sources
+    let s3_bucket = get_env_or_default("S3_BUCKET", "");
+    let s3_prefix = get_env_or_default("S3_PREFIX", "");
+    let s3_region = get_env_or_default("S3_REGION", "us-east-1");
+    let s3_endpoint = get_env_or_default("S3_ENDPOINT", "");
+    let s3_batch_max_bytes =
+        parse_env::<usize>("S3_BATCH_MAX_BYTES", "8388608", 8 * 1024 * 1024).max(1);
+    let s3_batch_interval_secs = parse_env::<u64>("S3_BATCH_INTERVAL_SECS", "60", 60).max(1);
+    let s3_retries = parse_env::<u32>("S3_RETRIES", "3", 3);
+
+    SinkConfig {
+        outputs,
+        webhook: (!webhook_url.is_empty()).then(|| WebhookConfig {
+            url: webhook_url,
+            timeout: Duration::from_millis(webhook_timeout_ms),
+            retries: webhook_retries,
+            concurrency: webhook_concurrency,
+        }),
+        s3: (!s3_bucket.is_empty()).then(|| S3Config {
+            bucket: s3_bucket,
+            prefix: s3_prefix,
+            region: s3_region,
+            endpoint: (!s3_endpoint.is_empty()).then_some(s3_endpoint),
+            batch_max_bytes: s3_batch_max_bytes,
+            batch_interval: Duration::from_secs(s3_batch_interval_secs),
+            retries: s3_retries,
+        }),
     }
 }
 
 pub fn load_config() -> Config {
     Config {
+        instance_id: load_instance_id(),
         mqtt: load_mqtt_configs(),
         api: load_api_configs(),
         kafka: load_kafka_configs(),
+        processor: load_processor_configs(),
+        statsd: load_statsd_configs(),
+        influxdb: load_influxdb_configs(),
+        metrics: load_metrics_configs(),
+        sinks: load_sink_configs(),
     }
 }
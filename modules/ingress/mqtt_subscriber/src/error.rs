@@ -0,0 +1,50 @@
+//! Structured errors shared across the service
+
+use std::fmt;
+
+/// Errors returned by service operations, e.g. when managing MQTT subscriptions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpineError {
+    /// The topic or topic filter is not valid
+    InvalidTopic(String),
+    /// The broker refused the operation for this client
+    NotAuthorized(String),
+    /// The MQTT client could not pass the request to the broker connection
+    Disconnected,
+    /// The MQTT client hasn't connected to the broker since the service started
+    Connecting,
+    /// The broker didn't acknowledge the request in time
+    AckTimeout,
+}
+
+impl SpineError {
+    /// Machine-readable error code used in API responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            SpineError::InvalidTopic(_) => "invalid_topic",
+            SpineError::NotAuthorized(_) => "not_authorized",
+            SpineError::Disconnected => "disconnected",
+            SpineError::Connecting => "connecting",
+            SpineError::AckTimeout => "ack_timeout",
+        }
+    }
+}
+
+impl fmt::Display for SpineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpineError::InvalidTopic(topic) => write!(f, "Invalid topic: {}", topic),
+            SpineError::NotAuthorized(topic) => write!(f, "Not authorized for topic: {}", topic),
+            SpineError::Disconnected => write!(f, "MQTT client is disconnected"),
+            SpineError::Connecting => {
+                write!(
+                    f,
+                    "MQTT client is still connecting to the broker, retry later"
+                )
+            }
+            SpineError::AckTimeout => write!(f, "Broker did not acknowledge the request in time"),
+        }
+    }
+}
+
+impl std::error::Error for SpineError {}
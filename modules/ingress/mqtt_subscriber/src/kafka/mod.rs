@@ -1,3 +1,5 @@
 //! Kafka functionality
 
 pub mod producer;
+pub mod throttle;
+pub mod topic_template;
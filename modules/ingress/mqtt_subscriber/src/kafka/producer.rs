@@ -1,35 +1,55 @@
 //! Kafka integration for MQTT messages
 
+use arc_swap::ArcSwap;
 use log::{debug, error, info, warn};
+use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
+use rdkafka::client::DefaultClientContext;
 use rdkafka::config::ClientConfig;
 use rdkafka::consumer::{BaseConsumer, Consumer};
 use rdkafka::error::KafkaError;
+use rdkafka::message::{Header, OwnedHeaders};
 use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
-use crate::models::SensorData;
+use crate::config::KafkaConfig;
+use crate::kafka::throttle::SendThrottle;
+use crate::metrics::UptimeTracker;
+use crate::models::{
+    DeadLetterReason, MqttMessage, OutputRecord, ProcessingError, SensorData, SerializationFormat,
+};
+use crate::mqtt::topic::sanitize_topic;
+use crate::processor::protobuf::encode_sensor_data;
+
+/// Maximum number of payload bytes included in error events
+const ERROR_PAYLOAD_SAMPLE_BYTES: usize = 256;
 
 /// Kafka producer for sending MQTT messages to Kafka
 pub struct KafkaProducer {
     producer: FutureProducer,
     bootstrap_servers: String,
     connection_status: Arc<AtomicBool>,
-    available_topics: Vec<String>,
+    uptime: Arc<UptimeTracker>,
+    /// Topics in the cluster, refreshed with every health check
+    available_topics: Arc<ArcSwap<Vec<String>>>,
     sensor_data_topic: String,
     service_metrics_topic: String,
+    dead_letter_topic: Option<String>,
+    errors_topic: Option<String>,
+    use_sensor_timestamp: bool,
+    /// Name of this instance, sent as the `instance-id` header of every record
+    instance_id: String,
+    /// Limit on the send rate, if configured
+    throttle: Option<SendThrottle>,
     health_check_interval: Duration,
     reconnect_backoff_ms: Arc<std::sync::atomic::AtomicU64>,
 }
 
 impl KafkaProducer {
-    /// Create a new Kafka producer
-    pub async fn new(
-        bootstrap_servers: &str,
-        sensor_data_topic: &str,
-        service_metrics_topic: &str,
-    ) -> Result<Self, KafkaError> {
+    /// Create a new Kafka producer for the configured broker and topics
+    pub async fn new(config: &KafkaConfig, instance_id: &str) -> Result<Self, KafkaError> {
+        let bootstrap_servers = config.broker.as_str();
         let reconnect_attempts = 5;
         let health_check_interval = Duration::from_secs(30);
 
@@ -40,9 +60,15 @@ impl KafkaProducer {
             producer,
             bootstrap_servers: bootstrap_servers.to_string(),
             connection_status: Arc::new(AtomicBool::new(connection_status)),
-            available_topics,
-            sensor_data_topic: sensor_data_topic.to_string(),
-            service_metrics_topic: service_metrics_topic.to_string(),
+            uptime: Arc::new(UptimeTracker::new(connection_status)),
+            available_topics: Arc::new(ArcSwap::from_pointee(available_topics)),
+            sensor_data_topic: config.topic_sensor_data.clone(),
+            service_metrics_topic: config.topic_service_metrics.clone(),
+            dead_letter_topic: config.topic_dead_letter.clone(),
+            errors_topic: config.topic_errors.clone(),
+            use_sensor_timestamp: config.use_sensor_timestamp,
+            instance_id: instance_id.to_string(),
+            throttle: config.throttle.as_ref().map(SendThrottle::new),
             health_check_interval,
             reconnect_backoff_ms: Arc::new(std::sync::atomic::AtomicU64::new(1000)),
         };
@@ -65,6 +91,8 @@ impl KafkaProducer {
             .set("retry.backoff.ms", "1000")
             .set("request.timeout.ms", "10000")
             .set("message.send.max.retries", "3")
+            // Wait for all in-sync replicas, which at-least-once delivery relies on
+            .set("acks", "all")
             .set("client.id", "mqtt_subscriber")
             .set("compression.type", "snappy")
             .create()?;
@@ -126,9 +154,11 @@ impl KafkaProducer {
 
     fn start_health_check(&self) {
         let connection_status = self.connection_status.clone();
+        let uptime = self.uptime.clone();
         let bootstrap_servers = self.bootstrap_servers.clone();
         let interval = self.health_check_interval;
         let reconnect_backoff = self.reconnect_backoff_ms.clone();
+        let available_topics = self.available_topics.clone();
 
         tokio::spawn(async move {
             let mut interval_timer = tokio::time::interval(interval);
@@ -157,10 +187,23 @@ impl KafkaProducer {
 
                 match client_config.create::<BaseConsumer>() {
                     Ok(client) => match client.fetch_metadata(None, Duration::from_secs(5)) {
-                        Ok(_) => {
+                        Ok(metadata) => {
+                            // Pick up topics created since the last check
+                            let topics = metadata
+                                .topics()
+                                .iter()
+                                .map(|t| t.name().to_string())
+                                .collect::<Vec<_>>();
+                            let known = available_topics.load();
+                            for topic in topics.iter().filter(|t| !known.contains(t)) {
+                                info!("Kafka topic {} is now available", topic);
+                            }
+                            available_topics.store(Arc::new(topics));
+
                             if !connection_status.load(Ordering::SeqCst) {
                                 info!("Kafka connection restored");
                                 connection_status.store(true, Ordering::SeqCst);
+                                uptime.record(true);
                                 reconnect_backoff.store(1000, Ordering::SeqCst);
                             }
                         }
@@ -168,6 +211,7 @@ impl KafkaProducer {
                             if connection_status.load(Ordering::SeqCst) {
                                 error!("Kafka connection lost: {}", e);
                                 connection_status.store(false, Ordering::SeqCst);
+                                uptime.record(false);
                             } else {
                                 error!("Kafka still disconnected: {}", e);
                             }
@@ -177,6 +221,7 @@ impl KafkaProducer {
                         if connection_status.load(Ordering::SeqCst) {
                             error!("Failed to create Kafka client for health check: {}", e);
                             connection_status.store(false, Ordering::SeqCst);
+                            uptime.record(false);
                         } else {
                             error!("Still unable to create Kafka client: {}", e);
                         }
@@ -186,30 +231,139 @@ impl KafkaProducer {
         });
     }
 
+    /// Check that the given routing destinations exist, creating missing ones if `auto_create`
+    /// is set
+    ///
+    /// Created topics get the broker's default partition count and replication factor. Topics
+    /// that are still missing are logged and become usable once they are created, as the
+    /// health check refreshes the available topics.
+    pub async fn prepare_topics(&self, topics: &[String], auto_create: bool) {
+        let missing = {
+            let available = self.available_topics.load();
+            topics
+                .iter()
+                .filter(|topic| !available.contains(topic))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if missing.is_empty() {
+            info!("All {} routing topics exist in Kafka", topics.len());
+            return;
+        }
+        if !auto_create {
+            warn!(
+                "Routing topics missing in Kafka, messages for them are dead-lettered until they are created: {}",
+                missing.join(", ")
+            );
+            return;
+        }
+
+        let admin = match ClientConfig::new()
+            .set("bootstrap.servers", &self.bootstrap_servers)
+            .create::<AdminClient<DefaultClientContext>>()
+        {
+            Ok(admin) => admin,
+            Err(e) => {
+                error!("Failed to create Kafka admin client: {}", e);
+                return;
+            }
+        };
+        let new_topics = missing
+            .iter()
+            .map(|topic| NewTopic::new(topic, -1, TopicReplication::Fixed(-1)))
+            .collect::<Vec<_>>();
+        let options = AdminOptions::new().operation_timeout(Some(Duration::from_secs(10)));
+
+        let created = match admin.create_topics(&new_topics, &options).await {
+            Ok(results) => results
+                .into_iter()
+                .filter_map(|result| match result {
+                    Ok(topic) => Some(topic),
+                    Err((topic, e)) => {
+                        error!("Failed to create Kafka topic {}: {}", topic, e);
+                        None
+                    }
+                })
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                error!("Failed to create Kafka topics: {}", e);
+                return;
+            }
+        };
+        if created.is_empty() {
+            return;
+        }
+        info!("Created Kafka topics: {}", created.join(", "));
+
+        // Make the created topics usable right away instead of after the next health check
+        let mut available = self.available_topics.load().as_ref().clone();
+        available.extend(created);
+        self.available_topics.store(Arc::new(available));
+    }
+
+    /// Get the topic sensor data is sent to when a record doesn't name one
+    #[cfg_attr(not(feature = "s3"), allow(dead_code))]
+    pub fn sensor_data_topic(&self) -> &str {
+        &self.sensor_data_topic
+    }
+
     /// Check if Kafka is connected
     pub fn is_connected(&self) -> bool {
         self.connection_status.load(Ordering::Relaxed)
     }
 
+    /// Check if sends are currently being delayed or dropped by `KAFKA_MAX_SEND_RATE`
+    pub fn is_throttled(&self) -> bool {
+        self.throttle
+            .as_ref()
+            .is_some_and(|throttle| throttle.is_active())
+    }
+
+    /// Get the fraction of the last `window` the producer was connected (0.0 - 1.0)
+    pub fn uptime_ratio(&self, window: Duration) -> f64 {
+        self.uptime.uptime_ratio(window)
+    }
+
     /// Internal method to send a message to a Kafka topic
-    async fn send_to_topic(&self, topic: &str, key: &str, payload: &str) -> Result<(), String> {
+    ///
+    /// `timestamp` is the record timestamp in milliseconds since the epoch, the broker sets
+    /// the send time if `None`.
+    async fn send_to_topic(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: &[u8],
+        headers: Option<OwnedHeaders>,
+        timestamp: Option<i64>,
+    ) -> Result<(), String> {
         // Check connection status
         if !self.connection_status.load(Ordering::SeqCst) {
             return Err("Skipped sending to Kafka (known disconnected)".to_string());
         }
 
-        // Check if topic exists
-        if !self.available_topics.contains(&topic.to_string()) {
+        // Check if the destination topic exists, whichever of the configured topics it is
+        if !self.available_topics.load().iter().any(|t| t == topic) {
             return Err(format!(
                 "Skipped sending to Kafka (topic {} not available)",
-                self.sensor_data_topic
+                topic
             ));
         }
 
-        // TODO: Add protobuf serialization
+        // Pace the send if a rate limit is configured
+        if let Some(throttle) = &self.throttle {
+            if !throttle.acquire().await {
+                return Err("Skipped sending to Kafka (send rate limit reached)".to_string());
+            }
+        }
 
         // Create the record
-        let record = FutureRecord::to(topic).key(key).payload(payload);
+        let mut record = FutureRecord::to(topic).key(key).payload(payload);
+        if let Some(headers) = headers {
+            record = record.headers(headers);
+        }
+        if let Some(timestamp) = timestamp {
+            record = record.timestamp(timestamp);
+        }
 
         // Send to Kafka
         match self.producer.send(record, Duration::from_secs(1)).await {
@@ -218,33 +372,486 @@ impl KafkaProducer {
                 // Update connection status on failure
                 if self.connection_status.load(Ordering::SeqCst) {
                     self.connection_status.store(false, Ordering::Relaxed);
-                    return Err(format!("Failed to send to Kafka: {}", e));
+                    self.uptime.record(false);
+                    Err(format!("Failed to send to Kafka: {}", e))
                 } else {
                     debug!("Still unable to send to Kafka topic {}: {}", topic, e);
-                    return Err(format!(
+                    Err(format!(
                         "Skipped sending to Kafka (known disconnected): {}",
                         e
-                    ));
+                    ))
                 }
             }
         }
     }
 
-    /// Send a message to the sensor data topic
-    pub async fn send_sensor_data(&self, data: SensorData) -> Result<(), String> {
-        let payload = serde_json::to_string(&data).unwrap();
-        self.send_to_topic(&self.sensor_data_topic, &self.sensor_data_topic, &payload)
-            .await
+    /// Serialize a `SensorData` record in the given format
+    ///
+    /// `protobuf` records follow `proto/sensor_data.proto`, all other formats are sent as JSON.
+    pub fn serialize(
+        &self,
+        data: &SensorData,
+        format: SerializationFormat,
+    ) -> Result<Vec<u8>, serde_json::Error> {
+        match format {
+            SerializationFormat::Protobuf => Ok(encode_sensor_data(data)),
+            SerializationFormat::Json | SerializationFormat::Raw => serde_json::to_vec(data),
+        }
     }
 
-    /// Send a message to the service metrics topic
-    pub async fn send_service_metrics(&self, data: &[u8]) -> Result<(), String> {
-        let payload = serde_json::to_string(data).unwrap();
+    /// Send a message to its resolved topic, or the sensor data topic
+    pub async fn send_sensor_data(&self, record: &OutputRecord) -> Result<(), String> {
+        // Keyed by the MQTT topic like dead letters, so a sensor's records share a partition
+        let topic = record.topic.as_deref().unwrap_or(&self.sensor_data_topic);
+        self.send_to_topic(
+            topic,
+            &sanitize_topic(&record.mqtt_topic),
+            &record.payload,
+            Some(self.sensor_data_headers(record)),
+            self.record_timestamp(record),
+        )
+        .await
+    }
+
+    /// Build the headers of a sensor data record: its encoding, this instance, and the
+    /// message and correlation IDs if set
+    fn sensor_data_headers(&self, record: &OutputRecord) -> OwnedHeaders {
+        let mut headers = OwnedHeaders::new()
+            .insert(Header {
+                key: "content-type",
+                value: Some(record.content_type()),
+            })
+            .insert(Header {
+                key: "instance-id",
+                value: Some(self.instance_id.as_str()),
+            });
+        if let Some(message_id) = &record.message_id {
+            headers = headers.insert(Header {
+                key: "message-id",
+                value: Some(message_id.as_str()),
+            });
+        }
+        if let Some(correlation_id) = &record.correlation_id {
+            headers = headers.insert(Header {
+                key: "correlation-id",
+                value: Some(correlation_id.as_str()),
+            });
+        }
+        headers
+    }
+
+    /// Get the Kafka timestamp of a sensor data record in milliseconds since the epoch
+    ///
+    /// With `KAFKA_USE_SENSOR_TIMESTAMP` this is the record's measurement time (the receive
+    /// time if the payload had none), otherwise `None` and the broker sets the send time.
+    pub fn record_timestamp(&self, record: &OutputRecord) -> Option<i64> {
+        self.use_sensor_timestamp
+            .then(|| timestamp_millis(record.timestamp))
+    }
+
+    /// Send a serialized metrics record to the service metrics topic
+    pub async fn send_service_metrics(&self, key: &str, payload: &[u8]) -> Result<(), String> {
+        let headers = OwnedHeaders::new().insert(Header {
+            key: "instance-id",
+            value: Some(self.instance_id.as_str()),
+        });
         self.send_to_topic(
             &self.service_metrics_topic,
-            &self.service_metrics_topic,
+            key,
+            payload,
+            Some(headers),
+            None,
+        )
+        .await
+    }
+
+    /// Send an undeliverable message with its original payload to the dead-letter topic
+    ///
+    /// Does nothing if no dead-letter topic is configured.
+    pub async fn send_dead_letter(
+        &self,
+        message: &MqttMessage,
+        reason: DeadLetterReason,
+    ) -> Result<(), String> {
+        let Some(dead_letter_topic) = &self.dead_letter_topic else {
+            return Ok(());
+        };
+
+        // Keyed by the MQTT topic, with control characters escaped
+        self.send_to_topic(
+            dead_letter_topic,
+            &sanitize_topic(&message.topic),
+            &message.payload,
+            Some(self.dead_letter_headers(message, reason)),
+            None,
+        )
+        .await
+    }
+
+    /// Build the headers of a dead-lettered message, describing why and where it was received
+    fn dead_letter_headers(&self, message: &MqttMessage, reason: DeadLetterReason) -> OwnedHeaders {
+        OwnedHeaders::new()
+            .insert(Header {
+                key: "dead-letter-reason",
+                value: Some(reason.as_str()),
+            })
+            .insert(Header {
+                key: "mqtt-topic",
+                value: Some(message.topic.as_str()),
+            })
+            .insert(Header {
+                key: "instance-id",
+                value: Some(self.instance_id.as_str()),
+            })
+    }
+
+    /// Send a compact event describing a processing error to the errors topic
+    ///
+    /// Unlike dead-lettering, the event only carries a sample of the payload and is meant for
+    /// analytics. Does nothing if no errors topic is configured.
+    pub async fn send_error_event(
+        &self,
+        message: &MqttMessage,
+        error: &ProcessingError,
+    ) -> Result<(), String> {
+        let Some(errors_topic) = &self.errors_topic else {
+            return Ok(());
+        };
+
+        let sample_len = message.payload.len().min(ERROR_PAYLOAD_SAMPLE_BYTES);
+        let received_at = chrono::DateTime::<chrono::Utc>::from(message.timestamp);
+        let event = serde_json::json!({
+            "mqtt_topic": message.topic,
+            "reason": error.reason.as_str(),
+            "error": error.message,
+            "payload_size": message.payload.len(),
+            "payload_sample": String::from_utf8_lossy(&message.payload[..sample_len]),
+            "received_at": received_at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+            "instance_id": self.instance_id,
+        });
+        let payload = serde_json::to_vec(&event)
+            .map_err(|e| format!("Failed to serialize error event: {}", e))?;
+
+        let headers = OwnedHeaders::new()
+            .insert(Header {
+                key: "content-type",
+                value: Some("application/json"),
+            })
+            .insert(Header {
+                key: "instance-id",
+                value: Some(self.instance_id.as_str()),
+            });
+
+        self.send_to_topic(
+            errors_topic,
+            &sanitize_topic(&message.topic),
             &payload,
+            Some(headers),
+            None,
         )
         .await
     }
 }
+
+/// Convert a time to milliseconds since the epoch, times before the epoch count as 0
+fn timestamp_millis(time: SystemTime) -> i64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+impl KafkaProducer {
+    /// Create a producer that considers itself connected and the given topics available,
+    /// without contacting the broker or starting the health check
+    pub fn unconnected(config: &KafkaConfig, available_topics: Vec<String>) -> Self {
+        // Without bootstrap servers the client never tries to connect
+        let producer = ClientConfig::new()
+            .create()
+            .expect("Failed to create Kafka producer");
+
+        KafkaProducer {
+            producer,
+            bootstrap_servers: config.broker.clone(),
+            connection_status: Arc::new(AtomicBool::new(true)),
+            uptime: Arc::new(UptimeTracker::new(true)),
+            available_topics: Arc::new(ArcSwap::from_pointee(available_topics)),
+            sensor_data_topic: config.topic_sensor_data.clone(),
+            service_metrics_topic: config.topic_service_metrics.clone(),
+            dead_letter_topic: config.topic_dead_letter.clone(),
+            errors_topic: config.topic_errors.clone(),
+            use_sensor_timestamp: config.use_sensor_timestamp,
+            instance_id: "test-instance".to_string(),
+            throttle: None,
+            health_check_interval: Duration::from_secs(30),
+            reconnect_backoff_ms: Arc::new(std::sync::atomic::AtomicU64::new(1000)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_kafka_configs;
+    use crate::processor::protobuf::SensorDataProto;
+    use bytes::Bytes;
+    use prost::Message;
+    use rdkafka::message::Headers;
+    use rumqttc::QoS;
+    use serde_json::json;
+    use std::time::Instant;
+
+    const DEAD_LETTER_REASONS: [DeadLetterReason; 8] = [
+        DeadLetterReason::InvalidPayload,
+        DeadLetterReason::TooDeep,
+        DeadLetterReason::TransformFailed,
+        DeadLetterReason::ValidationFailed,
+        DeadLetterReason::ValidationUnavailable,
+        DeadLetterReason::SerializationFailed,
+        DeadLetterReason::KafkaFailed,
+        DeadLetterReason::WebhookFailed,
+    ];
+
+    fn mqtt_message(topic: &str, payload: &'static [u8]) -> MqttMessage {
+        MqttMessage {
+            topic: topic.to_string(),
+            payload: Bytes::from_static(payload),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            received_at: Instant::now(),
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    fn sensor_data() -> SensorData {
+        SensorData {
+            sensor_id: "lab/room1/temp".to_string(),
+            message: r#"{"temp":21.5}"#.to_string(),
+            sensor_timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            metadata: json!({"room": "room1", "floor": 2}).as_object().cloned(),
+        }
+    }
+
+    #[test]
+    fn serialize_json_round_trips() {
+        let producer = KafkaProducer::unconnected(&load_kafka_configs(), Vec::new());
+        let data = sensor_data();
+
+        let payload = producer
+            .serialize(&data, SerializationFormat::Json)
+            .unwrap();
+        let decoded: SensorData = serde_json::from_slice(&payload).unwrap();
+
+        assert_eq!(decoded.sensor_id, data.sensor_id);
+        assert_eq!(decoded.message, data.message);
+        assert_eq!(decoded.sensor_timestamp, data.sensor_timestamp);
+        assert_eq!(decoded.metadata, data.metadata);
+    }
+
+    #[test]
+    fn serialize_protobuf_round_trips() {
+        let producer = KafkaProducer::unconnected(&load_kafka_configs(), Vec::new());
+        let data = sensor_data();
+
+        let payload = producer
+            .serialize(&data, SerializationFormat::Protobuf)
+            .unwrap();
+        let decoded = SensorDataProto::decode(payload.as_slice()).unwrap();
+
+        assert_eq!(decoded.sensor_id, data.sensor_id);
+        assert_eq!(decoded.message, data.message);
+        assert_eq!(decoded.sensor_timestamp_ms, 1_700_000_000_123);
+        assert_eq!(decoded.metadata.len(), 2);
+        assert_eq!(decoded.metadata["room"], "room1");
+        assert_eq!(decoded.metadata["floor"], "2");
+        assert_eq!(SensorDataProto::from(&data), decoded);
+    }
+
+    #[test]
+    fn dead_letter_headers_carry_each_reason() {
+        let producer = KafkaProducer::unconnected(&load_kafka_configs(), Vec::new());
+        let message = mqtt_message("lab/room1/temp", b"{\"temp\":");
+
+        for reason in DEAD_LETTER_REASONS {
+            let headers = producer.dead_letter_headers(&message, reason);
+
+            let headers: Vec<(&str, &[u8])> = headers
+                .iter()
+                .map(|header| (header.key, header.value.unwrap()))
+                .collect();
+            assert_eq!(
+                headers,
+                vec![
+                    ("dead-letter-reason", reason.as_str().as_bytes()),
+                    ("mqtt-topic", b"lab/room1/temp".as_slice()),
+                    ("instance-id", b"test-instance".as_slice()),
+                ],
+                "headers for {:?}",
+                reason
+            );
+        }
+    }
+
+    #[test]
+    fn dead_letter_reasons_have_distinct_names() {
+        let names: std::collections::HashSet<&str> =
+            DEAD_LETTER_REASONS.iter().map(|r| r.as_str()).collect();
+        assert_eq!(names.len(), DEAD_LETTER_REASONS.len());
+    }
+
+    #[tokio::test]
+    async fn dead_letter_is_skipped_without_topic() {
+        let mut config = load_kafka_configs();
+        config.topic_dead_letter = None;
+        let producer = KafkaProducer::unconnected(&config, Vec::new());
+        let message = mqtt_message("lab/room1/temp", b"\xff");
+
+        for reason in DEAD_LETTER_REASONS {
+            assert_eq!(producer.send_dead_letter(&message, reason).await, Ok(()));
+        }
+    }
+
+    #[tokio::test]
+    async fn unavailable_topic_error_names_the_topic() {
+        let mut config = load_kafka_configs();
+        config.topic_sensor_data = "sensor-data".to_string();
+        config.topic_service_metrics = "service-metrics".to_string();
+        config.topic_dead_letter = Some("dead-letters".to_string());
+        let producer = KafkaProducer::unconnected(&config, vec!["sensor-data".to_string()]);
+
+        let error = producer
+            .send_service_metrics("instance", b"{}")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "Skipped sending to Kafka (topic service-metrics not available)"
+        );
+
+        let message = mqtt_message("lab/room1/temp", b"\xff");
+        let error = producer
+            .send_dead_letter(&message, DeadLetterReason::InvalidPayload)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "Skipped sending to Kafka (topic dead-letters not available)"
+        );
+
+        let record = OutputRecord {
+            payload: Bytes::from_static(b"{}"),
+            format: SerializationFormat::Json,
+            timestamp: SystemTime::now(),
+            mqtt_topic: message.topic.clone(),
+            topic: Some("large-payloads".to_string()),
+            message_id: None,
+            correlation_id: None,
+            detected_format: None,
+            sensor_data: None,
+        };
+        let error = producer.send_sensor_data(&record).await.unwrap_err();
+        assert_eq!(
+            error,
+            "Skipped sending to Kafka (topic large-payloads not available)"
+        );
+    }
+
+    fn output_record(timestamp: SystemTime) -> OutputRecord {
+        OutputRecord {
+            payload: Bytes::from_static(b"{}"),
+            format: SerializationFormat::Json,
+            timestamp,
+            mqtt_topic: "lab/room1/temp".to_string(),
+            topic: None,
+            message_id: None,
+            correlation_id: None,
+            detected_format: None,
+            sensor_data: None,
+        }
+    }
+
+    #[test]
+    fn record_timestamp_is_the_sensor_time_if_enabled() {
+        let mut config = load_kafka_configs();
+        config.use_sensor_timestamp = true;
+        let producer = KafkaProducer::unconnected(&config, Vec::new());
+        let sensor_time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+
+        assert_eq!(
+            producer.record_timestamp(&output_record(sensor_time)),
+            Some(1_700_000_000_123)
+        );
+    }
+
+    #[test]
+    fn record_timestamp_is_left_to_the_broker_if_disabled() {
+        let mut config = load_kafka_configs();
+        config.use_sensor_timestamp = false;
+        let producer = KafkaProducer::unconnected(&config, Vec::new());
+
+        assert_eq!(
+            producer.record_timestamp(&output_record(SystemTime::now())),
+            None
+        );
+    }
+
+    #[test]
+    fn timestamp_millis_clamps_times_before_the_epoch() {
+        assert_eq!(
+            timestamp_millis(SystemTime::UNIX_EPOCH + Duration::from_micros(1_500)),
+            1
+        );
+        assert_eq!(
+            timestamp_millis(SystemTime::UNIX_EPOCH - Duration::from_secs(1)),
+            0
+        );
+    }
+
+    /// Collect headers as UTF-8 key-value pairs
+    fn header_pairs(headers: &OwnedHeaders) -> Vec<(&str, &str)> {
+        headers
+            .iter()
+            .map(|header| {
+                (
+                    header.key,
+                    std::str::from_utf8(header.value.unwrap()).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sensor_data_headers_carry_the_correlation_id() {
+        let producer = KafkaProducer::unconnected(&load_kafka_configs(), Vec::new());
+        let mut record = output_record(SystemTime::now());
+        record.message_id = Some("db2129".to_string());
+        record.correlation_id = Some("trace-42".to_string());
+
+        let headers = producer.sensor_data_headers(&record);
+
+        assert_eq!(
+            header_pairs(&headers),
+            vec![
+                ("content-type", "application/json"),
+                ("instance-id", "test-instance"),
+                ("message-id", "db2129"),
+                ("correlation-id", "trace-42"),
+            ]
+        );
+    }
+
+    #[test]
+    fn sensor_data_headers_skip_unset_ids() {
+        let producer = KafkaProducer::unconnected(&load_kafka_configs(), Vec::new());
+
+        let headers = producer.sensor_data_headers(&output_record(SystemTime::now()));
+
+        assert_eq!(
+            header_pairs(&headers),
+            vec![
+                ("content-type", "application/json"),
+                ("instance-id", "test-instance"),
+            ]
+        );
+    }
+}
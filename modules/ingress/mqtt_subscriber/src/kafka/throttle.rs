@@ -0,0 +1,82 @@
+//! Token bucket pacing the messages sent to Kafka
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::KafkaThrottleConfig;
+
+/// How long after the last delayed or rejected send the throttle is reported as active
+const ACTIVE_HOLD: Duration = Duration::from_secs(1);
+
+/// Tokens of the bucket, refilled continuously at the configured rate
+struct Bucket {
+    /// Available tokens, negative while waiting senders have reserved future tokens
+    tokens: f64,
+    updated: Instant,
+    /// Last time a send had to wait or was rejected
+    last_throttled: Option<Instant>,
+}
+
+/// Limit on the rate of Kafka sends
+///
+/// The bucket holds up to one second worth of sends, so short bursts below that pass
+/// unpaced. Once it is empty, sends either wait for their token or are rejected.
+pub struct SendThrottle {
+    rate: f64,
+    drop_excess: bool,
+    bucket: Mutex<Bucket>,
+}
+
+impl SendThrottle {
+    /// Create a throttle with a full bucket
+    pub fn new(config: &KafkaThrottleConfig) -> Self {
+        let capacity = config.rate.max(1.0);
+        Self {
+            rate: config.rate,
+            drop_excess: config.drop_excess,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                updated: Instant::now(),
+                last_throttled: None,
+            }),
+        }
+    }
+
+    /// Take a token for a send, waiting for it if the bucket is empty
+    ///
+    /// Returns false if the bucket is empty and excess sends are dropped.
+    pub async fn acquire(&self) -> bool {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refilled = now.duration_since(bucket.updated).as_secs_f64() * self.rate;
+            bucket.tokens = (bucket.tokens + refilled).min(self.rate.max(1.0));
+            bucket.updated = now;
+
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                return true;
+            }
+            bucket.last_throttled = Some(now);
+            if self.drop_excess {
+                return false;
+            }
+
+            // Reserve the next free token, so waiting senders are served in order
+            bucket.tokens -= 1.0;
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        };
+
+        tokio::time::sleep(wait).await;
+        true
+    }
+
+    /// Check if a send was delayed or rejected within the last second
+    pub fn is_active(&self) -> bool {
+        self.bucket
+            .lock()
+            .unwrap()
+            .last_throttled
+            .is_some_and(|last_throttled| last_throttled.elapsed() < ACTIVE_HOLD)
+    }
+}
@@ -0,0 +1,63 @@
+//! Kafka topic names derived from payload fields or the MQTT topic
+
+use serde_json::Value;
+
+/// Topic name template with `{field}` placeholders, e.g. `sensors-{device_type}`
+#[derive(Debug, Clone)]
+pub struct TopicTemplate {
+    template: String,
+}
+
+impl TopicTemplate {
+    /// Create a new template
+    pub fn new(template: String) -> Self {
+        Self { template }
+    }
+
+    /// Fill the placeholders with the payload's top-level fields
+    ///
+    /// Returns `None` if a field is missing or is not a string, number or boolean.
+    pub fn resolve(&self, payload: &Value) -> Option<String> {
+        let mut topic = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}')?;
+            topic.push_str(&rest[..start]);
+
+            match payload.get(&rest[start + 1..end])? {
+                Value::String(value) => topic.push_str(value),
+                Value::Number(value) => topic.push_str(&value.to_string()),
+                Value::Bool(value) => topic.push_str(&value.to_string()),
+                _ => return None,
+            }
+            rest = &rest[end + 1..];
+        }
+        topic.push_str(rest);
+
+        Some(topic)
+    }
+}
+
+/// Mapping of the MQTT topic hierarchy to Kafka topic names, e.g. `lab/room1/temp` to
+/// `sensors.lab.room1.temp` with the prefix `sensors.` and the separator `.`
+#[derive(Debug, Clone)]
+pub struct MqttTopicMapping {
+    prefix: String,
+    separator: String,
+}
+
+impl MqttTopicMapping {
+    /// Create a new mapping
+    pub fn new(prefix: String, separator: String) -> Self {
+        Self { prefix, separator }
+    }
+
+    /// Kafka topic name for an MQTT topic: the prefix followed by the levels of the MQTT
+    /// topic joined with the separator
+    pub fn resolve(&self, mqtt_topic: &str) -> String {
+        let mut topic = self.prefix.clone();
+        topic.push_str(&mqtt_topic.replace('/', &self.separator));
+        topic
+    }
+}
@@ -0,0 +1,90 @@
+//! Logging with a filter that can be reloaded at runtime
+
+use arc_swap::ArcSwap;
+use log::{Level, Log, Metadata, Record};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+/// Name of this instance, added to every log line once the configuration is loaded
+static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+
+/// Number of warnings and errors logged since start
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// `env_logger` wrapper whose filter can be replaced while the service is running
+struct ReloadableLogger {
+    inner: ArcSwap<env_logger::Logger>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.load().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= Level::Warn {
+            WARNINGS.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.load().log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.load().flush();
+    }
+}
+
+/// Build a logger with the filter from `RUST_LOG`
+///
+/// Lines use the `env_logger` default format, with the instance ID after the level once set.
+fn build_logger() -> env_logger::Logger {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            let style = buf.default_level_style(record.level());
+            write!(
+                buf,
+                "[{} {style}{:<5}{style:#} ",
+                buf.timestamp(),
+                record.level()
+            )?;
+            if let Some(instance_id) = INSTANCE_ID.get() {
+                write!(buf, "{} ", instance_id)?;
+            }
+            writeln!(buf, "{}] {}", record.target(), record.args())
+        })
+        .build()
+}
+
+/// Initialize logging with the filter from `RUST_LOG`
+pub fn init() {
+    let logger = build_logger();
+    log::set_max_level(logger.filter());
+
+    let logger = LOGGER.get_or_init(|| ReloadableLogger {
+        inner: ArcSwap::from_pointee(logger),
+    });
+    log::set_logger(logger).expect("Logger already initialized");
+}
+
+/// Replace the log filter with the current value of `RUST_LOG`
+pub fn reload() {
+    let Some(reloadable) = LOGGER.get() else {
+        return;
+    };
+
+    let logger = build_logger();
+    log::set_max_level(logger.filter());
+    reloadable.inner.store(logger.into());
+}
+
+/// Add the instance ID to all further log lines
+pub fn set_instance_id(instance_id: &str) {
+    let _ = INSTANCE_ID.set(instance_id.to_string());
+}
+
+/// Get the number of warnings and errors logged since start
+pub fn warning_count() -> usize {
+    WARNINGS.load(Ordering::Relaxed)
+}
@@ -1,27 +1,47 @@
 //! MQTT Subscriber Service
 
+use arc_swap::ArcSwap;
 use dotenv::dotenv;
-use log::{info, warn};
+use log::{error, info, warn};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 // Import from our modules
 use crate::api::handlers::AppState;
-use crate::api::routes::create_router;
+use crate::api::routes::{create_router, export_openapi};
 use crate::config::load_config;
 use crate::kafka::producer::KafkaProducer;
-use crate::metrics::MessageMetrics;
+use crate::metrics::{
+    start_influxdb_exporter, start_kafka_metrics_publisher, start_statsd_exporter,
+    start_topic_compaction, MessageMetrics, WINDOW_DURATION,
+};
+use crate::mqtt::discovery::start_discovery;
 use crate::mqtt::subscriber::MqttSubscriber;
 use crate::processor::handler::start_message_processor;
+use crate::processor::rules::ProcessingRules;
+use crate::reload::{reload_config_on_sighup, subscribe_topics, RestartSettings};
+use crate::shutdown::disconnect_on_shutdown;
+use crate::sink::create_sinks;
 
 // Import our modules
 mod api;
+mod check;
 mod config;
+mod error;
 mod kafka;
+mod logging;
 mod metrics;
 mod models;
 mod mqtt;
 mod processor;
+mod reload;
+mod shutdown;
+mod sink;
+
+// Use jemalloc to make its allocation statistics available
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 #[tokio::main]
 async fn main() {
@@ -29,24 +49,47 @@ async fn main() {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info");
     }
-    env_logger::init();
+    logging::init();
 
     // Load environment variables
     dotenv().ok();
 
+    // Only validate the configuration if requested, without starting the service
+    if std::env::args().any(|arg| arg == "--check-config") {
+        std::process::exit(if check::check_config() { 0 } else { 1 });
+    }
+
+    // Only export the OpenAPI spec if requested, without starting the service
+    if let Some(path) = openapi_export_path() {
+        match export_openapi(&path) {
+            Ok(_) => info!("Exported OpenAPI spec to {}", path),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     info!("Starting MQTT Subscriber Service");
 
     // Load configurations
     let configs = load_config();
+    logging::set_instance_id(&configs.instance_id);
+    info!("Running as instance {}", configs.instance_id);
+
+    // Topic-based processing rules, swapped on a configuration reload
+    let rules = match ProcessingRules::new(&configs.processor, &configs.kafka) {
+        Ok(rules) => Arc::new(ArcSwap::from_pointee(rules)),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let restart_settings = RestartSettings::from_config(&configs);
 
     // Create and initialize the Kafka producer,
-    let kafka_producer = match KafkaProducer::new(
-        &configs.kafka.broker,
-        &configs.kafka.topic_sensor_data,
-        &configs.kafka.topic_service_metrics,
-    )
-    .await
-    {
+    let kafka_producer = match KafkaProducer::new(&configs.kafka, &configs.instance_id).await {
         Ok(producer) => Arc::new(producer),
         Err(e) => {
             warn!("Failed to create Kafka producer: {}", e);
@@ -54,14 +97,85 @@ async fn main() {
         }
     };
 
+    // Make sure the destinations of dynamic topics exist before messages are routed to them
+    if !configs.kafka.routing_topics.is_empty() {
+        kafka_producer
+            .prepare_topics(
+                &configs.kafka.routing_topics,
+                configs.kafka.auto_create_topics,
+            )
+            .await;
+    }
+
+    // Create the output sinks messages are delivered to
+    let output_sinks = match create_sinks(&configs.sinks, &kafka_producer).await {
+        Ok(output_sinks) => output_sinks,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
     // Create and initialize the metrics
-    let metrics = Arc::new(RwLock::new(MessageMetrics::new()));
+    let metrics = MessageMetrics::new(&configs.metrics);
+    let metrics_snapshot = metrics.snapshot();
+    let lifetime_metrics = metrics.lifetime();
+    let topic_metrics = metrics.topics();
+    let queue_depth = metrics.queue_depth();
+    let load = metrics.load();
+    let metrics = Arc::new(RwLock::new(metrics));
+
+    // Remove long-silent topics from the per-topic metrics if configured, keeping those
+    // with messages in any of the kept windows
+    start_topic_compaction(
+        configs.metrics.topic_compaction_interval,
+        WINDOW_DURATION * configs.metrics.history_windows as u32,
+        Arc::clone(&topic_metrics),
+    );
+
+    // Start pushing metrics to StatsD if configured
+    start_statsd_exporter(configs.statsd, Arc::clone(&metrics_snapshot));
+
+    // Start pushing metrics to InfluxDB if configured
+    start_influxdb_exporter(
+        configs.influxdb,
+        Arc::clone(&metrics_snapshot),
+        Arc::clone(&topic_metrics),
+    );
+
+    // Start publishing metrics to the service metrics topic if configured
+    start_kafka_metrics_publisher(
+        configs.metrics.publish_interval,
+        configs.metrics.publish_per_topic,
+        configs.instance_id.clone(),
+        Arc::clone(&kafka_producer),
+        Arc::clone(&metrics_snapshot),
+        Arc::clone(&topic_metrics),
+    );
 
     // Create and initialize the MQTT subscriber
-    let (subscriber, event_loop) =
-        MqttSubscriber::new(configs.mqtt.mqtt_options, configs.mqtt.mqtt_qos);
+    let (subscriber, event_loop) = MqttSubscriber::new(&configs.mqtt);
     let subscriber = Arc::new(subscriber);
 
+    // Subscribe to the configured topics once the event loop runs, and keep them in sync
+    // with the configuration on SIGHUP
+    let initial_subscriber = Arc::clone(&subscriber);
+    let initial_topics = configs.mqtt.topics.clone();
+    tokio::spawn(async move {
+        subscribe_topics(&initial_subscriber, &initial_topics).await;
+        start_discovery(initial_subscriber);
+    });
+    reload_config_on_sighup(
+        Arc::clone(&subscriber),
+        Arc::clone(&rules),
+        Arc::clone(&topic_metrics),
+        restart_settings,
+        configs.mqtt.topics,
+    );
+
+    // Send a clean MQTT DISCONNECT on SIGTERM and SIGINT
+    disconnect_on_shutdown(Arc::clone(&subscriber));
+
     // Start the message processor in a background task
     let processor_metrics = Arc::clone(&metrics);
     let processor_subscriber = Arc::clone(&subscriber);
@@ -70,12 +184,23 @@ async fn main() {
     // Create application state for API
     let app_state = Arc::new(AppState {
         subscriber: Arc::clone(&subscriber),
-        metrics: Arc::clone(&metrics),
+        metrics: metrics_snapshot,
+        lifetime_metrics,
+        topic_metrics,
+        queue_depth,
+        load,
         kafka_producer: Arc::clone(&kafka_producer),
+        webhook: output_sinks.webhook,
+        debug_endpoints: configs.api.debug_endpoints,
+        instance_id: configs.instance_id.clone(),
     });
 
     // Create API router
-    let app = create_router(app_state);
+    let app = create_router(
+        app_state,
+        configs.api.max_connections,
+        configs.api.metrics_api_key.clone(),
+    );
 
     // Start the HTTP server
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", configs.api.port))
@@ -98,7 +223,24 @@ async fn main() {
         event_loop,
         processor_subscriber,
         processor_kafka,
+        output_sinks.sink,
         processor_metrics,
+        configs.processor,
+        rules,
     )
     .await;
 }
+
+/// Get the OpenAPI export path from `--export-openapi <path>` or `EXPORT_OPENAPI_PATH`
+fn openapi_export_path() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--export-openapi" {
+            return args.next();
+        }
+    }
+
+    std::env::var("EXPORT_OPENAPI_PATH")
+        .ok()
+        .filter(|path| !path.is_empty())
+}
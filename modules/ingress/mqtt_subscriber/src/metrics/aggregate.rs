@@ -0,0 +1,334 @@
+//! Aggregation of metrics over a range of windows
+
+use std::collections::BTreeMap;
+
+use crate::metrics::{ClassCounts, Duration, SystemTime, WindowedMetrics};
+
+/// Aggregates of a range of windows, oldest first
+pub struct WindowAggregate<'a> {
+    windows: Vec<&'a WindowedMetrics>,
+    last_message_time: Option<SystemTime>,
+}
+
+impl<'a> WindowAggregate<'a> {
+    /// Aggregate the given windows, oldest first, with the time of the last message in them
+    pub fn new(windows: Vec<&'a WindowedMetrics>, last_message_time: Option<SystemTime>) -> Self {
+        Self {
+            windows,
+            last_message_time,
+        }
+    }
+
+    /// Get the last message time or None if no messages have been received
+    pub fn last_message_time(&self) -> Option<SystemTime> {
+        self.last_message_time
+    }
+
+    /// Get the total number of messages received across all windows
+    pub fn messages_received(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.messages_received)
+            .sum::<usize>()
+    }
+
+    /// Get the total number of messages processed across all windows
+    pub fn messages_processed(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.messages_processed)
+            .sum::<usize>()
+    }
+
+    /// Get the total number of messages dropped across all windows
+    pub fn messages_dropped(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.messages_dropped)
+            .sum::<usize>()
+    }
+
+    /// Get the total number of processed messages the sinks failed to deliver across all
+    /// windows
+    pub fn messages_delivery_failed(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.messages_delivery_failed)
+            .sum::<usize>()
+    }
+
+    /// Get the total number of processing errors across all windows
+    pub fn processing_errors(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.processing_errors)
+            .sum::<usize>()
+    }
+
+    /// Get the total number of messages dropped by the processing queue across all windows
+    pub fn queue_dropped(&self) -> usize {
+        self.windows.iter().map(|w| w.queue_dropped).sum::<usize>()
+    }
+
+    /// Get the total number of dropped empty-payload messages across all windows
+    pub fn messages_empty(&self) -> usize {
+        self.windows.iter().map(|w| w.messages_empty).sum::<usize>()
+    }
+
+    /// Get the total number of messages exceeding the JSON nesting limit across all windows
+    pub fn messages_too_deep(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.messages_too_deep)
+            .sum::<usize>()
+    }
+
+    /// Get the total number of messages delivered out of order across all windows
+    pub fn messages_late(&self) -> usize {
+        self.windows.iter().map(|w| w.messages_late).sum::<usize>()
+    }
+
+    /// Get the total number of MQTT pings without a response across all windows
+    pub fn ping_timeouts(&self) -> usize {
+        self.windows.iter().map(|w| w.ping_timeouts).sum::<usize>()
+    }
+
+    /// Get the total number of reconnects with a new MQTT client ID across all windows
+    pub fn fresh_clients(&self) -> usize {
+        self.windows.iter().map(|w| w.fresh_clients).sum::<usize>()
+    }
+
+    /// Get the number of messages with a malformed topic name across all windows
+    pub fn messages_bad_topic(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.messages_bad_topic)
+            .sum::<usize>()
+    }
+
+    /// Get the total number of detected MQTT client ID conflicts across all windows
+    pub fn client_id_conflicts(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.client_id_conflicts)
+            .sum::<usize>()
+    }
+
+    /// Get the number of dead-lettered messages across all windows, by reason
+    pub fn dead_lettered_by_reason(&self) -> BTreeMap<String, usize> {
+        let mut by_reason = BTreeMap::new();
+        for window in &self.windows {
+            for (reason, count) in &window.dead_lettered_by_reason {
+                *by_reason.entry(reason.as_str().to_string()).or_insert(0) += count;
+            }
+        }
+        by_reason
+    }
+
+    /// Get the number of messages added to the processing queue across all windows, by lane
+    pub fn queued_by_lane(&self) -> BTreeMap<String, usize> {
+        let mut by_lane = BTreeMap::new();
+        for window in &self.windows {
+            for (lane, count) in &window.queued_by_lane {
+                *by_lane.entry(lane.as_str().to_string()).or_insert(0) += count;
+            }
+        }
+        by_lane
+    }
+
+    /// Get the number of messages received across all windows, by QoS level
+    pub fn received_by_qos(&self) -> BTreeMap<String, usize> {
+        let mut by_qos = BTreeMap::new();
+        for window in &self.windows {
+            for (qos, count) in &window.received_by_qos {
+                *by_qos.entry(qos.to_string()).or_insert(0) += count;
+            }
+        }
+        by_qos
+    }
+
+    /// Get the number of payloads detected across all windows, by format
+    pub fn detected_by_format(&self) -> BTreeMap<String, usize> {
+        let mut by_format = BTreeMap::new();
+        for window in &self.windows {
+            for (format, count) in &window.detected_by_format {
+                *by_format.entry(format.as_str().to_string()).or_insert(0) += count;
+            }
+        }
+        by_format
+    }
+
+    /// Get the number of messages delivered across all windows, by delivery guarantee
+    pub fn delivered_by_guarantee(&self) -> BTreeMap<String, usize> {
+        let mut by_guarantee = BTreeMap::new();
+        for window in &self.windows {
+            for (guarantee, count) in &window.delivered_by_guarantee {
+                *by_guarantee
+                    .entry(guarantee.as_str().to_string())
+                    .or_insert(0) += count;
+            }
+        }
+        by_guarantee
+    }
+
+    /// Get the number of failed messages across all windows, by delivery guarantee
+    pub fn failed_by_guarantee(&self) -> BTreeMap<String, usize> {
+        let mut by_guarantee = BTreeMap::new();
+        for window in &self.windows {
+            for (guarantee, count) in &window.failed_by_guarantee {
+                *by_guarantee
+                    .entry(guarantee.as_str().to_string())
+                    .or_insert(0) += count;
+            }
+        }
+        by_guarantee
+    }
+
+    /// Get the number of completed QoS 2 handshakes across all windows
+    pub fn qos2_completed(&self) -> usize {
+        self.windows.iter().map(|w| w.qos2_completed).sum::<usize>()
+    }
+
+    /// Get the number of delivery retries across all windows
+    pub fn delivery_retries(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.delivery_retries)
+            .sum::<usize>()
+    }
+
+    /// Get the number of messages dropped by the processing queue across all windows, by lane
+    pub fn queue_dropped_by_lane(&self) -> BTreeMap<String, usize> {
+        let mut by_lane = BTreeMap::new();
+        for window in &self.windows {
+            for (lane, count) in &window.queue_dropped_by_lane {
+                *by_lane.entry(lane.as_str().to_string()).or_insert(0) += count;
+            }
+        }
+        by_lane
+    }
+
+    /// Get the message counts across all windows, by topic class
+    pub fn by_class(&self) -> BTreeMap<String, ClassCounts> {
+        let mut by_class: BTreeMap<String, ClassCounts> = BTreeMap::new();
+        for window in &self.windows {
+            for (class, counts) in &window.by_class {
+                let total = by_class.entry(class.as_str().to_string()).or_default();
+                total.messages_received += counts.messages_received;
+                total.messages_dropped += counts.messages_dropped;
+                total.processing_errors += counts.processing_errors;
+            }
+        }
+        by_class
+    }
+
+    /// Get the maximum message size seen in any window
+    pub fn max_message_size(&self) -> usize {
+        self.windows
+            .iter()
+            .map(|w| w.max_message_size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Get the average message size across all windows
+    pub fn average_message_size(&self) -> usize {
+        let total_size = self
+            .windows
+            .iter()
+            .map(|w| w.total_message_size)
+            .sum::<usize>();
+        let total_messages = self.messages_received();
+
+        if total_messages == 0 {
+            return 0;
+        }
+        total_size / total_messages
+    }
+
+    /// Get the maximum processing time seen in any window
+    pub fn max_processing_time(&self) -> Duration {
+        self.windows
+            .iter()
+            .map(|w| w.max_processing_time)
+            .max_by_key(|d| d.as_nanos())
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+
+    /// Get the average processing time across all windows
+    pub fn average_processing_time(&self) -> Duration {
+        let total_time: Duration = self.windows.iter().fold(Duration::from_secs(0), |acc, w| {
+            acc + w.total_processing_time
+        });
+        let total_processed = self.messages_processed();
+
+        if total_processed == 0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_nanos((total_time.as_nanos() / total_processed as u128) as u64)
+        }
+    }
+
+    /// Get the average end-to-end latency across all windows, None without any samples
+    pub fn average_end_to_end_latency(&self) -> Option<Duration> {
+        let total_count = self
+            .windows
+            .iter()
+            .map(|w| w.end_to_end_latency_count)
+            .sum::<usize>();
+        if total_count == 0 {
+            return None;
+        }
+
+        let total_latency: Duration = self.windows.iter().fold(Duration::from_secs(0), |acc, w| {
+            acc + w.total_end_to_end_latency
+        });
+        Some(Duration::from_nanos(
+            (total_latency.as_nanos() / total_count as u128) as u64,
+        ))
+    }
+
+    /// Get the 95th percentile of the sampled end-to-end latencies across all windows, None
+    /// without any samples
+    pub fn p95_end_to_end_latency(&self) -> Option<Duration> {
+        let mut samples: Vec<Duration> = self
+            .windows
+            .iter()
+            .flat_map(|w| w.end_to_end_latency_samples.iter().copied())
+            .collect();
+        if samples.is_empty() {
+            return None;
+        }
+
+        samples.sort_unstable();
+        let index = (samples.len() * 95).div_ceil(100) - 1;
+        Some(samples[index])
+    }
+
+    /// Get the combined throughput across all active windows
+    pub fn throughput(&self) -> f64 {
+        // No data, no throughput
+        if self.windows.is_empty() {
+            return 0.0;
+        }
+
+        // Find total messages
+        let total_messages: usize = self.messages_received();
+
+        // If we have data, calculate based on wall clock time
+        if total_messages > 0 {
+            // Find time range from start of first window to end of last
+            let start_time = self.windows[0].start_time;
+            let end_time = self.windows[self.windows.len() - 1].end_time;
+
+            if let Ok(duration) = end_time.duration_since(start_time) {
+                if duration.as_secs() > 0 {
+                    return total_messages as f64 / duration.as_secs_f64();
+                }
+            }
+        }
+
+        // Default if we can't calculate
+        0.0
+    }
+}
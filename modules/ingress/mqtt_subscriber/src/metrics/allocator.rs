@@ -0,0 +1,34 @@
+//! Allocator statistics of the jemalloc global allocator
+
+use tikv_jemalloc_ctl::{epoch, stats};
+
+/// Byte counts reported by jemalloc
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatorStats {
+    /// Bytes allocated by the application
+    pub allocated: usize,
+    /// Bytes in active pages, a multiple of the page size
+    pub active: usize,
+    /// Bytes in physically resident data pages mapped by the allocator
+    pub resident: usize,
+    /// Bytes in virtual memory mappings kept by the allocator
+    pub mapped: usize,
+    /// Bytes retained for reuse instead of being returned to the operating system
+    pub retained: usize,
+}
+
+/// Read the current allocator statistics
+///
+/// jemalloc caches its statistics, so the epoch is advanced first to refresh them.
+pub fn allocator_stats() -> Result<AllocatorStats, String> {
+    let read_error = |e: tikv_jemalloc_ctl::Error| format!("Failed to read allocator stats: {}", e);
+
+    epoch::advance().map_err(read_error)?;
+    Ok(AllocatorStats {
+        allocated: stats::allocated::read().map_err(read_error)?,
+        active: stats::active::read().map_err(read_error)?,
+        resident: stats::resident::read().map_err(read_error)?,
+        mapped: stats::mapped::read().map_err(read_error)?,
+        retained: stats::retained::read().map_err(read_error)?,
+    })
+}
@@ -0,0 +1,216 @@
+//! Periodic InfluxDB export of the metrics in line protocol
+
+use arc_swap::ArcSwap;
+use log::{debug, error, info};
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::config::InfluxdbConfig;
+use crate::metrics::{MetricsSnapshot, SystemTime, TopicMetrics};
+
+/// Start a background task pushing the metrics to the InfluxDB write endpoint over HTTP
+///
+/// Does nothing if no InfluxDB URL is configured.
+pub fn start_influxdb_exporter(
+    config: InfluxdbConfig,
+    metrics: Arc<ArcSwap<MetricsSnapshot>>,
+    topic_metrics: Arc<TopicMetrics>,
+) {
+    let Some(url) = config.url else {
+        return;
+    };
+
+    info!(
+        "Sending metrics to InfluxDB at {} every {} seconds",
+        url,
+        config.interval.as_secs()
+    );
+
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut interval_timer = tokio::time::interval(config.interval);
+
+        loop {
+            interval_timer.tick().await;
+
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let body = format_metrics(
+                &config.measurement,
+                &config.instance,
+                &metrics.load(),
+                &topic_metrics,
+                timestamp,
+            );
+
+            let mut request = client.post(&url).body(body);
+            if let Some(token) = &config.token {
+                request = request.header("authorization", format!("Token {}", token));
+            }
+
+            // A failed push is only logged, the next one sends the current values again
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Sent metrics to InfluxDB")
+                }
+                Ok(response) => error!(
+                    "InfluxDB at {} rejected the metrics with {}",
+                    url,
+                    response.status()
+                ),
+                Err(e) => error!("Failed to send metrics to InfluxDB at {}: {}", url, e),
+            }
+        }
+    });
+}
+
+/// Format the metrics as InfluxDB line protocol
+///
+/// The first line holds the windowed metrics tagged with the instance, followed by one line
+/// with the cumulative counters of each tracked topic, additionally tagged with the topic.
+fn format_metrics(
+    measurement: &str,
+    instance: &str,
+    metrics: &MetricsSnapshot,
+    topic_metrics: &TopicMetrics,
+    timestamp: u128,
+) -> String {
+    let measurement = escape(measurement, &[',', ' ']);
+    let instance = escape(instance, &[',', '=', ' ']);
+
+    let mut lines = format!(
+        "{},instance={} throughput={},messages_received={}i,messages_processed={}i,messages_dropped={}i,processing_errors={}i {}\n",
+        measurement,
+        instance,
+        metrics.throughput,
+        metrics.messages_received,
+        metrics.messages_processed,
+        metrics.messages_dropped,
+        metrics.processing_errors,
+        timestamp
+    );
+
+    for (topic, counts) in topic_metrics.totals().topics {
+        let _ = writeln!(
+            lines,
+            "{},instance={},topic={} messages_received={}i,messages_dropped={}i,processing_errors={}i {}",
+            measurement,
+            instance,
+            escape(&topic, &[',', '=', ' ']),
+            counts.messages_received,
+            counts.messages_dropped,
+            counts.processing_errors,
+            timestamp
+        );
+    }
+
+    lines
+}
+
+/// Escape the given characters with a backslash, as line protocol requires in names and tags
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_backslashes_the_special_characters() {
+        assert_eq!(escape("lab temp", &[',', ' ']), "lab\\ temp");
+        assert_eq!(escape("a,b=c d", &[',', '=', ' ']), "a\\,b\\=c\\ d");
+        assert_eq!(escape("a=b", &[',', ' ']), "a=b");
+        assert_eq!(escape("C:\\data", &[',', ' ']), "C:\\\\data");
+        assert_eq!(escape("räume/温度", &[',', '=', ' ']), "räume/温度");
+    }
+
+    #[test]
+    fn metrics_line_escapes_measurement_and_instance() {
+        let metrics = MetricsSnapshot {
+            throughput: 2.5,
+            messages_received: 150,
+            messages_processed: 148,
+            messages_dropped: 1,
+            processing_errors: 1,
+            ..MetricsSnapshot::default()
+        };
+
+        let lines = format_metrics(
+            "mqtt subscriber,eu=1",
+            "node 1,rack=a",
+            &metrics,
+            &TopicMetrics::new(10),
+            1_700_000_000_000_000_000,
+        );
+
+        assert_eq!(
+            lines,
+            "mqtt\\ subscriber\\,eu=1,instance=node\\ 1\\,rack\\=a throughput=2.5,messages_received=150i,messages_processed=148i,messages_dropped=1i,processing_errors=1i 1700000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn topic_lines_escape_the_topic_tag() {
+        let topic_metrics = TopicMetrics::new(10);
+        let now = SystemTime::now();
+        topic_metrics.record_message_received("lab/room 1/temp,c", now);
+        topic_metrics.record_message_received("lab/room 1/temp,c", now);
+        topic_metrics.record_message_dropped("lab/room 1/temp,c");
+        topic_metrics.record_message_received("lab/x=y", now);
+        topic_metrics.record_processing_error("lab/x=y");
+
+        let lines = format_metrics(
+            "mqtt_subscriber",
+            "node-1",
+            &MetricsSnapshot::default(),
+            &topic_metrics,
+            42,
+        );
+        let lines: Vec<&str> = lines.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            "mqtt_subscriber,instance=node-1,topic=lab/room\\ 1/temp\\,c messages_received=2i,messages_dropped=1i,processing_errors=0i 42"
+        );
+        assert_eq!(
+            lines[2],
+            "mqtt_subscriber,instance=node-1,topic=lab/x\\=y messages_received=1i,messages_dropped=0i,processing_errors=1i 42"
+        );
+    }
+
+    #[test]
+    fn lines_have_measurement_tags_fields_and_timestamp() {
+        let topic_metrics = TopicMetrics::new(10);
+        topic_metrics.record_message_received("lab/temp", SystemTime::now());
+
+        let lines = format_metrics(
+            "mqtt_subscriber",
+            "node-1",
+            &MetricsSnapshot::default(),
+            &topic_metrics,
+            42,
+        );
+
+        for line in lines.lines() {
+            // Unescaped spaces separate the tag set, the field set and the timestamp
+            let parts: Vec<&str> = line.split(' ').collect();
+            assert_eq!(parts.len(), 3, "{}", line);
+            assert!(parts[0].starts_with("mqtt_subscriber,instance=node-1"));
+            assert!(parts[1].split(',').all(|field| field
+                .split_once('=')
+                .is_some_and(|(k, v)| !k.is_empty() && !v.is_empty())));
+            assert_eq!(parts[2], "42");
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Periodic export of the metrics to the Kafka service metrics topic
+
+use arc_swap::ArcSwap;
+use log::{debug, error, info};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::kafka::producer::KafkaProducer;
+use crate::metrics::{Duration, MetricsSnapshot, SystemTime, TopicCounts, TopicMetrics};
+use crate::mqtt::topic::sanitize_topic;
+
+/// Which records are published to the service metrics topic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerTopicPublish {
+    /// Only the aggregate metrics of the instance
+    Off,
+    /// The aggregate metrics and one record per active topic
+    Both,
+    /// Only one record per active topic
+    Only,
+}
+
+impl PerTopicPublish {
+    /// Parse a `METRICS_PUBLISH_PER_TOPIC` value
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "false" => Some(PerTopicPublish::Off),
+            "true" => Some(PerTopicPublish::Both),
+            "only" => Some(PerTopicPublish::Only),
+            _ => None,
+        }
+    }
+}
+
+/// Start a background task publishing the metrics to the service metrics topic
+///
+/// Does nothing without a publish interval. The aggregate record holds the windowed metrics
+/// and is keyed by the instance ID. Per-topic records hold the counts of a topic since the
+/// previous publish and are keyed by the topic, topics without messages in the interval are
+/// skipped.
+pub fn start_kafka_metrics_publisher(
+    interval: Option<Duration>,
+    per_topic: PerTopicPublish,
+    instance_id: String,
+    kafka_producer: Arc<KafkaProducer>,
+    metrics: Arc<ArcSwap<MetricsSnapshot>>,
+    topic_metrics: Arc<TopicMetrics>,
+) {
+    let Some(interval) = interval else {
+        return;
+    };
+
+    info!(
+        "Publishing metrics to the service metrics topic every {} seconds{}",
+        interval.as_secs(),
+        match per_topic {
+            PerTopicPublish::Off => "",
+            PerTopicPublish::Both => " (with per-topic records)",
+            PerTopicPublish::Only => " (per-topic records only)",
+        }
+    );
+
+    tokio::spawn(async move {
+        let mut interval_timer = tokio::time::interval(interval);
+        // Counts at the previous publish, to send each topic's counts of the interval
+        let mut previous: BTreeMap<String, TopicCounts> = BTreeMap::new();
+
+        loop {
+            interval_timer.tick().await;
+
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+
+            if per_topic != PerTopicPublish::Only {
+                let snapshot = metrics.load();
+                let record = json!({
+                    "instance_id": instance_id,
+                    "timestamp": timestamp,
+                    "window_time_sec": snapshot.window_time_sec,
+                    "throughput": snapshot.throughput,
+                    "messages_received": snapshot.messages_received,
+                    "messages_processed": snapshot.messages_processed,
+                    "messages_dropped": snapshot.messages_dropped,
+                    "processing_errors": snapshot.processing_errors,
+                });
+                publish(&kafka_producer, &instance_id, &record).await;
+            }
+
+            if per_topic != PerTopicPublish::Off {
+                let totals = topic_metrics.totals().topics;
+                let mut published = 0;
+                for (topic, counts) in &totals {
+                    // A topic removed from the per-topic metrics since starts counting from 0
+                    let last = previous
+                        .get(topic)
+                        .filter(|last| last.messages_received <= counts.messages_received)
+                        .cloned()
+                        .unwrap_or_default();
+                    let received = counts
+                        .messages_received
+                        .saturating_sub(last.messages_received);
+                    if received == 0 {
+                        continue;
+                    }
+                    let dropped = counts
+                        .messages_dropped
+                        .saturating_sub(last.messages_dropped);
+                    let errors = counts
+                        .processing_errors
+                        .saturating_sub(last.processing_errors);
+                    let record = json!({
+                        "instance_id": instance_id,
+                        "timestamp": timestamp,
+                        "interval_sec": interval.as_secs(),
+                        "topic": topic,
+                        "messages_received": received,
+                        "messages_dropped": dropped,
+                        "processing_errors": errors,
+                    });
+                    // The other topics would most likely fail the same way
+                    if !publish(&kafka_producer, &sanitize_topic(topic), &record).await {
+                        break;
+                    }
+                    published += 1;
+                }
+                debug!("Published metrics of {} active topics", published);
+                previous = totals;
+            }
+        }
+    });
+}
+
+/// Send a metrics record, a failed send is only logged and the next interval sends new values
+async fn publish(kafka_producer: &KafkaProducer, key: &str, record: &serde_json::Value) -> bool {
+    match kafka_producer
+        .send_service_metrics(key, record.to_string().as_bytes())
+        .await
+    {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to publish metrics: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Cumulative metrics since process start (or the last reset)
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::metrics::{Duration, SystemTime};
+
+/// All-time message counters, kept in atomics so they can be read without the metrics lock
+#[derive(Debug)]
+pub struct LifetimeMetrics {
+    since: Mutex<SystemTime>,
+    messages_received: AtomicU64,
+    messages_processed: AtomicU64,
+    messages_dropped: AtomicU64,
+    messages_delivery_failed: AtomicU64,
+    processing_errors: AtomicU64,
+    bytes_received: AtomicU64,
+    // Receive time of the last message in milliseconds since the epoch, 0 before the first
+    last_message_ms: AtomicU64,
+}
+
+/// Point-in-time copy of the lifetime counters
+#[derive(Debug, Clone)]
+pub struct LifetimeTotals {
+    pub since: SystemTime,
+    pub messages_received: u64,
+    pub messages_processed: u64,
+    pub messages_dropped: u64,
+    pub messages_delivery_failed: u64,
+    pub processing_errors: u64,
+    pub bytes_received: u64,
+}
+
+impl LifetimeMetrics {
+    /// Create zeroed counters starting now
+    pub fn new() -> Self {
+        Self {
+            since: Mutex::new(SystemTime::now()),
+            messages_received: AtomicU64::new(0),
+            messages_processed: AtomicU64::new(0),
+            messages_dropped: AtomicU64::new(0),
+            messages_delivery_failed: AtomicU64::new(0),
+            processing_errors: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            last_message_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a new message received at `timestamp`
+    pub fn record_message_received(&self, size: usize, timestamp: SystemTime) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(size as u64, Ordering::Relaxed);
+        let millis = timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.last_message_ms.fetch_max(millis, Ordering::Relaxed);
+    }
+
+    /// Get the receive time of the last message, updated with every message unlike the
+    /// windowed metrics
+    pub fn last_message_time(&self) -> Option<SystemTime> {
+        match self.last_message_ms.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(SystemTime::UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+
+    /// Record a message as processed
+    pub fn record_message_processed(&self) {
+        self.messages_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a message as dropped
+    pub fn record_message_dropped(&self) {
+        self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a processed message the sinks failed to deliver
+    pub fn record_delivery_failed(&self) {
+        self.messages_delivery_failed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a processing error
+    pub fn record_processing_error(&self) {
+        self.processing_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the current totals
+    pub fn totals(&self) -> LifetimeTotals {
+        LifetimeTotals {
+            since: *self.since.lock().unwrap(),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_processed: self.messages_processed.load(Ordering::Relaxed),
+            messages_dropped: self.messages_dropped.load(Ordering::Relaxed),
+            messages_delivery_failed: self.messages_delivery_failed.load(Ordering::Relaxed),
+            processing_errors: self.processing_errors.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reset all counters to zero, starting a new period now
+    ///
+    /// The last message time is kept, as it is not a counter.
+    pub fn reset(&self) {
+        *self.since.lock().unwrap() = SystemTime::now();
+        self.messages_received.store(0, Ordering::Relaxed);
+        self.messages_processed.store(0, Ordering::Relaxed);
+        self.messages_dropped.store(0, Ordering::Relaxed);
+        self.messages_delivery_failed.store(0, Ordering::Relaxed);
+        self.processing_errors.store(0, Ordering::Relaxed);
+        self.bytes_received.store(0, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,128 @@
+//! In-flight and throughput gauges and the scaling hint derived from them
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::MetricsConfig;
+
+/// Normalized load with the inputs it was calculated from
+#[derive(Debug, Clone, Copy)]
+pub struct ScalingHint {
+    /// Highest ratio of an input to its target, above 1.0 when saturated
+    pub load: f64,
+    pub throughput: f64,
+    pub queue_depth: usize,
+    pub in_flight: usize,
+}
+
+/// Marks a message as in flight until dropped
+pub struct InFlightGuard<'a> {
+    in_flight: &'a AtomicUsize,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Number of messages being processed and the recent message rate
+///
+/// The throughput is the rate of received messages between the last two samples, so unlike
+/// the windowed metrics it reflects the current load within a sample interval.
+#[derive(Debug)]
+pub struct LoadGauges {
+    in_flight: AtomicUsize,
+    received: AtomicU64,
+    // Messages per second as `f64` bits
+    throughput: AtomicU64,
+    // Time and received count of the last sample
+    last_sample: Mutex<(Instant, u64)>,
+    target_throughput: f64,
+    target_queue_depth: usize,
+    target_in_flight: usize,
+}
+
+impl LoadGauges {
+    /// Create the gauges with the scaling targets of the configuration
+    pub fn new(config: &MetricsConfig) -> Self {
+        Self {
+            in_flight: AtomicUsize::new(0),
+            received: AtomicU64::new(0),
+            throughput: AtomicU64::new(0f64.to_bits()),
+            last_sample: Mutex::new((Instant::now(), 0)),
+            target_throughput: config.scaling_target_throughput,
+            target_queue_depth: config.scaling_target_queue_depth,
+            target_in_flight: config.scaling_target_in_flight,
+        }
+    }
+
+    /// Count a received message as in flight until the returned guard is dropped
+    pub fn start_message(&self) -> InFlightGuard<'_> {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard {
+            in_flight: &self.in_flight,
+        }
+    }
+
+    /// Update the throughput from the messages received since the last sample
+    pub fn sample(&self) {
+        let received = self.received.load(Ordering::Relaxed);
+        let mut last_sample = self.last_sample.lock().unwrap();
+        let elapsed = last_sample.0.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            let throughput = received.saturating_sub(last_sample.1) as f64 / elapsed;
+            self.throughput
+                .store(throughput.to_bits(), Ordering::Relaxed);
+        }
+        *last_sample = (Instant::now(), received);
+    }
+
+    /// Get the number of messages currently being processed
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Get the message rate between the last two samples
+    pub fn throughput(&self) -> f64 {
+        f64::from_bits(self.throughput.load(Ordering::Relaxed))
+    }
+
+    /// Get the scaling targets (throughput, queue depth, in-flight), 0 for disabled inputs
+    pub fn targets(&self) -> (f64, usize, usize) {
+        (
+            self.target_throughput,
+            self.target_queue_depth,
+            self.target_in_flight,
+        )
+    }
+
+    /// Calculate the scaling hint for the current load and the given queue depth
+    ///
+    /// The load is the highest ratio of throughput, queue depth and in-flight count to their
+    /// targets, ignoring inputs whose target is 0.
+    pub fn scaling_hint(&self, queue_depth: usize) -> ScalingHint {
+        let throughput = self.throughput();
+        let in_flight = self.in_flight();
+
+        let ratios = [
+            (throughput, self.target_throughput),
+            (queue_depth as f64, self.target_queue_depth as f64),
+            (in_flight as f64, self.target_in_flight as f64),
+        ];
+        let load = ratios
+            .iter()
+            .filter(|(_, target)| *target > 0.0)
+            .map(|(value, target)| value / target)
+            .fold(0.0, f64::max);
+
+        ScalingHint {
+            load,
+            throughput,
+            queue_depth,
+            in_flight,
+        }
+    }
+}
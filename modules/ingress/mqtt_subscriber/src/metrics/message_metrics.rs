@@ -1,41 +1,179 @@
 //! Main metrics aggregation and calculation
 
+use arc_swap::ArcSwap;
+use rumqttc::QoS;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use crate::config::MetricsConfig;
+use crate::metrics::aggregate::WindowAggregate;
 use crate::metrics::ring_buffer::RingBuffer;
-use crate::metrics::{Duration, SystemTime, WindowedMetrics, NUM_WINDOWS, WINDOW_DURATION};
+use crate::metrics::{
+    Duration, LifetimeMetrics, LoadGauges, MetricsSnapshot, QueueDepth, SystemTime, TopicMetrics,
+    WindowedMetrics, WINDOW_DURATION,
+};
+use crate::models::{DeadLetterReason, DeliveryGuarantee, PayloadFormat, TopicClass};
+use crate::processor::queue::Lane;
 
 /// Message processing metrics with sliding windows
 ///
-/// IMPORTANT: Only the last completed one-minute window is included in metrics.
-/// The current window (up to one minute of the most recent data) is excluded.
+/// IMPORTANT: Only the last `METRICS_WINDOWS` completed one-minute windows are included in
+/// metrics. The current window (up to one minute of the most recent data) is excluded.
 ///
 /// This approach provides stable metrics by using only complete windows,
 /// at the tradeoff of not including the very latest data (max 1 minute lag).
 ///
-/// Historical metrics can be implemented in the future by increasing NUM_WINDOWS.
+/// Until the first window completes, the metrics are taken from the current window instead,
+/// unless `METRICS_WARMUP_CURRENT_WINDOW` is disabled.
+///
+/// With `METRICS_SAMPLE_RATE` above 1, only sampled messages are recorded in the windows,
+/// through `weighted`, so their counts stand for the unsampled messages as well.
 #[derive(Debug, Clone)]
 pub struct MessageMetrics {
     current_window: WindowedMetrics, // Current window being accumulated
-    windows: RingBuffer<WindowedMetrics>, // Historical windows (ring buffer, oldest first)
+    windows: RingBuffer<Arc<WindowedMetrics>>, // Historical windows (ring buffer, oldest first)
+    // Longer series of completed windows for backfilling, without their latency samples
+    history: RingBuffer<Arc<WindowedMetrics>>,
 
     // Time window in seconds
     pub window_time_sec: u64,
     // Last message time
     pub last_message_time: Option<SystemTime>,
+    // Round trip time of the last answered MQTT ping
+    pub last_ping_latency: Option<Duration>,
+    // Snapshot of the completed windows for lock-free readers
+    snapshot: Arc<ArcSwap<MetricsSnapshot>>,
+    // Cumulative counters since start or the last reset
+    lifetime: Arc<LifetimeMetrics>,
+    // Cumulative counters per topic, with bounded cardinality
+    topics: Arc<TopicMetrics>,
+    // Depth of the processing queue
+    queue_depth: Arc<QueueDepth>,
+    // Messages in flight and the recent message rate
+    load: Arc<LoadGauges>,
+    // Whether the metrics fall back to the current window until the first one completes
+    warmup_current_window: bool,
+    // Time the snapshot was last refreshed from the current window during warm-up
+    warmup_captured_at: Option<SystemTime>,
+    // One in this many messages is recorded in the windows
+    sample_rate: usize,
+    // Number of messages each window count currently stands for, see `weighted`
+    weight: usize,
 }
 
 impl MessageMetrics {
     /// Create a new metrics instance
-    pub fn new() -> Self {
-        Self {
+    pub fn new(config: &MetricsConfig) -> Self {
+        let num_windows = config.windows.max(1);
+        let mut metrics = Self {
             current_window: WindowedMetrics::new(SystemTime::now()),
-            windows: RingBuffer::new(NUM_WINDOWS),
-            window_time_sec: WINDOW_DURATION.as_secs() * NUM_WINDOWS as u64,
+            windows: RingBuffer::new(num_windows),
+            history: RingBuffer::new(config.history_windows.max(num_windows)),
+            window_time_sec: WINDOW_DURATION.as_secs() * num_windows as u64,
             last_message_time: None,
-        }
+            last_ping_latency: None,
+            snapshot: Arc::default(),
+            lifetime: Arc::new(LifetimeMetrics::new()),
+            topics: Arc::new(TopicMetrics::new(config.max_topics)),
+            queue_depth: Arc::new(QueueDepth::new(
+                config.queue_high_watermark,
+                config.queue_high_watermark_duration,
+            )),
+            load: Arc::new(LoadGauges::new(config)),
+            warmup_current_window: config.warmup_current_window,
+            warmup_captured_at: None,
+            sample_rate: config.sample_rate.max(1),
+            weight: 1,
+        };
+        metrics.snapshot = Arc::new(ArcSwap::from_pointee(MetricsSnapshot::capture(&metrics)));
+        metrics
+    }
+
+    /// Get a handle to the snapshot of the completed windows, updated on every rotation
+    pub fn snapshot(&self) -> Arc<ArcSwap<MetricsSnapshot>> {
+        Arc::clone(&self.snapshot)
+    }
+
+    /// Get the rate of the messages recorded in the windows, one in `sample_rate`
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// Record the metrics of a sampled message, each window count standing for `weight`
+    /// messages
+    ///
+    /// Only the message counts of the current window are weighted. Sizes, processing times
+    /// and latencies are recorded once, and the lifetime and per-topic counters, which every
+    /// message updates, count the message once as well.
+    pub fn weighted(&mut self, weight: usize) -> WeightedMetrics<'_> {
+        self.weight = weight.max(1);
+        WeightedMetrics { metrics: self }
+    }
+
+    /// Get the completed windows, oldest first
+    pub fn window_list(&self) -> Vec<Arc<WindowedMetrics>> {
+        self.windows.iter().cloned().collect()
+    }
+
+    /// Get the completed windows kept for `METRICS_HISTORY_WINDOWS`, oldest first
+    ///
+    /// The windows carry their counts, but not their end-to-end latency samples.
+    pub fn history_list(&self) -> Vec<Arc<WindowedMetrics>> {
+        self.history.iter().cloned().collect()
+    }
+
+    /// Get a handle to the lifetime counters, readable without the metrics lock
+    pub fn lifetime(&self) -> Arc<LifetimeMetrics> {
+        Arc::clone(&self.lifetime)
+    }
+
+    /// Get a handle to the per-topic counters, readable without the metrics lock
+    pub fn topics(&self) -> Arc<TopicMetrics> {
+        Arc::clone(&self.topics)
+    }
+
+    /// Get a handle to the processing queue depth gauge, readable without the metrics lock
+    pub fn queue_depth(&self) -> Arc<QueueDepth> {
+        Arc::clone(&self.queue_depth)
+    }
+
+    /// Get a handle to the in-flight and throughput gauges, readable without the metrics lock
+    pub fn load(&self) -> Arc<LoadGauges> {
+        Arc::clone(&self.load)
     }
 
     /// Record a new message received
+    ///
+    /// `timestamp` is the clock the windows rotate on, see `advance`.
     pub fn record_message_received(&mut self, size: usize, timestamp: SystemTime) {
+        self.lifetime.record_message_received(size, timestamp);
+        self.advance(timestamp);
+
+        // Update the current window
+        self.current_window
+            .record_message_received(size, timestamp, self.weight);
+        self.refresh_warmup(timestamp);
+    }
+
+    /// Record the arrival of a message not sampled for the window counts
+    ///
+    /// Counts nothing, but keeps the last message time, the window rotation and the warm-up
+    /// snapshot as current as without sampling.
+    pub fn record_message_seen(&mut self, timestamp: SystemTime) {
+        self.advance(timestamp);
+        self.current_window.record_message_seen(timestamp);
+        self.refresh_warmup(timestamp);
+    }
+
+    /// Update the last message time and rotate the windows if the current one is complete
+    ///
+    /// This is the only place windows rotate, and `timestamp` is the clock they rotate on:
+    /// - The current window is completed by the first message at least `WINDOW_DURATION`
+    ///   after its start, so a quiet period leaves it open (and excluded) until the next message.
+    /// - The new window starts at that message's timestamp, not at the end of the previous
+    ///   window, so no empty windows are added for the quiet period in between.
+    /// - Timestamps before the current window's start (clock going backwards) never rotate.
+    fn advance(&mut self, timestamp: SystemTime) {
         // Update global timestamp tracking
         self.last_message_time = Some(timestamp);
 
@@ -43,149 +181,592 @@ impl MessageMetrics {
         if let Ok(elapsed) = timestamp.duration_since(self.current_window.start_time) {
             if elapsed >= WINDOW_DURATION {
                 // Rotate to a new window
-                let completed_window =
+                let mut completed_window =
                     std::mem::replace(&mut self.current_window, WindowedMetrics::new(timestamp));
-                self.windows.push(completed_window);
+
+                // The history only reports counts, so it doesn't keep the latency samples
+                let samples = std::mem::take(&mut completed_window.end_to_end_latency_samples);
+                self.history.push(Arc::new(completed_window.clone()));
+                completed_window.end_to_end_latency_samples = samples;
+                self.windows.push(Arc::new(completed_window));
+
+                // Publish the new completed windows to the readers
+                self.snapshot
+                    .store(Arc::new(MetricsSnapshot::capture(self)));
             }
         }
+    }
 
-        // Update the current window
-        self.current_window.record_message_received(size, timestamp);
+    /// Until the first window completes, let the readers see the current window, refreshed at
+    /// most once per second
+    fn refresh_warmup(&mut self, timestamp: SystemTime) {
+        if self.warmup_current_window && self.windows.is_empty() {
+            let stale = self.warmup_captured_at.is_none_or(|captured_at| {
+                timestamp
+                    .duration_since(captured_at)
+                    .map_or(true, |age| age >= Duration::from_secs(1))
+            });
+            if stale {
+                self.warmup_captured_at = Some(timestamp);
+                self.snapshot
+                    .store(Arc::new(MetricsSnapshot::capture(self)));
+            }
+        }
     }
 
     /// Record a message as processed
     pub fn record_message_processed(&mut self, processing_time: Duration) {
+        self.lifetime.record_message_processed();
         self.current_window
-            .record_message_processed(processing_time);
+            .record_message_processed(processing_time, self.weight);
     }
 
     /// Record a message as dropped
     pub fn record_message_dropped(&mut self) {
-        self.current_window.record_message_dropped();
+        self.lifetime.record_message_dropped();
+        self.current_window.record_message_dropped(self.weight);
+    }
+
+    /// Record a processed message the sinks failed to deliver
+    pub fn record_delivery_failed(&mut self) {
+        self.lifetime.record_delivery_failed();
+        self.current_window.record_delivery_failed(self.weight);
     }
 
     /// Record a processing error
     pub fn record_processing_error(&mut self) {
-        self.current_window.record_processing_error();
+        self.lifetime.record_processing_error();
+        self.current_window.record_processing_error(self.weight);
+    }
+
+    /// Record a message added to the processing queue
+    pub fn record_message_queued(&mut self, lane: Lane) {
+        self.current_window.record_message_queued(lane, self.weight);
+    }
+
+    /// Record a message dropped by the processing queue
+    pub fn record_queue_drop(&mut self, lane: Lane) {
+        self.current_window.record_queue_drop(lane, self.weight);
+    }
+
+    /// Record a message received with the given QoS
+    pub fn record_qos_received(&mut self, qos: QoS) {
+        self.current_window
+            .record_qos_received(qos as u8, self.weight);
+    }
+
+    /// Record the detected format of a payload
+    pub fn record_format_detected(&mut self, format: PayloadFormat) {
+        self.current_window
+            .record_format_detected(format, self.weight);
+    }
+
+    /// Record a completed QoS 2 handshake
+    pub fn record_qos2_completed(&mut self) {
+        self.current_window.record_qos2_completed();
+    }
+
+    /// Record a delivered message of a topic with the given delivery guarantee
+    pub fn record_guarantee_delivered(&mut self, guarantee: DeliveryGuarantee) {
+        self.current_window
+            .record_guarantee_delivered(guarantee, self.weight);
+    }
+
+    /// Record a failed message of a topic with the given delivery guarantee
+    pub fn record_guarantee_failed(&mut self, guarantee: DeliveryGuarantee) {
+        self.current_window
+            .record_guarantee_failed(guarantee, self.weight);
+    }
+
+    /// Record a retried delivery
+    pub fn record_delivery_retry(&mut self) {
+        self.current_window.record_delivery_retry(self.weight);
+    }
+
+    /// Record a dropped message with an empty payload
+    pub fn record_message_empty(&mut self) {
+        self.current_window.record_message_empty(self.weight);
+    }
+
+    /// Record a message rejected for exceeding the JSON nesting limit
+    pub fn record_message_too_deep(&mut self) {
+        self.current_window.record_message_too_deep(self.weight);
+    }
+
+    /// Record a message that arrived too late to be delivered in timestamp order
+    pub fn record_message_late(&mut self) {
+        self.current_window.record_message_late(self.weight);
+    }
+
+    /// Record the round trip time of an answered MQTT ping
+    pub fn record_ping_latency(&mut self, latency: Duration) {
+        self.last_ping_latency = Some(latency);
+    }
+
+    /// Record an MQTT ping without a response
+    pub fn record_ping_timeout(&mut self) {
+        self.current_window.record_ping_timeout();
+    }
+
+    /// Record a reconnect with a new MQTT client ID
+    pub fn record_fresh_client(&mut self) {
+        self.current_window.record_fresh_client();
+    }
+
+    /// Record a detected MQTT client ID conflict
+    pub fn record_client_id_conflict(&mut self) {
+        self.current_window.record_client_id_conflict();
+    }
+
+    /// Record a message with a malformed topic name
+    pub fn record_bad_topic(&mut self) {
+        self.current_window.record_bad_topic();
+    }
+
+    /// Record a message as dead-lettered
+    pub fn record_message_dead_lettered(&mut self, reason: DeadLetterReason) {
+        self.current_window
+            .record_message_dead_lettered(reason, self.weight);
+    }
+
+    /// Record the time from a message's measurement to its delivery
+    pub fn record_end_to_end_latency(&mut self, latency: Duration) {
+        self.current_window.record_end_to_end_latency(latency);
+    }
+
+    /// Record a message received on the given topic
+    pub fn record_topic_received(&mut self, topic: &str, timestamp: SystemTime) {
+        self.topics.record_message_received(topic, timestamp);
+    }
+
+    /// Record a dropped message on the given topic
+    pub fn record_topic_dropped(&mut self, topic: &str) {
+        self.topics.record_message_dropped(topic);
+    }
+
+    /// Record a processing error on the given topic
+    pub fn record_topic_error(&mut self, topic: &str) {
+        self.topics.record_processing_error(topic);
+    }
+
+    /// Record a message received on a topic of the given class
+    pub fn record_class_received(&mut self, class: TopicClass) {
+        self.current_window
+            .record_class_received(class, self.weight);
+    }
+
+    /// Record a dropped message on a topic of the given class
+    pub fn record_class_dropped(&mut self, class: TopicClass) {
+        self.current_window.record_class_dropped(class, self.weight);
+    }
+
+    /// Record a processing error on a topic of the given class
+    pub fn record_class_error(&mut self, class: TopicClass) {
+        self.current_window.record_class_error(class, self.weight);
     }
 
     // Combined metrics access methods
 
-    /// Get the last message time or None if no messages have been received
-    pub fn window_last_message_time(&self) -> Option<SystemTime> {
-        if self.windows.is_empty() {
-            self.last_message_time
-        } else {
-            Some(self.windows.get(self.windows.len() - 1).unwrap().end_time)
-        }
+    /// Check if no window has completed yet, so the window metrics are empty or, with
+    /// `METRICS_WARMUP_CURRENT_WINDOW`, taken from the current window
+    pub fn is_warming_up(&self) -> bool {
+        self.windows.is_empty()
     }
 
-    /// Get the total number of messages received across all windows
-    pub fn window_messages_received(&self) -> usize {
-        self.windows
-            .iter()
-            .map(|w| w.messages_received)
-            .sum::<usize>()
+    /// Get the windows the metrics are aggregated from: the completed windows, or the
+    /// current window if none completed yet and the warm-up fallback is enabled
+    fn aggregated_windows(&self) -> impl Iterator<Item = &WindowedMetrics> {
+        let warmup =
+            (self.warmup_current_window && self.windows.is_empty()).then_some(&self.current_window);
+        self.windows.iter().map(Arc::as_ref).chain(warmup)
     }
 
-    /// Get the total number of messages processed across all windows
-    pub fn window_messages_processed(&self) -> usize {
-        self.windows
-            .iter()
-            .map(|w| w.messages_processed)
-            .sum::<usize>()
+    /// Get the aggregates of the windows the metrics are reported from
+    pub fn aggregate(&self) -> WindowAggregate<'_> {
+        // The last completed window ends with its last message, the current window is still
+        // open and may have received more since
+        let last_message_time = match self.windows.len() {
+            0 => self.last_message_time,
+            len => self.windows.get(len - 1).map(|window| window.end_time),
+        };
+        WindowAggregate::new(self.aggregated_windows().collect(), last_message_time)
+    }
+}
+
+/// Metrics recording a sampled message, see `MessageMetrics::weighted`
+pub struct WeightedMetrics<'a> {
+    metrics: &'a mut MessageMetrics,
+}
+
+impl Deref for WeightedMetrics<'_> {
+    type Target = MessageMetrics;
+
+    fn deref(&self) -> &MessageMetrics {
+        self.metrics
     }
+}
 
-    /// Get the total number of messages dropped across all windows
-    pub fn window_messages_dropped(&self) -> usize {
-        self.windows
-            .iter()
-            .map(|w| w.messages_dropped)
-            .sum::<usize>()
+impl DerefMut for WeightedMetrics<'_> {
+    fn deref_mut(&mut self) -> &mut MessageMetrics {
+        self.metrics
     }
+}
 
-    /// Get the total number of processing errors across all windows
-    pub fn window_processing_errors(&self) -> usize {
-        self.windows
-            .iter()
-            .map(|w| w.processing_errors)
-            .sum::<usize>()
+impl Drop for WeightedMetrics<'_> {
+    fn drop(&mut self) {
+        // Count anything recorded afterwards once again
+        self.metrics.weight = 1;
     }
+}
 
-    /// Get the maximum message size seen in any window
-    pub fn window_max_message_size(&self) -> usize {
-        self.windows
-            .iter()
-            .map(|w| w.max_message_size)
-            .max()
-            .unwrap_or(0)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_metrics_configs;
+
+    /// Create metrics aggregating `windows` windows, with the start of the first window
+    fn metrics(windows: usize, warmup_current_window: bool) -> (MessageMetrics, SystemTime) {
+        let mut config = load_metrics_configs();
+        config.windows = windows;
+        config.history_windows = windows;
+        config.warmup_current_window = warmup_current_window;
+        config.sample_rate = 1;
+        let metrics = MessageMetrics::new(&config);
+        let start = metrics.current_window.start_time;
+        (metrics, start)
     }
 
-    /// Get the average message size across all windows
-    pub fn window_average_message_size(&self) -> usize {
-        let total_size = self
-            .windows
-            .iter()
-            .map(|w| w.total_message_size)
-            .sum::<usize>();
-        let total_messages = self.window_messages_received();
+    /// Create metrics aggregating `windows` windows and keeping `history` windows
+    fn metrics_with_history(windows: usize, history: usize) -> (MessageMetrics, SystemTime) {
+        let mut config = load_metrics_configs();
+        config.windows = windows;
+        config.history_windows = history;
+        config.sample_rate = 1;
+        let metrics = MessageMetrics::new(&config);
+        let start = metrics.current_window.start_time;
+        (metrics, start)
+    }
 
-        if total_messages == 0 {
-            return 0;
+    /// Record one message per minute, completing a window with every message after the first
+    fn record_minutes(metrics: &mut MessageMetrics, start: SystemTime, minutes: u64) {
+        for minute in 0..minutes {
+            metrics.record_message_received(10, at(start, minute * 60_000));
         }
-        total_size / total_messages
     }
 
-    /// Get the maximum processing time seen in any window
-    pub fn window_max_processing_time(&self) -> Duration {
-        self.windows
-            .iter()
-            .map(|w| w.max_processing_time)
-            .max_by_key(|d| d.as_nanos())
-            .unwrap_or_else(|| Duration::from_secs(0))
+    fn start_times(windows: &[Arc<WindowedMetrics>]) -> Vec<SystemTime> {
+        windows.iter().map(|window| window.start_time).collect()
     }
 
-    /// Get the average processing time across all windows
-    pub fn window_average_processing_time(&self) -> Duration {
-        let total_time: Duration = self.windows.iter().fold(Duration::from_secs(0), |acc, w| {
-            acc + w.total_processing_time
-        });
-        let total_processed = self.window_messages_processed();
+    fn at(start: SystemTime, millis: u64) -> SystemTime {
+        start + Duration::from_millis(millis)
+    }
 
-        if total_processed == 0 {
-            Duration::from_secs(0)
-        } else {
-            Duration::from_nanos((total_time.as_nanos() / total_processed as u128) as u64)
-        }
+    #[test]
+    fn window_rotates_on_first_message_after_duration() {
+        let (mut metrics, start) = metrics(3, false);
+
+        metrics.record_message_received(10, start);
+        metrics.record_message_received(10, at(start, 59_999));
+        assert!(metrics.is_warming_up());
+
+        metrics.record_message_received(10, at(start, 60_000));
+        assert!(!metrics.is_warming_up());
+        assert_eq!(metrics.windows.len(), 1);
+        assert_eq!(metrics.aggregate().messages_received(), 2);
+        assert_eq!(metrics.current_window.start_time, at(start, 60_000));
     }
 
-    /// Get the combined throughput across all active windows
-    pub fn window_throughput(&self) -> f64 {
-        // No data, no throughput
-        if self.windows.iter().next().is_none() {
-            return 0.0;
+    #[test]
+    fn new_window_starts_at_rotating_message() {
+        let (mut metrics, start) = metrics(3, false);
+
+        metrics.record_message_received(10, at(start, 70_000));
+        // A full window after the rotating message, not after the end of the previous window
+        metrics.record_message_received(10, at(start, 129_999));
+        assert_eq!(metrics.windows.len(), 1);
+
+        metrics.record_message_received(10, at(start, 130_000));
+        assert_eq!(metrics.windows.len(), 2);
+    }
+
+    #[test]
+    fn current_window_is_excluded_from_aggregates() {
+        let (mut metrics, start) = metrics(3, false);
+
+        metrics.record_message_received(100, start);
+        metrics.record_message_received(100, at(start, 60_000));
+        for millis in [61_000, 62_000, 63_000] {
+            metrics.record_message_received(500, at(start, millis));
         }
 
-        // Get all completed windows
-        let windows: Vec<&WindowedMetrics> = self.windows.iter().collect();
+        let aggregate = metrics.aggregate();
+        assert_eq!(aggregate.messages_received(), 1);
+        assert_eq!(aggregate.max_message_size(), 100);
+        assert_eq!(aggregate.last_message_time(), Some(start));
+        assert_eq!(metrics.snapshot().load().messages_received, 1);
+        assert_eq!(metrics.current_window.messages_received, 4);
+    }
+
+    #[test]
+    fn quiet_period_adds_no_empty_windows() {
+        let (mut metrics, start) = metrics(3, false);
+
+        metrics.record_message_received(10, start);
+        // Five windows without any message
+        metrics.record_message_received(10, at(start, 300_000));
 
-        // Find total messages
-        let total_messages: usize = self.window_messages_received();
+        assert_eq!(metrics.windows.len(), 1);
+        assert_eq!(metrics.aggregate().messages_received(), 1);
+        assert_eq!(metrics.current_window.start_time, at(start, 300_000));
+    }
+
+    #[test]
+    fn window_stays_open_until_next_message() {
+        let (mut metrics, start) = metrics(3, false);
+
+        metrics.record_message_received(10, start);
+        // Later processing outcomes don't rotate the window, only received messages do
+        metrics.record_message_processed(Duration::from_millis(5));
+        metrics.record_message_dropped();
+
+        assert!(metrics.is_warming_up());
+        assert_eq!(metrics.aggregate().messages_received(), 0);
+    }
 
-        // If we have data, calculate based on wall clock time
-        if total_messages > 0 {
-            // Find time range from start of first window to end of last
-            let start_time = windows[0].start_time;
-            let end_time = windows[windows.len() - 1].end_time;
+    #[test]
+    fn clock_going_backwards_never_rotates() {
+        let (mut metrics, start) = metrics(3, false);
 
-            if let Ok(duration) = end_time.duration_since(start_time) {
-                if duration.as_secs() > 0 {
-                    return total_messages as f64 / duration.as_secs_f64();
-                }
+        metrics.record_message_received(10, start);
+        metrics.record_message_received(10, start - Duration::from_secs(3600));
+
+        assert!(metrics.is_warming_up());
+        assert_eq!(metrics.current_window.messages_received, 2);
+    }
+
+    #[test]
+    fn only_the_configured_windows_are_aggregated() {
+        let (mut metrics, start) = metrics(2, false);
+
+        // Windows of 1, 2 and 3 messages, the last rotating message opens a fourth
+        let mut millis = 0;
+        for count in 1..=3 {
+            for _ in 0..count {
+                metrics.record_message_received(10, at(start, millis));
             }
+            millis += 60_000;
         }
+        metrics.record_message_received(10, at(start, millis));
+
+        assert_eq!(metrics.windows.len(), 2);
+        assert_eq!(metrics.aggregate().messages_received(), 5);
+    }
+
+    #[test]
+    fn warmup_reports_current_window_until_first_rotation() {
+        let (mut metrics, start) = metrics(3, true);
+
+        metrics.record_message_received(100, start);
+        metrics.record_message_received(300, at(start, 2_000));
+
+        let aggregate = metrics.aggregate();
+        assert!(metrics.is_warming_up());
+        assert_eq!(aggregate.messages_received(), 2);
+        assert_eq!(aggregate.average_message_size(), 200);
+        assert_eq!(aggregate.throughput(), 1.0);
+        assert_eq!(aggregate.last_message_time(), Some(at(start, 2_000)));
+
+        let snapshot = metrics.snapshot().load_full();
+        assert!(snapshot.warming_up);
+        assert_eq!(snapshot.messages_received, 2);
+        assert_eq!(snapshot.last_message_time, Some(at(start, 2_000)));
+    }
+
+    #[test]
+    fn warmup_disabled_reports_zeros_until_first_rotation() {
+        let (mut metrics, start) = metrics(3, false);
+
+        metrics.record_message_received(100, start);
+        metrics.record_message_received(300, at(start, 2_000));
+
+        let aggregate = metrics.aggregate();
+        assert_eq!(aggregate.messages_received(), 0);
+        assert_eq!(aggregate.throughput(), 0.0);
+        // The last message time is still known without any completed window
+        assert_eq!(aggregate.last_message_time(), Some(at(start, 2_000)));
+        assert_eq!(metrics.snapshot().load().messages_received, 0);
+    }
+
+    #[test]
+    fn warmup_ends_with_first_completed_window() {
+        let (mut metrics, start) = metrics(3, true);
+
+        metrics.record_message_received(10, start);
+        metrics.record_message_received(10, at(start, 60_000));
+        metrics.record_message_received(10, at(start, 61_000));
+
+        assert!(!metrics.is_warming_up());
+        assert_eq!(metrics.aggregate().messages_received(), 1);
+        let snapshot = metrics.snapshot().load_full();
+        assert!(!snapshot.warming_up);
+        assert_eq!(snapshot.messages_received, 1);
+    }
+
+    #[test]
+    fn warmup_snapshot_refreshes_at_most_once_per_second() {
+        let (mut metrics, start) = metrics(3, true);
+
+        metrics.record_message_received(10, start);
+        metrics.record_message_received(10, at(start, 500));
+        assert_eq!(metrics.snapshot().load().messages_received, 1);
+
+        metrics.record_message_received(10, at(start, 1_000));
+        assert_eq!(metrics.snapshot().load().messages_received, 3);
+    }
+
+    #[test]
+    fn unsampled_messages_advance_windows_without_counting() {
+        let (mut metrics, start) = metrics(3, true);
+
+        metrics.weighted(4).record_message_received(10, start);
+        metrics.record_message_seen(at(start, 1_500));
+        assert_eq!(metrics.aggregate().messages_received(), 4);
+        assert_eq!(
+            metrics.snapshot().load().last_message_time,
+            Some(at(start, 1_500))
+        );
+
+        metrics.record_message_seen(at(start, 60_000));
+        assert!(!metrics.is_warming_up());
+        assert_eq!(metrics.aggregate().messages_received(), 4);
+        assert_eq!(metrics.last_message_time, Some(at(start, 60_000)));
+        assert_eq!(metrics.current_window.messages_received, 0);
+    }
+
+    #[test]
+    fn sampled_queue_drops_are_weighted() {
+        let (mut metrics, start) = metrics(3, false);
+
+        metrics.record_message_received(10, start);
+        metrics.record_queue_drop(Lane::High);
+        metrics.weighted(4).record_queue_drop(Lane::Normal);
+
+        assert_eq!(metrics.current_window.queue_dropped, 5);
+        assert_eq!(metrics.current_window.queue_dropped_by_lane[&Lane::High], 1);
+        assert_eq!(
+            metrics.current_window.queue_dropped_by_lane[&Lane::Normal],
+            4
+        );
+    }
+
+    #[test]
+    fn dead_letters_are_counted_per_reason() {
+        let (mut metrics, start) = metrics(3, false);
+
+        metrics.record_message_received(10, start);
+        metrics.record_message_dead_lettered(DeadLetterReason::InvalidPayload);
+        metrics.record_message_dead_lettered(DeadLetterReason::TooDeep);
+        metrics.record_message_dead_lettered(DeadLetterReason::TooDeep);
+        metrics
+            .weighted(5)
+            .record_message_dead_lettered(DeadLetterReason::KafkaFailed);
+        metrics.record_message_received(10, at(start, 60_000));
+
+        let by_reason = metrics.aggregate().dead_lettered_by_reason();
+        assert_eq!(
+            by_reason.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("invalid_payload".to_string(), 1),
+                ("kafka_failed".to_string(), 5),
+                ("too_deep".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn history_keeps_more_windows_than_aggregated() {
+        let (mut metrics, start) = metrics_with_history(3, 5);
+
+        record_minutes(&mut metrics, start, 6);
+
+        assert_eq!(metrics.window_list().len(), 3);
+        assert_eq!(
+            start_times(&metrics.history_list()),
+            (0..5)
+                .map(|minute| at(start, minute * 60_000))
+                .collect::<Vec<_>>()
+        );
+        let snapshot = metrics.snapshot().load_full();
+        assert_eq!(snapshot.windows.len(), 3);
+        assert_eq!(snapshot.history.len(), 5);
+        assert_eq!(snapshot.messages_received, 3);
+    }
+
+    #[test]
+    fn history_evicts_oldest_window_past_capacity() {
+        let (mut metrics, start) = metrics_with_history(2, 4);
+
+        record_minutes(&mut metrics, start, 8);
+
+        // Windows 0 to 6 completed, the oldest three were evicted
+        assert_eq!(
+            start_times(&metrics.history_list()),
+            (3..7)
+                .map(|minute| at(start, minute * 60_000))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            start_times(&metrics.window_list()),
+            (5..7)
+                .map(|minute| at(start, minute * 60_000))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn history_is_at_least_as_long_as_the_aggregated_windows() {
+        let (mut metrics, start) = metrics_with_history(4, 1);
+
+        record_minutes(&mut metrics, start, 6);
+
+        assert_eq!(metrics.history_list().len(), 4);
+    }
+
+    #[test]
+    fn history_drops_latency_samples_but_keeps_counts() {
+        let (mut metrics, start) = metrics_with_history(2, 4);
+
+        metrics.record_message_received(10, start);
+        metrics.record_end_to_end_latency(Duration::from_millis(20));
+        metrics.record_end_to_end_latency(Duration::from_millis(40));
+        metrics.record_message_received(10, at(start, 60_000));
+
+        let history = metrics.history_list();
+        assert_eq!(history[0].end_to_end_latency_count, 2);
+        assert_eq!(
+            history[0].total_end_to_end_latency,
+            Duration::from_millis(60)
+        );
+        assert!(history[0].end_to_end_latency_samples.is_empty());
+        // The aggregated windows keep them for the percentiles
+        assert_eq!(metrics.window_list()[0].end_to_end_latency_samples.len(), 2);
+        assert_eq!(
+            metrics.aggregate().p95_end_to_end_latency(),
+            Some(Duration::from_millis(40))
+        );
+    }
+
+    #[test]
+    fn snapshots_share_the_completed_windows() {
+        let (mut metrics, start) = metrics_with_history(3, 5);
+
+        record_minutes(&mut metrics, start, 3);
+        let first = metrics.snapshot().load_full();
+        metrics.record_message_received(10, at(start, 180_000));
+        let second = metrics.snapshot().load_full();
 
-        // Default if we can't calculate
-        0.0
+        assert!(Arc::ptr_eq(&first.windows[0], &second.windows[0]));
+        assert!(Arc::ptr_eq(&first.history[1], &second.history[1]));
     }
 }
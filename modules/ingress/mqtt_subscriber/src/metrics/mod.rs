@@ -3,20 +3,42 @@
 //! This module contains all the functionality for tracking, calculating,
 //! and reporting performance metrics for the MQTT subscriber service.
 
+mod aggregate;
+#[cfg(feature = "jemalloc")]
+mod allocator;
+mod influxdb;
+mod kafka_export;
+mod lifetime;
+mod load;
 mod message_metrics;
+mod queue_depth;
 mod ring_buffer;
+mod sampler;
+mod snapshot;
+mod statsd;
+mod topics;
+mod uptime;
 mod windowed;
 
 // Re-export the main types
+#[cfg(feature = "jemalloc")]
+pub use allocator::allocator_stats;
+pub use influxdb::start_influxdb_exporter;
+pub use kafka_export::{start_kafka_metrics_publisher, PerTopicPublish};
+pub use lifetime::LifetimeMetrics;
+pub use load::LoadGauges;
 pub use message_metrics::MessageMetrics;
-pub use windowed::WindowedMetrics;
+pub use queue_depth::QueueDepth;
+pub use sampler::MetricsSampler;
+pub use snapshot::MetricsSnapshot;
+pub use statsd::start_statsd_exporter;
+pub use topics::{start_topic_compaction, TopicCounts, TopicMetrics};
+pub use uptime::UptimeTracker;
+pub use windowed::{ClassCounts, WindowedMetrics};
 
 // Constants used across the metrics module
 /// The time window duration for each metrics bucket (1 minute)
 pub const WINDOW_DURATION: Duration = Duration::from_secs(60);
 
-/// Number of windows to maintain (1 minute total)
-pub const NUM_WINDOWS: usize = 1;
-
 // Re-export std::time for convenience
 pub use std::time::{Duration, SystemTime};
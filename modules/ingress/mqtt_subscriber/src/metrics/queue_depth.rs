@@ -0,0 +1,57 @@
+//! Processing queue depth gauge with a sustained high watermark
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::metrics::Duration;
+
+/// Current depth of the processing queue, sampled by the processor
+///
+/// The queue counts as backlogged once its depth stayed above `high_watermark` for at least
+/// `sustain`, so short bursts the workers catch up with do not flap the readiness.
+#[derive(Debug)]
+pub struct QueueDepth {
+    depth: AtomicUsize,
+    high_watermark: usize,
+    sustain: Duration,
+    // Start of the current period above the high watermark
+    above_since: Mutex<Option<Instant>>,
+}
+
+impl QueueDepth {
+    /// Create a gauge with the given high watermark (0 to disable the backlog check)
+    pub fn new(high_watermark: usize, sustain: Duration) -> Self {
+        Self {
+            depth: AtomicUsize::new(0),
+            high_watermark,
+            sustain,
+            above_since: Mutex::new(None),
+        }
+    }
+
+    /// Record a sample of the queue depth
+    pub fn record(&self, depth: usize) {
+        self.depth.store(depth, Ordering::Relaxed);
+
+        let mut above_since = self.above_since.lock().unwrap();
+        if self.high_watermark > 0 && depth > self.high_watermark {
+            above_since.get_or_insert_with(Instant::now);
+        } else {
+            *above_since = None;
+        }
+    }
+
+    /// Get the last sampled queue depth
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Check whether the depth stayed above the high watermark for the sustain period
+    pub fn is_backlogged(&self) -> bool {
+        self.above_since
+            .lock()
+            .unwrap()
+            .is_some_and(|since| since.elapsed() >= self.sustain)
+    }
+}
@@ -0,0 +1,41 @@
+//! Sampling of the messages recorded in the windowed metrics
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Picks one in every `rate` messages to be recorded in the windowed metrics
+///
+/// Messages are picked by arrival order rather than at random, which is cheaper and exact
+/// over a window, but the per-class and per-QoS counts are skewed if devices publish in a
+/// strict rotation whose length shares a factor with the rate.
+#[derive(Debug)]
+pub struct MetricsSampler {
+    rate: usize,
+    counter: AtomicUsize,
+}
+
+impl MetricsSampler {
+    /// Create a sampler picking one in every `rate` messages, every message for 0 or 1
+    pub fn new(rate: usize) -> Self {
+        Self {
+            rate: rate.max(1),
+            counter: AtomicUsize::new(0),
+        }
+    }
+
+    /// Decide whether to record the next message
+    ///
+    /// Returns the number of messages it stands for in the window counts, `None` if it is not
+    /// sampled.
+    pub fn sample(&self) -> Option<usize> {
+        if self.rate == 1
+            || self
+                .counter
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.rate)
+        {
+            Some(self.rate)
+        } else {
+            None
+        }
+    }
+}
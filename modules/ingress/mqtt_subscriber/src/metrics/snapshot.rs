@@ -0,0 +1,140 @@
+//! Lock-free snapshot of the completed metrics windows
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::metrics::aggregate::WindowAggregate;
+use crate::metrics::{
+    ClassCounts, Duration, MessageMetrics, SystemTime, WindowedMetrics, WINDOW_DURATION,
+};
+
+/// Metrics of the completed windows, published on every window rotation
+///
+/// Until the first window completes, it is also refreshed from the current window as messages
+/// arrive, unless `METRICS_WARMUP_CURRENT_WINDOW` is disabled.
+///
+/// Readers load the latest snapshot from an `ArcSwap` without taking the metrics lock, so
+/// reading never contends with the message processing hot path.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    pub window_time_sec: u64,
+    pub warming_up: bool,
+    pub sample_rate: usize,
+    pub messages_received: usize,
+    pub messages_processed: usize,
+    pub messages_dropped: usize,
+    pub messages_delivery_failed: usize,
+    pub processing_errors: usize,
+    pub queue_dropped: usize,
+    pub queued_by_lane: BTreeMap<String, usize>,
+    pub queue_dropped_by_lane: BTreeMap<String, usize>,
+    pub received_by_qos: BTreeMap<String, usize>,
+    pub qos2_completed: usize,
+    pub detected_by_format: BTreeMap<String, usize>,
+    pub delivered_by_guarantee: BTreeMap<String, usize>,
+    pub failed_by_guarantee: BTreeMap<String, usize>,
+    pub delivery_retries: usize,
+    pub messages_empty: usize,
+    pub messages_too_deep: usize,
+    pub messages_late: usize,
+    pub messages_bad_topic: usize,
+    pub dead_lettered_by_reason: BTreeMap<String, usize>,
+    pub by_class: BTreeMap<String, ClassCounts>,
+    pub throughput: f64,
+    pub average_message_size: usize,
+    pub max_message_size: usize,
+    pub average_processing_time: Duration,
+    pub max_processing_time: Duration,
+    pub average_end_to_end_latency: Option<Duration>,
+    pub p95_end_to_end_latency: Option<Duration>,
+    pub last_message_time: Option<SystemTime>,
+    pub last_ping_latency: Option<Duration>,
+    pub ping_timeouts: usize,
+    pub fresh_clients: usize,
+    pub client_id_conflicts: usize,
+    /// The completed windows the aggregates are calculated from, oldest first, shared with
+    /// the metrics and the later snapshots
+    pub windows: Vec<Arc<WindowedMetrics>>,
+    /// The longer series of completed windows kept for backfilling, oldest first, without
+    /// their latency samples
+    pub history: Vec<Arc<WindowedMetrics>>,
+}
+
+impl MetricsSnapshot {
+    /// Capture the completed windows of the given metrics
+    pub fn capture(metrics: &MessageMetrics) -> Self {
+        Self {
+            window_time_sec: metrics.window_time_sec,
+            warming_up: metrics.is_warming_up(),
+            sample_rate: metrics.sample_rate(),
+            last_ping_latency: metrics.last_ping_latency,
+            windows: metrics.window_list(),
+            history: metrics.history_list(),
+            ..Self::aggregate(&metrics.aggregate())
+        }
+    }
+
+    /// Get the snapshot of the `count` most recent windows, or of all windows if fewer
+    /// completed so far
+    ///
+    /// Shorter ranges are aggregated from the stored windows on each call, which keeps the
+    /// rotation under the metrics lock independent of the number of windows.
+    pub fn for_windows(&self, count: usize) -> Cow<'_, MetricsSnapshot> {
+        let count = count.max(1);
+        if count >= self.windows.len() {
+            return Cow::Borrowed(self);
+        }
+
+        let windows = &self.windows[self.windows.len() - count..];
+        let last_message_time = windows.last().map(|window| window.end_time);
+        Cow::Owned(Self {
+            window_time_sec: WINDOW_DURATION.as_secs() * count as u64,
+            warming_up: self.warming_up,
+            sample_rate: self.sample_rate,
+            last_ping_latency: self.last_ping_latency,
+            ..Self::aggregate(&WindowAggregate::new(
+                windows.iter().map(Arc::as_ref).collect(),
+                last_message_time,
+            ))
+        })
+    }
+
+    /// Capture the aggregates of a range of windows, without the windows themselves
+    fn aggregate(aggregate: &WindowAggregate) -> Self {
+        Self {
+            messages_received: aggregate.messages_received(),
+            messages_processed: aggregate.messages_processed(),
+            messages_dropped: aggregate.messages_dropped(),
+            messages_delivery_failed: aggregate.messages_delivery_failed(),
+            processing_errors: aggregate.processing_errors(),
+            queue_dropped: aggregate.queue_dropped(),
+            queued_by_lane: aggregate.queued_by_lane(),
+            queue_dropped_by_lane: aggregate.queue_dropped_by_lane(),
+            received_by_qos: aggregate.received_by_qos(),
+            qos2_completed: aggregate.qos2_completed(),
+            detected_by_format: aggregate.detected_by_format(),
+            delivered_by_guarantee: aggregate.delivered_by_guarantee(),
+            failed_by_guarantee: aggregate.failed_by_guarantee(),
+            delivery_retries: aggregate.delivery_retries(),
+            messages_empty: aggregate.messages_empty(),
+            messages_too_deep: aggregate.messages_too_deep(),
+            messages_late: aggregate.messages_late(),
+            messages_bad_topic: aggregate.messages_bad_topic(),
+            dead_lettered_by_reason: aggregate.dead_lettered_by_reason(),
+            by_class: aggregate.by_class(),
+            throughput: aggregate.throughput(),
+            average_message_size: aggregate.average_message_size(),
+            max_message_size: aggregate.max_message_size(),
+            average_processing_time: aggregate.average_processing_time(),
+            max_processing_time: aggregate.max_processing_time(),
+            average_end_to_end_latency: aggregate.average_end_to_end_latency(),
+            p95_end_to_end_latency: aggregate.p95_end_to_end_latency(),
+            last_message_time: aggregate.last_message_time(),
+            ping_timeouts: aggregate.ping_timeouts(),
+            fresh_clients: aggregate.fresh_clients(),
+            client_id_conflicts: aggregate.client_id_conflicts(),
+            ..Self::default()
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Periodic StatsD export of the windowed metrics
+
+use arc_swap::ArcSwap;
+use log::{debug, error, info};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+use crate::config::StatsdConfig;
+use crate::metrics::MetricsSnapshot;
+
+/// Start a background task pushing the metrics to a StatsD endpoint over UDP
+///
+/// Does nothing if no StatsD address is configured.
+pub fn start_statsd_exporter(config: StatsdConfig, metrics: Arc<ArcSwap<MetricsSnapshot>>) {
+    let Some(addr) = config.addr else {
+        return;
+    };
+
+    info!(
+        "Sending metrics to StatsD at {} every {} seconds",
+        addr,
+        config.interval.as_secs()
+    );
+
+    tokio::spawn(async move {
+        let socket = match UdpSocket::bind("0.0.0.0:0").await {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Failed to create StatsD socket: {}", e);
+                return;
+            }
+        };
+
+        let mut interval_timer = tokio::time::interval(config.interval);
+
+        loop {
+            interval_timer.tick().await;
+
+            let packet = format_metrics(&config.prefix, &metrics.load());
+
+            // UDP is fire-and-forget, so a failed send is only logged
+            match socket.send_to(packet.as_bytes(), &addr).await {
+                Ok(_) => debug!("Sent metrics to StatsD"),
+                Err(e) => error!("Failed to send metrics to StatsD at {}: {}", addr, e),
+            }
+        }
+    });
+}
+
+/// Format the windowed metrics as StatsD gauges, one per line
+fn format_metrics(prefix: &str, metrics: &MetricsSnapshot) -> String {
+    let gauges = [
+        ("throughput", metrics.throughput),
+        ("messages_received", metrics.messages_received as f64),
+        ("messages_processed", metrics.messages_processed as f64),
+        ("messages_dropped", metrics.messages_dropped as f64),
+        ("processing_errors", metrics.processing_errors as f64),
+    ];
+
+    gauges
+        .iter()
+        .map(|(name, value)| format!("{}.{}:{}|g", prefix, name, value))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,236 @@
+//! Per-topic message counters with bounded cardinality
+
+use log::{debug, info};
+use rumqttc::matches;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::metrics::{Duration, SystemTime};
+
+/// Name of the bucket collecting the counts of evicted topics
+pub const OTHER_TOPICS: &str = "__other__";
+
+/// Message counts of a single topic
+#[derive(Debug, Clone, Default)]
+pub struct TopicCounts {
+    pub messages_received: u64,
+    pub messages_dropped: u64,
+    pub processing_errors: u64,
+    pub last_message_time: Option<SystemTime>,
+}
+
+impl TopicCounts {
+    /// Add another topic's counts to these
+    fn merge(&mut self, other: &TopicCounts) {
+        self.messages_received += other.messages_received;
+        self.messages_dropped += other.messages_dropped;
+        self.processing_errors += other.processing_errors;
+        self.last_message_time = self.last_message_time.max(other.last_message_time);
+    }
+}
+
+/// Point-in-time copy of the per-topic counters
+#[derive(Debug, Clone)]
+pub struct TopicTotals {
+    /// Counts by topic, including the `__other__` bucket once a topic was evicted
+    pub topics: BTreeMap<String, TopicCounts>,
+    /// Number of evictions into the `__other__` bucket
+    pub other_topics: u64,
+    /// Maximum number of individually tracked topics
+    pub max_topics: usize,
+}
+
+/// A tracked topic with the time of its last use
+#[derive(Debug)]
+struct TopicEntry {
+    counts: TopicCounts,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct TopicState {
+    topics: HashMap<String, TopicEntry>,
+    /// Tracked topics by their last use, oldest first
+    lru: BTreeMap<u64, String>,
+    /// Monotonic use counter ordering the LRU
+    tick: u64,
+    other: TopicCounts,
+    other_topics: u64,
+}
+
+/// Cumulative counters per concrete topic since start
+///
+/// At most `max_topics` topics are tracked individually. Once the limit is reached, the least
+/// recently used topic is evicted to make room for a new one and its counts are added to the
+/// `__other__` bucket, so memory stays bounded with any number of topics.
+#[derive(Debug)]
+pub struct TopicMetrics {
+    state: Mutex<TopicState>,
+    max_topics: usize,
+}
+
+impl TopicMetrics {
+    /// Create empty counters tracking up to `max_topics` topics (0 to disable)
+    pub fn new(max_topics: usize) -> Self {
+        Self {
+            state: Mutex::new(TopicState::default()),
+            max_topics,
+        }
+    }
+
+    /// Record a new message received on a topic
+    pub fn record_message_received(&self, topic: &str, timestamp: SystemTime) {
+        self.update(topic, |counts| {
+            counts.messages_received += 1;
+            counts.last_message_time = Some(timestamp);
+        });
+    }
+
+    /// Record a dropped message on a topic
+    pub fn record_message_dropped(&self, topic: &str) {
+        self.update(topic, |counts| counts.messages_dropped += 1);
+    }
+
+    /// Record a processing error on a topic
+    pub fn record_processing_error(&self, topic: &str) {
+        self.update(topic, |counts| counts.processing_errors += 1);
+    }
+
+    /// Get the current counters
+    pub fn totals(&self) -> TopicTotals {
+        let state = self.state.lock().unwrap();
+        let mut topics: BTreeMap<String, TopicCounts> = state
+            .topics
+            .iter()
+            .map(|(topic, entry)| (topic.clone(), entry.counts.clone()))
+            .collect();
+        if state.other_topics > 0 {
+            topics.insert(OTHER_TOPICS.to_string(), state.other.clone());
+        }
+
+        TopicTotals {
+            topics,
+            other_topics: state.other_topics,
+            max_topics: self.max_topics,
+        }
+    }
+
+    /// Stop tracking the topics not matched by any of the `subscriptions`, so unsubscribed
+    /// topics don't linger in the counters
+    pub fn retain_subscribed(&self, subscriptions: &[String]) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.topics.retain(|topic, entry| {
+            let subscribed = subscriptions.iter().any(|filter| matches(topic, filter));
+            if !subscribed {
+                state.lru.remove(&entry.last_used);
+            }
+            subscribed
+        });
+    }
+
+    /// Get the number of individually tracked topics
+    pub fn tracked(&self) -> usize {
+        self.state.lock().unwrap().topics.len()
+    }
+
+    /// Stop tracking the topics without a message since `cutoff`
+    ///
+    /// Returns the number of topics removed. Their counts are dropped rather than added to
+    /// the `__other__` bucket, which only collects evicted topics.
+    pub fn remove_silent(&self, cutoff: SystemTime) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let tracked = state.topics.len();
+        state.topics.retain(|_, entry| {
+            let active = entry
+                .counts
+                .last_message_time
+                .is_some_and(|time| time >= cutoff);
+            if !active {
+                state.lru.remove(&entry.last_used);
+            }
+            active
+        });
+        tracked - state.topics.len()
+    }
+
+    /// Apply `update` to the counts of a topic, tracking it if needed
+    fn update(&self, topic: &str, update: impl FnOnce(&mut TopicCounts)) {
+        if self.max_topics == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.tick += 1;
+        let tick = state.tick;
+
+        if let Some(entry) = state.topics.get_mut(topic) {
+            state.lru.remove(&entry.last_used);
+            state.lru.insert(tick, topic.to_string());
+            entry.last_used = tick;
+            update(&mut entry.counts);
+            return;
+        }
+
+        // Make room by folding the coldest topic into the `__other__` bucket
+        if state.topics.len() >= self.max_topics {
+            if let Some((_, evicted)) = state.lru.pop_first() {
+                if let Some(entry) = state.topics.remove(&evicted) {
+                    state.other.merge(&entry.counts);
+                    state.other_topics += 1;
+                }
+            }
+        }
+
+        let mut counts = TopicCounts::default();
+        update(&mut counts);
+        state.lru.insert(tick, topic.to_string());
+        state.topics.insert(
+            topic.to_string(),
+            TopicEntry {
+                counts,
+                last_used: tick,
+            },
+        );
+    }
+}
+
+/// Start a background task removing the topics without messages in the last `retention` from
+/// the per-topic metrics every `interval`
+///
+/// Does nothing without an interval. The cardinality limit bounds bursts of new topics, this
+/// keeps topics that went silent long ago from accumulating on long-running instances.
+pub fn start_topic_compaction(
+    interval: Option<Duration>,
+    retention: Duration,
+    topic_metrics: Arc<TopicMetrics>,
+) {
+    let Some(interval) = interval else {
+        return;
+    };
+
+    info!(
+        "Removing topics silent for {} seconds from the per-topic metrics every {} seconds",
+        retention.as_secs(),
+        interval.as_secs()
+    );
+
+    tokio::spawn(async move {
+        let mut interval_timer = tokio::time::interval(interval);
+        loop {
+            interval_timer.tick().await;
+
+            let Some(cutoff) = SystemTime::now().checked_sub(retention) else {
+                continue;
+            };
+            let removed = topic_metrics.remove_silent(cutoff);
+            debug!(
+                "Compacted the per-topic metrics: removed {} silent topics, {} tracked",
+                removed,
+                topic_metrics.tracked()
+            );
+        }
+    });
+}
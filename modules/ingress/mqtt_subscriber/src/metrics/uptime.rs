@@ -0,0 +1,95 @@
+//! Connection state history for uptime reporting
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::metrics::{Duration, SystemTime};
+
+/// Maximum number of state transitions kept in the history
+const MAX_TRANSITIONS: usize = 1000;
+
+/// Tracks connection state transitions to report the fraction of time spent connected
+#[derive(Debug)]
+pub struct UptimeTracker {
+    state: Mutex<UptimeState>,
+}
+
+#[derive(Debug)]
+struct UptimeState {
+    /// When tracking started
+    started_at: SystemTime,
+    /// Connection state when tracking started (or before the oldest kept transition)
+    initial_connected: bool,
+    /// Current connection state
+    connected: bool,
+    /// State transitions with their timestamps (oldest first)
+    transitions: VecDeque<(SystemTime, bool)>,
+}
+
+impl UptimeTracker {
+    /// Start tracking with the given initial connection state
+    pub fn new(connected: bool) -> Self {
+        Self {
+            state: Mutex::new(UptimeState {
+                started_at: SystemTime::now(),
+                initial_connected: connected,
+                connected,
+                transitions: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Record the current connection state, only transitions are kept
+    pub fn record(&self, connected: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.connected == connected {
+            return;
+        }
+
+        state.connected = connected;
+        state.transitions.push_back((SystemTime::now(), connected));
+
+        // Drop the oldest transitions, remembering the state they led to
+        while state.transitions.len() > MAX_TRANSITIONS {
+            if let Some((time, connected)) = state.transitions.pop_front() {
+                state.started_at = time;
+                state.initial_connected = connected;
+            }
+        }
+    }
+
+    /// Get the fraction of the last `window` spent connected (0.0 - 1.0)
+    ///
+    /// Only the time since tracking started is considered if that is shorter than the window.
+    pub fn uptime_ratio(&self, window: Duration) -> f64 {
+        let state = self.state.lock().unwrap();
+        let now = SystemTime::now();
+        let window_start = now
+            .checked_sub(window)
+            .map_or(state.started_at, |start| start.max(state.started_at));
+
+        let total = now.duration_since(window_start).unwrap_or_default();
+        if total.is_zero() {
+            return if state.connected { 1.0 } else { 0.0 };
+        }
+
+        // Walk through the transitions, adding up the connected periods within the window
+        let mut connected = state.initial_connected;
+        let mut period_start = window_start;
+        let mut connected_time = Duration::from_secs(0);
+        for &(time, new_state) in &state.transitions {
+            if time > window_start {
+                if connected {
+                    connected_time += time.duration_since(period_start).unwrap_or_default();
+                }
+                period_start = time;
+            }
+            connected = new_state;
+        }
+        if connected {
+            connected_time += now.duration_since(period_start).unwrap_or_default();
+        }
+
+        connected_time.as_secs_f64() / total.as_secs_f64()
+    }
+}
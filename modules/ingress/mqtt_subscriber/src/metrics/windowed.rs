@@ -1,7 +1,22 @@
 //! Time-windowed metrics collection
 
+use std::collections::HashMap;
+
 use crate::metrics::Duration;
 use crate::metrics::SystemTime;
+use crate::models::{DeadLetterReason, DeliveryGuarantee, PayloadFormat, TopicClass};
+use crate::processor::queue::Lane;
+
+/// Maximum number of end-to-end latency samples kept per window for the percentiles
+const MAX_LATENCY_SAMPLES: usize = 10_000;
+
+/// Message counts of a single topic class
+#[derive(Debug, Clone, Default)]
+pub struct ClassCounts {
+    pub messages_received: usize,
+    pub messages_dropped: usize,
+    pub processing_errors: usize,
+}
 
 /// Metrics for a specific time window (e.g., one minute)
 #[derive(Debug, Clone)]
@@ -17,8 +32,46 @@ pub struct WindowedMetrics {
     pub messages_processed: usize,
     /// Number of messages dropped in this window
     pub messages_dropped: usize,
+    /// Number of processed messages the sinks failed to deliver in this window
+    pub messages_delivery_failed: usize,
     /// Number of processing errors in this window
     pub processing_errors: usize,
+    /// Number of messages dropped by the processing queue's drop policy in this window
+    pub queue_dropped: usize,
+    /// Number of messages added to the processing queue in this window, by priority lane
+    pub queued_by_lane: HashMap<Lane, usize>,
+    /// Number of messages dropped by the processing queue in this window, by priority lane
+    pub queue_dropped_by_lane: HashMap<Lane, usize>,
+    /// Number of messages received in this window, by QoS level
+    pub received_by_qos: HashMap<u8, usize>,
+    /// Number of payloads detected in this window, by format
+    pub detected_by_format: HashMap<PayloadFormat, usize>,
+    /// Number of messages delivered in this window, by delivery guarantee
+    pub delivered_by_guarantee: HashMap<DeliveryGuarantee, usize>,
+    /// Number of messages that failed in this window, by delivery guarantee
+    pub failed_by_guarantee: HashMap<DeliveryGuarantee, usize>,
+    /// Number of delivery retries of at-least-once messages in this window
+    pub delivery_retries: usize,
+    /// Number of QoS 2 handshakes completed with a PubComp in this window
+    pub qos2_completed: usize,
+    /// Number of messages with an empty payload that were dropped in this window
+    pub messages_empty: usize,
+    /// Number of messages rejected for exceeding the JSON nesting limit in this window
+    pub messages_too_deep: usize,
+    /// Number of messages that arrived after the reorder window of later messages in this window
+    pub messages_late: usize,
+    /// Number of messages with a malformed topic name in this window
+    pub messages_bad_topic: usize,
+    /// Number of MQTT pings without a response in this window
+    pub ping_timeouts: usize,
+    /// Number of reconnects with a new MQTT client ID in this window
+    pub fresh_clients: usize,
+    /// Number of detected MQTT client ID conflicts in this window
+    pub client_id_conflicts: usize,
+    /// Number of dead-lettered messages in this window, by reason
+    pub dead_lettered_by_reason: HashMap<DeadLetterReason, usize>,
+    /// Message counts in this window, by topic class
+    pub by_class: HashMap<TopicClass, ClassCounts>,
 
     /// Total message size in this window (for averaging)
     pub total_message_size: usize,
@@ -29,6 +82,13 @@ pub struct WindowedMetrics {
     pub max_message_size: usize,
     /// Maximum processing time seen in this window
     pub max_processing_time: Duration,
+
+    /// Number of messages with a known end-to-end latency in this window
+    pub end_to_end_latency_count: usize,
+    /// Total end-to-end latency in this window (for averaging)
+    pub total_end_to_end_latency: Duration,
+    /// The most recent end-to-end latencies in this window (for percentiles)
+    pub end_to_end_latency_samples: Vec<Duration>,
 }
 
 impl Default for WindowedMetrics {
@@ -40,11 +100,33 @@ impl Default for WindowedMetrics {
             messages_received: 0,
             messages_processed: 0,
             messages_dropped: 0,
+            messages_delivery_failed: 0,
             processing_errors: 0,
+            queue_dropped: 0,
+            queued_by_lane: HashMap::new(),
+            queue_dropped_by_lane: HashMap::new(),
+            received_by_qos: HashMap::new(),
+            detected_by_format: HashMap::new(),
+            delivered_by_guarantee: HashMap::new(),
+            failed_by_guarantee: HashMap::new(),
+            delivery_retries: 0,
+            qos2_completed: 0,
+            messages_empty: 0,
+            messages_too_deep: 0,
+            messages_late: 0,
+            ping_timeouts: 0,
+            fresh_clients: 0,
+            client_id_conflicts: 0,
+            messages_bad_topic: 0,
+            dead_lettered_by_reason: HashMap::new(),
+            by_class: HashMap::new(),
             total_message_size: 0,
             total_processing_time: Duration::from_secs(0),
             max_message_size: 0,
             max_processing_time: Duration::from_secs(0),
+            end_to_end_latency_count: 0,
+            total_end_to_end_latency: Duration::from_secs(0),
+            end_to_end_latency_samples: Vec::new(),
         }
     }
 }
@@ -59,18 +141,23 @@ impl WindowedMetrics {
         }
     }
 
-    /// Update window with a received message
-    pub fn record_message_received(&mut self, size: usize, timestamp: SystemTime) {
-        self.messages_received += 1;
-        self.total_message_size += size;
+    /// Update window with a received message, counted `count` times
+    pub fn record_message_received(&mut self, size: usize, timestamp: SystemTime, count: usize) {
+        self.messages_received += count;
+        self.total_message_size += size * count;
         self.max_message_size = self.max_message_size.max(size);
         self.end_time = timestamp;
     }
 
-    /// Update window with a processed message
-    pub fn record_message_processed(&mut self, processing_time: Duration) {
-        self.messages_processed += 1;
-        self.total_processing_time += processing_time;
+    /// Extend the window to a message that is not counted in it
+    pub fn record_message_seen(&mut self, timestamp: SystemTime) {
+        self.end_time = timestamp;
+    }
+
+    /// Update window with a processed message, counted `count` times
+    pub fn record_message_processed(&mut self, processing_time: Duration, count: usize) {
+        self.messages_processed += count;
+        self.total_processing_time += processing_time * count as u32;
         self.max_processing_time = if processing_time > self.max_processing_time {
             processing_time
         } else {
@@ -79,13 +166,127 @@ impl WindowedMetrics {
     }
 
     /// Record a message as dropped
-    pub fn record_message_dropped(&mut self) {
-        self.messages_dropped += 1;
+    pub fn record_message_dropped(&mut self, count: usize) {
+        self.messages_dropped += count;
+    }
+
+    /// Record a processed message the sinks failed to deliver
+    pub fn record_delivery_failed(&mut self, count: usize) {
+        self.messages_delivery_failed += count;
     }
 
     /// Record a processing error
-    pub fn record_processing_error(&mut self) {
-        self.processing_errors += 1;
+    pub fn record_processing_error(&mut self, count: usize) {
+        self.processing_errors += count;
+    }
+
+    /// Record a message added to the processing queue
+    pub fn record_message_queued(&mut self, lane: Lane, count: usize) {
+        *self.queued_by_lane.entry(lane).or_insert(0) += count;
+    }
+
+    /// Record a message dropped by the processing queue
+    pub fn record_queue_drop(&mut self, lane: Lane, count: usize) {
+        self.queue_dropped += count;
+        *self.queue_dropped_by_lane.entry(lane).or_insert(0) += count;
+    }
+
+    /// Record a message received with the given QoS level
+    pub fn record_qos_received(&mut self, qos: u8, count: usize) {
+        *self.received_by_qos.entry(qos).or_insert(0) += count;
+    }
+
+    /// Record the detected format of a payload
+    pub fn record_format_detected(&mut self, format: PayloadFormat, count: usize) {
+        *self.detected_by_format.entry(format).or_insert(0) += count;
+    }
+
+    /// Record a completed QoS 2 handshake
+    pub fn record_qos2_completed(&mut self) {
+        self.qos2_completed += 1;
+    }
+
+    /// Record a delivered message of a topic with the given delivery guarantee
+    pub fn record_guarantee_delivered(&mut self, guarantee: DeliveryGuarantee, count: usize) {
+        *self.delivered_by_guarantee.entry(guarantee).or_insert(0) += count;
+    }
+
+    /// Record a failed message of a topic with the given delivery guarantee
+    pub fn record_guarantee_failed(&mut self, guarantee: DeliveryGuarantee, count: usize) {
+        *self.failed_by_guarantee.entry(guarantee).or_insert(0) += count;
+    }
+
+    /// Record a retried delivery
+    pub fn record_delivery_retry(&mut self, count: usize) {
+        self.delivery_retries += count;
+    }
+
+    /// Record a dropped message with an empty payload
+    pub fn record_message_empty(&mut self, count: usize) {
+        self.messages_empty += count;
+    }
+
+    /// Record a message rejected for exceeding the JSON nesting limit
+    pub fn record_message_too_deep(&mut self, count: usize) {
+        self.messages_too_deep += count;
+    }
+
+    /// Record a message that arrived too late to be delivered in timestamp order
+    pub fn record_message_late(&mut self, count: usize) {
+        self.messages_late += count;
+    }
+
+    /// Record an MQTT ping without a response
+    pub fn record_ping_timeout(&mut self) {
+        self.ping_timeouts += 1;
+    }
+
+    /// Record a reconnect with a new MQTT client ID
+    pub fn record_fresh_client(&mut self) {
+        self.fresh_clients += 1;
+    }
+
+    /// Record a detected MQTT client ID conflict
+    pub fn record_client_id_conflict(&mut self) {
+        self.client_id_conflicts += 1;
+    }
+
+    /// Record a message with a malformed topic name
+    pub fn record_bad_topic(&mut self) {
+        self.messages_bad_topic += 1;
+    }
+
+    /// Record a message as dead-lettered
+    pub fn record_message_dead_lettered(&mut self, reason: DeadLetterReason, count: usize) {
+        *self.dead_lettered_by_reason.entry(reason).or_insert(0) += count;
+    }
+
+    /// Record the time from a message's measurement to its delivery
+    pub fn record_end_to_end_latency(&mut self, latency: Duration) {
+        // Overwrite the oldest sample once full, keeping the most recent ones
+        if self.end_to_end_latency_samples.len() < MAX_LATENCY_SAMPLES {
+            self.end_to_end_latency_samples.push(latency);
+        } else {
+            self.end_to_end_latency_samples[self.end_to_end_latency_count % MAX_LATENCY_SAMPLES] =
+                latency;
+        }
+        self.end_to_end_latency_count += 1;
+        self.total_end_to_end_latency += latency;
+    }
+
+    /// Record a message received on a topic of the given class
+    pub fn record_class_received(&mut self, class: TopicClass, count: usize) {
+        self.by_class.entry(class).or_default().messages_received += count;
+    }
+
+    /// Record a dropped message on a topic of the given class
+    pub fn record_class_dropped(&mut self, class: TopicClass, count: usize) {
+        self.by_class.entry(class).or_default().messages_dropped += count;
+    }
+
+    /// Record a processing error on a topic of the given class
+    pub fn record_class_error(&mut self, class: TopicClass, count: usize) {
+        self.by_class.entry(class).or_default().processing_errors += count;
     }
 
     // /// Calculate the message throughput for this window
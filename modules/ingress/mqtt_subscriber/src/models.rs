@@ -1,7 +1,10 @@
 //! Shared data models for the MQTT subscriber service
 
+use bytes::Bytes;
 use rumqttc::QoS;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
 use std::time::{Instant, SystemTime};
 
 /// MQTT Message with metadata
@@ -9,7 +12,8 @@ use std::time::{Instant, SystemTime};
 #[allow(dead_code)] // Silence warning about unused fields
 pub struct MqttMessage {
     pub topic: String,
-    pub payload: Vec<u8>,
+    /// Payload sharing the buffer of the received publish packet
+    pub payload: Bytes,
     pub qos: QoS,
     pub retain: bool,
     pub received_at: Instant,  // Kept for internal timing
@@ -21,4 +25,239 @@ pub struct SensorData {
     pub sensor_id: String,
     pub message: String,
     pub sensor_timestamp: SystemTime,
+    /// Metadata about the sensor from the enrichment table
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Map<String, Value>>,
+}
+
+/// Serialization format of the records sent to the output sinks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerializationFormat {
+    /// The message wrapped in a `SensorData` JSON object
+    Json,
+    /// The message wrapped in a `SensorData` protobuf message (`proto/sensor_data.proto`)
+    Protobuf,
+    /// The (transformed) payload as is, e.g. for protobuf sensors
+    Raw,
+}
+
+impl SerializationFormat {
+    /// Parse a format name as used in the configuration
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(SerializationFormat::Json),
+            "protobuf" => Some(SerializationFormat::Protobuf),
+            "raw" => Some(SerializationFormat::Raw),
+            _ => None,
+        }
+    }
+
+    /// Name used in the configuration
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SerializationFormat::Json => "json",
+            SerializationFormat::Protobuf => "protobuf",
+            SerializationFormat::Raw => "raw",
+        }
+    }
+
+    /// MIME type of records in this format, sent as the `content-type` header
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SerializationFormat::Json => "application/json",
+            SerializationFormat::Protobuf => "application/x-protobuf",
+            SerializationFormat::Raw => "application/octet-stream",
+        }
+    }
+}
+
+/// Encoding of a payload as detected from its bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PayloadFormat {
+    Json,
+    Cbor,
+    Protobuf,
+    /// None of the known formats
+    Binary,
+}
+
+impl PayloadFormat {
+    /// Name used in the metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayloadFormat::Json => "json",
+            PayloadFormat::Cbor => "cbor",
+            PayloadFormat::Protobuf => "protobuf",
+            PayloadFormat::Binary => "binary",
+        }
+    }
+
+    /// MIME type of payloads in this format, sent as the `content-type` header
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            PayloadFormat::Json => "application/json",
+            PayloadFormat::Cbor => "application/cbor",
+            PayloadFormat::Protobuf => "application/x-protobuf",
+            PayloadFormat::Binary => "application/octet-stream",
+        }
+    }
+}
+
+/// How hard delivery of a topic's messages to the output sinks is tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeliveryGuarantee {
+    /// A failed delivery is given up on right away
+    BestEffort,
+    /// A failed delivery is retried before the message is dead-lettered
+    AtLeastOnce,
+}
+
+impl DeliveryGuarantee {
+    /// Parse a guarantee name as used in the configuration
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "best_effort" => Some(DeliveryGuarantee::BestEffort),
+            "at_least_once" => Some(DeliveryGuarantee::AtLeastOnce),
+            _ => None,
+        }
+    }
+
+    /// Name used in the configuration and metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryGuarantee::BestEffort => "best_effort",
+            DeliveryGuarantee::AtLeastOnce => "at_least_once",
+        }
+    }
+}
+
+/// Service class of a topic, used to report the metrics of critical topics separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TopicClass {
+    Critical,
+    Normal,
+    Bulk,
+}
+
+impl TopicClass {
+    /// Parse a class name as used in the configuration
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "critical" => Some(TopicClass::Critical),
+            "normal" => Some(TopicClass::Normal),
+            "bulk" => Some(TopicClass::Bulk),
+            _ => None,
+        }
+    }
+
+    /// Name used in the configuration and metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TopicClass::Critical => "critical",
+            TopicClass::Normal => "normal",
+            TopicClass::Bulk => "bulk",
+        }
+    }
+}
+
+/// A processed message, encoded for delivery to the output sinks
+#[derive(Debug, Clone)]
+pub struct OutputRecord {
+    /// Encoded record, sharing the received payload's buffer for raw records
+    pub payload: Bytes,
+    /// Format the payload is encoded in
+    pub format: SerializationFormat,
+    /// Measurement time of the message, or its receive time if unknown
+    pub timestamp: SystemTime,
+    /// MQTT topic the message was received on, used as the Kafka key
+    pub mqtt_topic: String,
+    /// Kafka topic resolved from the payload, the sensor data topic if `None`
+    pub topic: Option<String>,
+    /// ID for deduplication by consumers, if enabled
+    pub message_id: Option<String>,
+    /// ID tying the record to the upstream trace, if propagation is enabled
+    pub correlation_id: Option<String>,
+    /// Detected encoding of a raw payload, if format detection is enabled
+    pub detected_format: Option<PayloadFormat>,
+    /// The `SensorData` wrapped in `json` and `protobuf` records, for sinks with their own
+    /// encoding
+    pub sensor_data: Option<Arc<SensorData>>,
+}
+
+impl OutputRecord {
+    /// MIME type of the record, sent as the `content-type` header
+    pub fn content_type(&self) -> &'static str {
+        match (self.format, self.detected_format) {
+            (SerializationFormat::Raw, Some(detected_format)) => detected_format.content_type(),
+            (format, _) => format.content_type(),
+        }
+    }
+}
+
+/// Reason a message could not be forwarded and was dead-lettered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeadLetterReason {
+    /// The payload could not be decoded (e.g. invalid UTF-8)
+    InvalidPayload,
+    /// The JSON payload is nested deeper than `MAX_JSON_DEPTH`
+    TooDeep,
+    /// The payload transformation failed
+    #[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+    TransformFailed,
+    /// The validation service rejected the payload
+    ValidationFailed,
+    /// The validation service could not be reached (with `VALIDATION_FAIL_MODE=closed`)
+    ValidationUnavailable,
+    /// The output record could not be serialized
+    SerializationFailed,
+    /// The message could not be delivered to Kafka
+    KafkaFailed,
+    /// The message could not be delivered to the webhook
+    WebhookFailed,
+}
+
+impl DeadLetterReason {
+    /// Machine-readable name used in Kafka headers and metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeadLetterReason::InvalidPayload => "invalid_payload",
+            DeadLetterReason::TooDeep => "too_deep",
+            DeadLetterReason::TransformFailed => "transform_failed",
+            DeadLetterReason::ValidationFailed => "validation_failed",
+            DeadLetterReason::ValidationUnavailable => "validation_unavailable",
+            DeadLetterReason::SerializationFailed => "serialization_failed",
+            DeadLetterReason::KafkaFailed => "kafka_failed",
+            DeadLetterReason::WebhookFailed => "webhook_failed",
+        }
+    }
+
+    /// Check if the message was processed but a sink failed to deliver it
+    pub fn is_delivery_failure(&self) -> bool {
+        matches!(
+            self,
+            DeadLetterReason::KafkaFailed | DeadLetterReason::WebhookFailed
+        )
+    }
+}
+
+/// Error raised while processing a message, with the reason used for dead-lettering
+#[derive(Debug)]
+pub struct ProcessingError {
+    pub reason: DeadLetterReason,
+    pub message: String,
+}
+
+impl ProcessingError {
+    pub fn new(reason: DeadLetterReason, message: impl Into<String>) -> Self {
+        Self {
+            reason,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
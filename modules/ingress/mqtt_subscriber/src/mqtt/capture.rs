@@ -0,0 +1,58 @@
+//! Capture of raw MQTT publish packets for protocol debugging
+
+use rumqttc::Publish;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// All fields of a received publish packet
+#[derive(Debug, Clone)]
+pub struct CapturedPacket {
+    pub captured_at: SystemTime,
+    pub topic: String,
+    pub pkid: u16,
+    pub qos: u8,
+    pub dup: bool,
+    pub retain: bool,
+    pub payload: Vec<u8>,
+}
+
+/// Bounded buffer of the most recently received publish packets
+pub struct PacketCapture {
+    packets: Mutex<VecDeque<CapturedPacket>>,
+    capacity: usize,
+}
+
+impl PacketCapture {
+    /// Create a capture keeping the last `capacity` packets
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            packets: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Record a received publish packet, evicting the oldest one when full
+    pub fn record(&self, publish: &Publish) {
+        let packet = CapturedPacket {
+            captured_at: SystemTime::now(),
+            topic: publish.topic.clone(),
+            pkid: publish.pkid,
+            qos: publish.qos as u8,
+            dup: publish.dup,
+            retain: publish.retain,
+            payload: publish.payload.to_vec(),
+        };
+
+        let mut packets = self.packets.lock().unwrap();
+        if packets.len() >= self.capacity {
+            packets.pop_front();
+        }
+        packets.push_back(packet);
+    }
+
+    /// Get the captured packets, oldest first
+    pub fn packets(&self) -> Vec<CapturedPacket> {
+        self.packets.lock().unwrap().iter().cloned().collect()
+    }
+}
@@ -0,0 +1,131 @@
+//! Automatic discovery of the topics that actually produce data
+
+use log::{error, info, warn};
+use rumqttc::matches;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::config::DiscoveryConfig;
+use crate::mqtt::subscriber::MqttSubscriber;
+
+/// Maximum number of distinct topics recorded during discovery
+const MAX_DISCOVERED_TOPICS: usize = 10_000;
+
+/// Topics observed on the discovery pattern, with their message counts
+pub struct TopicDiscovery {
+    config: DiscoveryConfig,
+    observing: AtomicBool,
+    topics: Mutex<BTreeMap<String, u64>>,
+}
+
+impl TopicDiscovery {
+    /// Create a discovery that observes topics once started
+    pub fn new(config: DiscoveryConfig) -> Self {
+        Self {
+            config,
+            observing: AtomicBool::new(false),
+            topics: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Get the broad topic filter subscribed during discovery
+    pub fn pattern(&self) -> &str {
+        &self.config.pattern
+    }
+
+    /// Check if topics are currently being observed
+    pub fn is_observing(&self) -> bool {
+        self.observing.load(Ordering::Relaxed)
+    }
+
+    /// Record a message received on a topic, if discovery is running
+    pub fn observe(&self, topic: &str) {
+        if !self.is_observing() || !matches(topic, &self.config.pattern) {
+            return;
+        }
+
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(count) = topics.get_mut(topic) {
+            *count += 1;
+        } else if topics.len() < MAX_DISCOVERED_TOPICS {
+            topics.insert(topic.to_string(), 1);
+        }
+    }
+
+    /// Get the observed topics with the number of messages received on each
+    pub fn topics(&self) -> BTreeMap<String, u64> {
+        self.topics.lock().unwrap().clone()
+    }
+}
+
+/// Run topic discovery in the background if enabled
+///
+/// Subscribes to the discovery pattern for the discovery window and records the topics
+/// messages arrive on. With narrowing enabled, the discovered topics are then subscribed
+/// explicitly and the pattern is unsubscribed, unless it was already subscribed before.
+pub fn start_discovery(subscriber: Arc<MqttSubscriber>) {
+    if subscriber.topic_discovery().is_none() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let Some(discovery) = subscriber.topic_discovery() else {
+            return;
+        };
+        let pattern = discovery.pattern().to_string();
+        let already_subscribed = subscriber.get_topics().await.contains(&pattern);
+
+        if let Err(e) = subscriber.subscribe(&pattern).await {
+            error!(
+                "Failed to subscribe to discovery pattern {}: {}",
+                pattern, e
+            );
+            return;
+        }
+        discovery.observing.store(true, Ordering::Relaxed);
+        info!(
+            "Discovering topics on {} for {} seconds",
+            pattern,
+            discovery.config.window.as_secs()
+        );
+
+        tokio::time::sleep(discovery.config.window).await;
+        discovery.observing.store(false, Ordering::Relaxed);
+
+        let topics = discovery.topics();
+        info!("Discovered {} active topics on {}", topics.len(), pattern);
+        if topics.len() >= MAX_DISCOVERED_TOPICS {
+            warn!(
+                "Topic discovery stopped recording after {} topics",
+                MAX_DISCOVERED_TOPICS
+            );
+        }
+
+        if !discovery.config.narrow || already_subscribed {
+            return;
+        }
+        if topics.is_empty() {
+            warn!(
+                "No topics discovered on {}, keeping the subscription",
+                pattern
+            );
+            return;
+        }
+
+        // Subscribe the discovered topics before dropping the pattern so no messages are lost
+        let discovered: Vec<String> = topics.into_keys().collect();
+        if let Err(e) = subscriber.subscribe_many(&discovered).await {
+            error!("Failed to subscribe to the discovered topics: {}", e);
+            return;
+        }
+        // The discovered topics are covered by the pattern and must stay subscribed
+        match subscriber.unsubscribe_with(&pattern, false).await {
+            Ok(_) => info!("Narrowed subscription {} to the discovered topics", pattern),
+            Err(e) => error!(
+                "Failed to unsubscribe from discovery pattern {}: {}",
+                pattern, e
+            ),
+        }
+    });
+}
@@ -1,3 +1,8 @@
 //! MQTT functionality
 
+pub mod capture;
+pub mod discovery;
+pub mod proxy;
 pub mod subscriber;
+pub mod tls;
+pub mod topic;
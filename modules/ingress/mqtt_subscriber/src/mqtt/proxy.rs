@@ -0,0 +1,225 @@
+//! Tunnelling of the MQTT connection through a SOCKS5 proxy
+//!
+//! The MQTT client can only connect to a host and port directly, so a local listener on the
+//! loopback interface accepts its connections and forwards each of them to the broker through
+//! the proxy.
+
+use log::{debug, error, info, warn};
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener as StdTcpListener};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// SOCKS5 protocol version
+const SOCKS_VERSION: u8 = 0x05;
+/// Authentication methods offered to the proxy
+const AUTH_NONE: u8 = 0x00;
+const AUTH_PASSWORD: u8 = 0x02;
+/// Address types of the CONNECT request and reply
+const ADDR_IPV4: u8 = 0x01;
+const ADDR_DOMAIN: u8 = 0x03;
+const ADDR_IPV6: u8 = 0x04;
+
+/// SOCKS5 proxy the MQTT connection is established through
+#[derive(Clone)]
+pub struct ProxyConfig {
+    pub host: String,
+    pub port: u16,
+    /// Username and password, if the proxy requires authentication
+    pub credentials: Option<(String, String)>,
+}
+
+impl ProxyConfig {
+    /// Parse a `socks5://[user:password@]host[:port]` URL, the port defaults to 1080
+    ///
+    /// `socks5h://` is accepted as well, the broker hostname is always resolved by the proxy.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let parsed =
+            reqwest::Url::parse(url).map_err(|e| format!("Invalid proxy URL {:?}: {}", url, e))?;
+        if !matches!(parsed.scheme(), "socks5" | "socks5h") {
+            return Err(format!(
+                "Unsupported proxy scheme {:?}, only socks5 is supported",
+                parsed.scheme()
+            ));
+        }
+        let host = parsed
+            .host_str()
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| format!("Proxy URL {:?} has no host", url))?;
+
+        Ok(Self {
+            host: host.trim_matches(['[', ']']).to_string(),
+            port: parsed.port().unwrap_or(1080),
+            credentials: (!parsed.username().is_empty()).then(|| {
+                (
+                    parsed.username().to_string(),
+                    parsed.password().unwrap_or_default().to_string(),
+                )
+            }),
+        })
+    }
+}
+
+/// Start forwarding connections to a local address through the proxy to the broker
+///
+/// Returns the local address the MQTT client should connect to instead of the broker. Every
+/// accepted connection opens its own connection through the proxy, so reconnects of the MQTT
+/// client go through the proxy again.
+pub fn start_proxy_tunnel(
+    proxy: &ProxyConfig,
+    broker: (String, u16),
+) -> Result<SocketAddr, String> {
+    let listener = StdTcpListener::bind(("127.0.0.1", 0))
+        .and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        })
+        .map_err(|e| format!("Failed to start the MQTT proxy tunnel: {}", e))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to start the MQTT proxy tunnel: {}", e))?;
+
+    info!(
+        "Connecting to the MQTT broker {}:{} through the SOCKS5 proxy {}:{}",
+        broker.0, broker.1, proxy.host, proxy.port
+    );
+
+    let proxy = proxy.clone();
+    tokio::spawn(async move {
+        loop {
+            let mut client = match listener.accept().await {
+                Ok((client, _)) => client,
+                Err(e) => {
+                    warn!("Failed to accept MQTT proxy tunnel connection: {}", e);
+                    continue;
+                }
+            };
+
+            let proxy = proxy.clone();
+            let (host, port) = broker.clone();
+            tokio::spawn(async move {
+                // Dropping the client connection on failure makes the MQTT client report a
+                // connection error and reconnect as usual
+                let mut upstream = match socks5_connect(&proxy, &host, port).await {
+                    Ok(upstream) => upstream,
+                    Err(e) => {
+                        error!(
+                            "Failed to connect to {}:{} through the SOCKS5 proxy {}:{}: {}",
+                            host, port, proxy.host, proxy.port, e
+                        );
+                        return;
+                    }
+                };
+                match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+                    Ok((sent, received)) => debug!(
+                        "MQTT proxy tunnel closed ({} bytes sent, {} bytes received)",
+                        sent, received
+                    ),
+                    Err(e) => debug!("MQTT proxy tunnel closed: {}", e),
+                }
+            });
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// Open a connection to `host:port` through a SOCKS5 proxy
+async fn socks5_connect(proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port)).await?;
+    stream.set_nodelay(true)?;
+
+    // Negotiate the authentication method
+    let method = if proxy.credentials.is_some() {
+        AUTH_PASSWORD
+    } else {
+        AUTH_NONE
+    };
+    stream.write_all(&[SOCKS_VERSION, 1, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION || reply[1] != method {
+        return Err(proxy_error("proxy rejected the authentication method"));
+    }
+
+    // Username/password authentication (RFC 1929)
+    if let Some((username, password)) = &proxy.credentials {
+        let mut request = vec![0x01];
+        for field in [username, password] {
+            let len = u8::try_from(field.len())
+                .map_err(|_| proxy_error("proxy username or password too long"))?;
+            request.push(len);
+            request.extend_from_slice(field.as_bytes());
+        }
+        stream.write_all(&request).await?;
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0x00 {
+            return Err(proxy_error("proxy rejected the credentials"));
+        }
+    }
+
+    // Request a connection to the broker, letting the proxy resolve hostnames
+    let mut request = vec![SOCKS_VERSION, 0x01, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ADDR_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ADDR_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let len =
+                u8::try_from(host.len()).map_err(|_| proxy_error("broker hostname too long"))?;
+            request.push(ADDR_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    if header[0] != SOCKS_VERSION {
+        return Err(proxy_error("invalid proxy reply"));
+    }
+    if header[1] != 0x00 {
+        return Err(proxy_error(&format!(
+            "proxy refused the connection ({})",
+            reply_message(header[1])
+        )));
+    }
+
+    // Skip the bound address and port
+    let addr_len = match header[3] {
+        ADDR_IPV4 => 4,
+        ADDR_IPV6 => 16,
+        ADDR_DOMAIN => usize::from(stream.read_u8().await?),
+        _ => return Err(proxy_error("invalid proxy reply")),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(stream)
+}
+
+/// Describe a SOCKS5 reply code
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::other(message.to_string())
+}
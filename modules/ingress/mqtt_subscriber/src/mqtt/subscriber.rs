@@ -1,33 +1,146 @@
 //! MQTT Subscriber implementation
 
-use log::{error, info};
-use rumqttc::{AsyncClient, EventLoop, MqttOptions, QoS};
-use std::collections::HashSet;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use log::{error, info, warn};
+use rumqttc::{
+    valid_filter, AsyncClient, ClientError, EventLoop, Publish, QoS, SubAck, SubscribeFilter,
+    SubscribeReasonCode,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{oneshot, watch, Notify, RwLock};
+use tokio::time::Instant;
+
+use crate::config::{with_broker_address, MqttConfig};
+use crate::error::SpineError;
+use crate::metrics::UptimeTracker;
+use crate::mqtt::capture::PacketCapture;
+use crate::mqtt::discovery::TopicDiscovery;
+use crate::mqtt::proxy::start_proxy_tunnel;
+use crate::mqtt::topic::{has_control_chars, sanitize_topic};
+
+/// Interval of the progress logs while resubscribing after a reconnect
+const RESUBSCRIBE_PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Subscription confirmed by the broker's SubAck
+#[derive(Debug, Clone, Copy)]
+pub struct GrantedSubscription {
+    /// Packet ID of the acknowledged SUBSCRIBE packet
+    pub packet_id: u16,
+    /// Maximum QoS the broker delivers messages of the subscription with
+    pub qos: QoS,
+}
+
+/// Topics of a SUBSCRIBE request, with the waiter for its SubAck if any
+struct PendingSubscribe {
+    topics: Vec<String>,
+    ack: Option<oneshot::Sender<(u16, SubscribeReasonCode)>>,
+}
 
 /// MQTT Subscriber for managing MQTT topic subscriptions
 pub struct MqttSubscriber {
     client: AsyncClient,
     topics: Arc<RwLock<HashSet<String>>>,
     mqtt_qos: QoS,
+    subscribe_batch_size: usize,
+    resubscribe_rate: usize,
+    // Serializes sending SUBSCRIBE requests so they leave in the order they are queued below
+    subscribe_lock: tokio::sync::Mutex<()>,
+    // Topics of the SUBSCRIBE requests not yet sent by the event loop, oldest first
+    queued_subscribes: Mutex<VecDeque<PendingSubscribe>>,
+    // Topics of the sent SUBSCRIBE packets waiting for a SubAck, by packet id
+    inflight_subscribes: Mutex<HashMap<u16, PendingSubscribe>>,
+    // Receive times of the QoS 2 messages whose handshake hasn't completed, by packet id
+    inflight_qos2: Mutex<HashMap<u16, Instant>>,
+    unsubscribe_covered: bool,
+    manual_ack: bool,
+    auto_reconnect: bool,
+    fresh_client_after: u32,
+    client_id_conflict_threshold: u32,
+    client_id_conflict_window: Duration,
+    packet_capture: Option<Arc<PacketCapture>>,
+    topic_discovery: Option<TopicDiscovery>,
     is_connected: AtomicBool,
+    // Number of ConnAcks received, identifies the current connection
+    connection_count: AtomicU64,
+    // Whether the client connected since the service started
+    connected_once: watch::Sender<bool>,
+    // Wakes the resubscribe task after a reconnect that lost the session
+    resubscribe_requested: Notify,
+    resubscriber_started: AtomicBool,
+    // How long API requests wait for the first connection
+    startup_wait: Duration,
+    // How long API subscribe requests wait for the SubAck
+    suback_timeout: Duration,
+    is_stopped: AtomicBool,
+    uptime: UptimeTracker,
 }
 
 impl MqttSubscriber {
     /// Create a new MQTT subscriber with a persistent connection
-    pub fn new(mqtt_options: MqttOptions, mqtt_qos: QoS) -> (Self, EventLoop) {
-        info!("Creating new MQTT client");
+    ///
+    /// `config.client_capacity` bounds the number of requests (subscribes, acks, ...) buffered
+    /// between the client and the event loop. With `config.auto_reconnect` disabled, the
+    /// message processor stops after the first connection failure. Otherwise it switches to a
+    /// new client ID after `config.fresh_client_after` consecutive failures (0 to never
+    /// switch). The last `config.capture_raw_packets` received publish packets are kept for
+    /// debugging (0 to disable). With `config.discovery` set, the topics of received messages
+    /// are recorded once discovery starts. With `config.proxy` set, the client connects to the
+    /// broker through a local tunnel to the SOCKS5 proxy.
+    pub fn new(config: &MqttConfig) -> (Self, EventLoop) {
+        let capacity = config.client_capacity;
+        info!("Creating new MQTT client (request capacity: {})", capacity);
+
+        let manual_ack = config.mqtt_options.manual_acks();
+
+        // Point the client at the local end of the proxy tunnel instead of the broker
+        let mqtt_options = match &config.proxy {
+            Some(proxy) => match start_proxy_tunnel(proxy, config.mqtt_options.broker_address()) {
+                Ok(tunnel) => with_broker_address(
+                    &config.mqtt_options,
+                    tunnel.ip().to_string(),
+                    tunnel.port(),
+                ),
+                Err(e) => {
+                    error!("{}, connecting to the broker directly", e);
+                    config.mqtt_options.clone()
+                }
+            },
+            None => config.mqtt_options.clone(),
+        };
 
         // Create MQTT client and event loop
-        let (client, event_loop) = AsyncClient::new(mqtt_options, 10);
+        let (client, event_loop) = AsyncClient::new(mqtt_options, capacity);
 
         let subscriber = Self {
             client,
             topics: Arc::new(RwLock::new(HashSet::new())),
-            mqtt_qos,
+            mqtt_qos: config.mqtt_qos,
+            subscribe_batch_size: config.subscribe_batch_size,
+            resubscribe_rate: config.resubscribe_rate,
+            subscribe_lock: tokio::sync::Mutex::new(()),
+            queued_subscribes: Mutex::new(VecDeque::new()),
+            inflight_subscribes: Mutex::new(HashMap::new()),
+            inflight_qos2: Mutex::new(HashMap::new()),
+            unsubscribe_covered: config.unsubscribe_covered,
+            manual_ack,
+            auto_reconnect: config.auto_reconnect,
+            fresh_client_after: config.fresh_client_after,
+            client_id_conflict_threshold: config.client_id_conflict_threshold,
+            client_id_conflict_window: config.client_id_conflict_window,
+            packet_capture: (config.capture_raw_packets > 0)
+                .then(|| Arc::new(PacketCapture::new(config.capture_raw_packets))),
+            topic_discovery: config.discovery.clone().map(TopicDiscovery::new),
             is_connected: AtomicBool::new(false),
+            connection_count: AtomicU64::new(0),
+            connected_once: watch::Sender::new(false),
+            resubscribe_requested: Notify::new(),
+            resubscriber_started: AtomicBool::new(false),
+            startup_wait: config.startup_wait,
+            suback_timeout: config.suback_timeout,
+            is_stopped: AtomicBool::new(false),
+            uptime: UptimeTracker::new(false),
         };
 
         info!("MQTT client created");
@@ -42,60 +155,475 @@ impl MqttSubscriber {
 
     /// Update the connection status
     pub fn update_connection_status(&self, status: bool) {
+        if status {
+            self.connection_count.fetch_add(1, Ordering::Relaxed);
+        }
         self.is_connected.store(status, Ordering::Relaxed);
+        self.uptime.record(status);
+        if status {
+            self.connected_once
+                .send_if_modified(|connected| !std::mem::replace(connected, true));
+        }
+    }
+
+    /// Wait for the first connection to the broker, for at most the configured startup wait
+    ///
+    /// Until the first ConnAck, requests to the broker are only queued behind the connection
+    /// attempt, so API requests made right after startup wait here and fail with
+    /// `SpineError::Connecting` if the client is still connecting. Returns immediately once the
+    /// client connected, even if it is currently reconnecting.
+    pub async fn wait_for_first_connection(&self) -> Result<(), SpineError> {
+        let mut connected_once = self.connected_once.subscribe();
+        let connected = tokio::time::timeout(self.startup_wait, connected_once.wait_for(|c| *c))
+            .await
+            .is_ok_and(|result| result.is_ok());
+        if connected {
+            Ok(())
+        } else {
+            Err(SpineError::Connecting)
+        }
+    }
+
+    /// Get the fraction of the last `window` the client was connected (0.0 - 1.0)
+    pub fn uptime_ratio(&self, window: Duration) -> f64 {
+        self.uptime.uptime_ratio(window)
+    }
+
+    /// Check if the connection should be re-established after a failure
+    pub fn auto_reconnect(&self) -> bool {
+        self.auto_reconnect
+    }
+
+    /// Get the number of consecutive connection failures before reconnecting with a new
+    /// client ID, 0 if disabled
+    pub fn fresh_client_after(&self) -> u32 {
+        self.fresh_client_after
+    }
+
+    /// Get the number of established connections dropped within the returned window that
+    /// indicates another client using the same client ID, 0 if the detection is disabled
+    pub fn client_id_conflict_detection(&self) -> (u32, Duration) {
+        (
+            self.client_id_conflict_threshold,
+            self.client_id_conflict_window,
+        )
+    }
+
+    /// Get the capture of received publish packets, if enabled
+    pub fn packet_capture(&self) -> Option<&Arc<PacketCapture>> {
+        self.packet_capture.as_ref()
+    }
+
+    /// Get the automatic topic discovery, if enabled
+    pub fn topic_discovery(&self) -> Option<&TopicDiscovery> {
+        self.topic_discovery.as_ref()
+    }
+
+    /// Check if the client gave up on the connection (only with auto-reconnect disabled)
+    pub fn is_stopped(&self) -> bool {
+        self.is_stopped.load(Ordering::Relaxed)
+    }
+
+    /// Mark the client as having given up on the connection
+    pub fn mark_stopped(&self) {
+        self.is_stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if incoming messages must be acknowledged manually
+    pub fn manual_ack(&self) -> bool {
+        self.manual_ack
+    }
+
+    /// Acknowledge an incoming message to the broker (only used in manual ack mode)
+    pub async fn ack(&self, publish: &Publish) -> Result<(), String> {
+        self.client
+            .ack(publish)
+            .await
+            .map_err(|e| format!("Failed to ack message: {:?}", e))
+    }
+
+    /// Queue an MQTT DISCONNECT packet, ending the connection on purpose
+    ///
+    /// The packet is sent after the requests queued before it, e.g. pending acks, and the
+    /// broker then discards the last will instead of publishing it.
+    pub async fn disconnect(&self) -> Result<(), String> {
+        self.client
+            .disconnect()
+            .await
+            .map_err(|e| format!("Failed to disconnect from the MQTT broker: {:?}", e))
     }
 
     /// Subscribe to a topic
-    pub async fn subscribe(&self, topic: &str) -> Result<(), String> {
+    pub async fn subscribe(&self, topic: &str) -> Result<(), SpineError> {
+        self.subscribe_with(topic, None).await.map(|_| ())
+    }
+
+    /// Subscribe to a topic and wait for the broker's SubAck, for at most the configured
+    /// SubAck timeout
+    ///
+    /// Returns `None` if no SUBSCRIBE packet was needed because the topic is already tracked
+    /// or covered by a wildcard subscription. A rejected subscription is no longer tracked and
+    /// fails with `SpineError::NotAuthorized`. Without a SubAck in time the subscription stays
+    /// tracked and the request fails with `SpineError::AckTimeout`. If the connection fails
+    /// before the SubAck, the topic is resubscribed after reconnecting.
+    pub async fn subscribe_acked(
+        &self,
+        topic: &str,
+    ) -> Result<Option<GrantedSubscription>, SpineError> {
+        let (ack, acked) = oneshot::channel();
+        if !self.subscribe_with(topic, Some(ack)).await? {
+            return Ok(None);
+        }
+
+        match tokio::time::timeout(self.suback_timeout, acked).await {
+            Ok(Ok((packet_id, SubscribeReasonCode::Success(qos)))) => {
+                Ok(Some(GrantedSubscription { packet_id, qos }))
+            }
+            Ok(Ok((_, SubscribeReasonCode::Failure))) => {
+                Err(SpineError::NotAuthorized(topic.to_string()))
+            }
+            // The SubAck was lost with the connection
+            Ok(Err(_)) => Err(SpineError::Disconnected),
+            Err(_) => Err(SpineError::AckTimeout),
+        }
+    }
+
+    /// Subscribe to a topic, passing the SubAck of the SUBSCRIBE packet to `ack`
+    ///
+    /// Returns whether a SUBSCRIBE packet was sent.
+    async fn subscribe_with(
+        &self,
+        topic: &str,
+        ack: Option<oneshot::Sender<(u16, SubscribeReasonCode)>>,
+    ) -> Result<bool, SpineError> {
+        validate_filter(topic)?;
+
         // Check if we're already subscribed
         {
             let topics_read = self.topics.read().await;
             if topics_read.contains(topic) {
-                return Ok(());
+                return Ok(false);
+            }
+
+            // Track topics covered by a wildcard subscription without a redundant broker subscription
+            if let Some(filter) = topics_read.iter().find(|f| is_covered_by(topic, f)) {
+                info!(
+                    "Topic {} is covered by subscription {}, skipping broker subscribe",
+                    topic, filter
+                );
+                drop(topics_read);
+                self.topics.write().await.insert(topic.to_string());
+                return Ok(false);
             }
         }
 
+        // Add to our list of topics before subscribing, so a rejecting SubAck finds it
+        self.topics.write().await.insert(topic.to_string());
+
         // Subscribe to the topic
-        match self.client.subscribe(topic, self.mqtt_qos).await {
+        match self.send_subscribe(vec![topic.to_string()], ack).await {
             Ok(_) => {
-                // Add to our list of topics
-                let mut topics_write = self.topics.write().await;
-                topics_write.insert(topic.to_string());
-
                 info!("Subscribed to topic: {}", topic);
-                Ok(())
+                Ok(true)
             }
             Err(e) => {
+                // The request can only fail if the event loop is gone
+                self.topics.write().await.remove(topic);
                 error!("Failed to subscribe to topic {}: {:?}", topic, e);
-                Err(format!("Failed to subscribe: {:?}", e))
+                Err(SpineError::Disconnected)
             }
         }
     }
 
     /// Unsubscribe from a topic
-    pub async fn unsubscribe(&self, topic: &str) -> Result<(), String> {
-        // Check if we're subscribed to this topic
-        {
+    ///
+    /// Returns the topics that are no longer tracked. Topics covered by an unsubscribed
+    /// wildcard are handled according to `MQTT_WILDCARD_UNSUBSCRIBE`.
+    pub async fn unsubscribe(&self, topic: &str) -> Result<Vec<String>, SpineError> {
+        self.unsubscribe_with(topic, self.unsubscribe_covered).await
+    }
+
+    /// Unsubscribe from a topic, also unsubscribing the tracked topics it covers if
+    /// `remove_covered` is set
+    ///
+    /// Covered topics that are kept get a broker subscription of their own.
+    pub async fn unsubscribe_with(
+        &self,
+        topic: &str,
+        remove_covered: bool,
+    ) -> Result<Vec<String>, SpineError> {
+        let removed: Vec<String> = {
             let topics_read = self.topics.read().await;
             if !topics_read.contains(topic) {
-                return Ok(());
+                return Ok(Vec::new());
+            }
+
+            let mut removed = vec![topic.to_string()];
+            if remove_covered {
+                removed.extend(
+                    topics_read
+                        .iter()
+                        .filter(|t| is_covered_by(t, topic))
+                        .cloned(),
+                );
             }
+            removed
+        };
+        self.remove_subscriptions(removed, topic).await
+    }
+
+    /// Unsubscribe from every tracked topic matched by the MQTT filter `filter`, including
+    /// the filter itself, and return the removed topics
+    pub async fn unsubscribe_matching(&self, filter: &str) -> Result<Vec<String>, SpineError> {
+        validate_filter(filter)?;
+
+        let removed: Vec<String> = {
+            let topics_read = self.topics.read().await;
+            topics_read
+                .iter()
+                .filter(|t| *t == filter || is_covered_by(t, filter))
+                .cloned()
+                .collect()
+        };
+        self.remove_subscriptions(removed, filter).await
+    }
+
+    /// Stop tracking the `removed` topics and unsubscribe those with a broker subscription
+    /// of their own
+    async fn remove_subscriptions(
+        &self,
+        removed: Vec<String>,
+        filter: &str,
+    ) -> Result<Vec<String>, SpineError> {
+        if removed.is_empty() {
+            return Ok(removed);
         }
 
-        // Unsubscribe from the topic
-        match self.client.unsubscribe(topic).await {
-            Ok(_) => {
-                // Remove from our list of topics
-                let mut topics_write = self.topics.write().await;
+        // Covered topics have no broker subscription of their own
+        let subscribed: Vec<String> = {
+            let topics_read = self.topics.read().await;
+            removed
+                .iter()
+                .filter(|t| !topics_read.iter().any(|f| is_covered_by(t, f)))
+                .cloned()
+                .collect()
+        };
+        for topic in &subscribed {
+            if let Err(e) = self.client.unsubscribe(topic).await {
+                error!("Failed to unsubscribe from topic {}: {:?}", topic, e);
+                return Err(SpineError::Disconnected);
+            }
+        }
+
+        // Remove from our list of topics
+        {
+            let mut topics_write = self.topics.write().await;
+            for topic in &removed {
                 topics_write.remove(topic);
+            }
+        }
+        info!("Unsubscribed from topics: {}", removed.join(", "));
+
+        // Topics only covered by the removed subscriptions need their own broker subscription now
+        let uncovered: Vec<String> = {
+            let topics_read = self.topics.read().await;
+            topics_read
+                .iter()
+                .filter(|t| {
+                    removed.iter().any(|r| is_covered_by(t, r))
+                        && !topics_read.iter().any(|f| f != *t && is_covered_by(t, f))
+                })
+                .cloned()
+                .collect()
+        };
+        self.subscribe_uncovered(uncovered, filter).await;
+
+        Ok(removed)
+    }
+
+    /// Subscribe to topics that were covered by the removed subscription `filter`
+    async fn subscribe_uncovered(&self, uncovered: Vec<String>, filter: &str) {
+        if uncovered.is_empty() {
+            return;
+        }
 
-                info!("Unsubscribed from topic: {}", topic);
-                Ok(())
+        for batch in uncovered.chunks(self.subscribe_batch_size) {
+            match self.send_subscribe(batch.to_vec(), None).await {
+                Ok(_) => info!(
+                    "Subscribed to topics no longer covered by {}: {}",
+                    filter,
+                    batch.join(", ")
+                ),
+                Err(e) => {
+                    error!(
+                        "Failed to subscribe to topics uncovered by {}: {:?}",
+                        filter, e
+                    );
+                    return;
+                }
             }
-            Err(e) => {
-                error!("Failed to unsubscribe from topic {}: {:?}", topic, e);
-                Err(format!("Failed to unsubscribe: {:?}", e))
+        }
+    }
+
+    /// Subscribe to several topics, sending them in SUBSCRIBE packets of up to
+    /// `MQTT_SUBSCRIBE_BATCH_SIZE` topics
+    ///
+    /// Invalid topics are logged and skipped. Like `subscribe`, topics covered by a wildcard
+    /// subscription are tracked without a broker subscription of their own.
+    pub async fn subscribe_many(&self, topics: &[String]) -> Result<(), SpineError> {
+        let to_subscribe: Vec<String> = {
+            let mut topics_write = self.topics.write().await;
+            let mut to_subscribe = Vec::new();
+            for topic in topics {
+                if !valid_filter(topic) || has_control_chars(topic) {
+                    error!("Skipping invalid topic {}", sanitize_topic(topic));
+                    continue;
+                }
+                if topics_write.contains(topic) {
+                    continue;
+                }
+                if !topics_write.iter().any(|f| is_covered_by(topic, f)) {
+                    to_subscribe.push(topic.clone());
+                }
+                topics_write.insert(topic.clone());
             }
+            // Topics covered by a wildcard from the same call need no broker subscription
+            to_subscribe
+                .iter()
+                .filter(|t| !topics_write.iter().any(|f| is_covered_by(t, f)))
+                .cloned()
+                .collect()
+        };
+
+        for batch in to_subscribe.chunks(self.subscribe_batch_size) {
+            if let Err(e) = self.send_subscribe(batch.to_vec(), None).await {
+                error!("Failed to subscribe to {} topics: {:?}", batch.len(), e);
+                return Err(SpineError::Disconnected);
+            }
+            info!("Subscribed to topics: {}", batch.join(", "));
+        }
+
+        Ok(())
+    }
+
+    /// Queue a SUBSCRIBE packet for the given topics, remembering them to check the SubAck
+    ///
+    /// The SubAck's packet ID and first return code are passed to `ack`, if given.
+    async fn send_subscribe(
+        &self,
+        topics: Vec<String>,
+        ack: Option<oneshot::Sender<(u16, SubscribeReasonCode)>>,
+    ) -> Result<(), ClientError> {
+        let filters: Vec<SubscribeFilter> = topics
+            .iter()
+            .map(|topic| SubscribeFilter::new(topic.clone(), self.mqtt_qos))
+            .collect();
+
+        // The event loop matches sent packets to the queued topics in order, so no other
+        // request may be queued between recording the topics and sending the request
+        let _guard = self.subscribe_lock.lock().await;
+        self.queued_subscribes
+            .lock()
+            .unwrap()
+            .push_back(PendingSubscribe { topics, ack });
+        let result = self.client.subscribe_many(filters).await;
+        if result.is_err() {
+            self.queued_subscribes.lock().unwrap().pop_back();
+        }
+        result
+    }
+
+    /// Record that the event loop sent the oldest queued SUBSCRIBE request as packet `pkid`
+    pub fn subscribe_sent(&self, pkid: u16) {
+        if let Some(pending) = self.queued_subscribes.lock().unwrap().pop_front() {
+            self.inflight_subscribes
+                .lock()
+                .unwrap()
+                .insert(pkid, pending);
+        }
+    }
+
+    /// Forget the sent SUBSCRIBE packets after the connection failed, their SubAcks are lost
+    ///
+    /// Requests waiting for one of these SubAcks fail as disconnected.
+    pub fn clear_inflight_subscribes(&self) {
+        self.inflight_subscribes.lock().unwrap().clear();
+    }
+
+    /// Record a received QoS 2 message, starting its PubRec/PubRel/PubComp handshake
+    pub fn qos2_received(&self, pkid: u16) {
+        self.inflight_qos2
+            .lock()
+            .unwrap()
+            .insert(pkid, Instant::now());
+    }
+
+    /// Record the PubComp that completed the handshake of packet `pkid`
+    ///
+    /// Returns how long the handshake took, `None` if the packet was not tracked.
+    pub fn qos2_completed(&self, pkid: u16) -> Option<Duration> {
+        self.inflight_qos2
+            .lock()
+            .unwrap()
+            .remove(&pkid)
+            .map(|received_at| received_at.elapsed())
+    }
+
+    /// Forget the open QoS 2 handshakes after the broker discarded the session
+    ///
+    /// Returns the number of handshakes that were abandoned.
+    pub fn clear_inflight_qos2(&self) -> usize {
+        let mut inflight_qos2 = self.inflight_qos2.lock().unwrap();
+        let abandoned = inflight_qos2.len();
+        inflight_qos2.clear();
+        abandoned
+    }
+
+    /// Get the number of QoS 2 messages whose handshake hasn't completed yet
+    pub fn qos2_inflight(&self) -> usize {
+        self.inflight_qos2.lock().unwrap().len()
+    }
+
+    /// Check the broker's answer to a SUBSCRIBE packet and stop tracking the rejected topics
+    pub async fn handle_suback(&self, suback: SubAck) {
+        let Some(PendingSubscribe { topics, ack }) = self
+            .inflight_subscribes
+            .lock()
+            .unwrap()
+            .remove(&suback.pkid)
+        else {
+            return;
+        };
+
+        let rejected: Vec<&String> = topics
+            .iter()
+            .zip(&suback.return_codes)
+            .filter(|(_, code)| matches!(code, SubscribeReasonCode::Failure))
+            .map(|(topic, _)| topic)
+            .collect();
+
+        for topic in rejected {
+            warn!("Broker rejected the subscription to {}", topic);
+            self.topics.write().await.remove(topic);
+
+            // Topics the rejected wildcard would have covered need their own subscription
+            let uncovered: Vec<String> = {
+                let topics_read = self.topics.read().await;
+                topics_read
+                    .iter()
+                    .filter(|t| {
+                        is_covered_by(t, topic)
+                            && !topics_read.iter().any(|f| f != *t && is_covered_by(t, f))
+                    })
+                    .cloned()
+                    .collect()
+            };
+            self.subscribe_uncovered(uncovered, topic).await;
+        }
+
+        // Answer the waiting request once the rejected topics are no longer tracked
+        if let (Some(ack), Some(code)) = (ack, suback.return_codes.first()) {
+            let _ = ack.send((suback.pkid, *code));
         }
     }
 
@@ -105,19 +633,371 @@ impl MqttSubscriber {
         topics_read.iter().cloned().collect()
     }
 
-    /// Resubscribe to all topics
-    pub async fn resubscribe_to_topics(&self) {
-        let topics_to_resubscribe = self.get_topics().await;
+    /// Request resubscribing to all tracked topics after the broker lost the session
+    ///
+    /// The topics are resubscribed by a single background task, started with the first
+    /// request. Requests made while it resubscribes for an earlier connection are coalesced
+    /// into one more run, so repeated reconnects never pile up resubscribe tasks.
+    pub fn request_resubscribe(self: &Arc<Self>) {
+        if !self.resubscriber_started.swap(true, Ordering::Relaxed) {
+            let subscriber = Arc::clone(self);
+            tokio::spawn(async move {
+                loop {
+                    subscriber.resubscribe_requested.notified().await;
+                    subscriber.resubscribe_to_topics().await;
+                }
+            });
+        }
+        self.resubscribe_requested.notify_one();
+    }
+
+    /// Resubscribe to all tracked topics after the broker lost the session, in batches
+    ///
+    /// Topics covered by another tracked subscription stay without a broker subscription.
+    /// Stops as soon as the connection it started on is lost, even if the client already
+    /// reconnected, as the next connection resubscribes from the start.
+    async fn resubscribe_to_topics(&self) {
+        let connection = self.connection_count.load(Ordering::Relaxed);
+        let topics_to_resubscribe: Vec<String> = {
+            let topics_read = self.topics.read().await;
+            topics_read
+                .iter()
+                .filter(|t| !topics_read.iter().any(|f| f != *t && is_covered_by(t, f)))
+                .cloned()
+                .collect()
+        };
 
         if topics_to_resubscribe.is_empty() {
             return;
         }
 
-        for topic in topics_to_resubscribe {
-            match self.subscribe(&topic).await {
-                Ok(_) => info!("Resubscribed to topic: {}", topic),
-                Err(e) => error!("Failed to resubscribe to {}: {:?}", topic, e),
+        // With a rate limit, batches are at most one second's worth of topics
+        let total = topics_to_resubscribe.len();
+        let batch_size = match self.resubscribe_rate {
+            0 => self.subscribe_batch_size,
+            rate => self.subscribe_batch_size.min(rate),
+        };
+        info!(
+            "Resubscribing to {} topics in batches of {}",
+            total, batch_size
+        );
+
+        let started = Instant::now();
+        let mut last_progress = started;
+        let mut sent = 0;
+        for batch in topics_to_resubscribe.chunks(batch_size) {
+            // Spread the batches evenly, so `sent` topics take `sent / rate` seconds
+            if self.resubscribe_rate > 0 {
+                let due =
+                    started + Duration::from_secs_f64(sent as f64 / self.resubscribe_rate as f64);
+                tokio::time::sleep_until(due).await;
+            }
+
+            if !self.is_connected() || self.connection_count.load(Ordering::Relaxed) != connection {
+                warn!(
+                    "Connection lost after resubscribing to {} of {} topics, stopping",
+                    sent, total
+                );
+                return;
+            }
+
+            if let Err(e) = self.send_subscribe(batch.to_vec(), None).await {
+                error!("Failed to resubscribe to {} topics: {:?}", batch.len(), e);
+                return;
+            }
+            sent += batch.len();
+
+            if last_progress.elapsed() >= RESUBSCRIBE_PROGRESS_INTERVAL && sent < total {
+                info!("Resubscribed to {} of {} topics", sent, total);
+                last_progress = Instant::now();
+            }
+        }
+        info!(
+            "Resubscribed to {} topics in {:.1} seconds",
+            total,
+            started.elapsed().as_secs_f64()
+        );
+    }
+}
+
+/// Check if every topic matched by `topic` (a topic name or filter) is also matched by `filter`
+///
+/// Follows the MQTT wildcard rules: `+` matches exactly one level, `#` matches the parent
+/// level and any number of levels below it, and wildcards in the first level do not match
+/// topics starting with `$`. A topic is not covered by itself.
+pub fn is_covered_by(topic: &str, filter: &str) -> bool {
+    if topic == filter {
+        return false;
+    }
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut topic_levels = topic.split('/');
+    let mut filter_levels = filter.split('/');
+    loop {
+        match (topic_levels.next(), filter_levels.next()) {
+            // `#` covers everything below, including another `#`
+            (_, Some("#")) => return true,
+            // `#` in the topic is wider than any other filter level
+            (Some("#"), _) => return false,
+            // `+` covers any single level, including another `+`
+            (Some(_), Some("+")) => {}
+            (Some(topic_level), Some(filter_level)) => {
+                if topic_level != filter_level {
+                    return false;
+                }
             }
+            (None, None) => return true,
+            // The filter has more levels, or the topic is deeper than the filter
+            _ => return false,
         }
     }
 }
+
+/// Check that a topic filter can be subscribed to
+pub fn validate_filter(filter: &str) -> Result<(), SpineError> {
+    if !valid_filter(filter) || has_control_chars(filter) {
+        return Err(SpineError::InvalidTopic(
+            sanitize_topic(filter).into_owned(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_mqtt_configs;
+    use rumqttc::Request;
+
+    /// Create a subscriber whose requests stay queued in its unpolled event loop
+    async fn subscriber(topics: &[&str]) -> (MqttSubscriber, EventLoop) {
+        let (subscriber, mut event_loop) = MqttSubscriber::new(&load_mqtt_configs());
+        let topics: Vec<String> = topics.iter().map(|t| t.to_string()).collect();
+        subscriber.subscribe_many(&topics).await.unwrap();
+        event_loop.clean();
+        event_loop.pending.clear();
+        (subscriber, event_loop)
+    }
+
+    /// Take the requests queued since the last call, e.g. `unsubscribe lab/#`
+    fn sent_requests(event_loop: &mut EventLoop) -> Vec<String> {
+        event_loop.clean();
+        event_loop
+            .pending
+            .drain(..)
+            .flat_map(|request| match request {
+                Request::Subscribe(subscribe) => subscribe
+                    .filters
+                    .into_iter()
+                    .map(|filter| format!("subscribe {}", filter.path))
+                    .collect(),
+                Request::Unsubscribe(unsubscribe) => unsubscribe
+                    .topics
+                    .into_iter()
+                    .map(|topic| format!("unsubscribe {}", topic))
+                    .collect(),
+                request => vec![format!("{:?}", request)],
+            })
+            .collect()
+    }
+
+    async fn sorted_topics(subscriber: &MqttSubscriber) -> Vec<String> {
+        let mut topics = subscriber.get_topics().await;
+        topics.sort();
+        topics
+    }
+
+    #[test]
+    fn is_covered_by_follows_the_wildcard_rules() {
+        let cases = [
+            // `+` matches exactly one level
+            ("lab/temp", "lab/+", true),
+            ("lab/room1/temp", "lab/+/temp", true),
+            ("lab/room1/temp", "lab/+", false),
+            ("lab", "lab/+", false),
+            ("lab/+", "+/+", true),
+            ("lab/#", "lab/+", false),
+            // `#` matches any number of levels below
+            ("lab/temp", "lab/#", true),
+            ("lab/room1/temp", "lab/#", true),
+            ("lab/+/temp", "lab/#", true),
+            ("lab/room1/#", "lab/#", true),
+            ("office/temp", "lab/#", false),
+            ("lab/#", "lab/room1/#", false),
+            // `#` also matches the parent level
+            ("lab", "lab/#", true),
+            ("lab", "#", true),
+            // Topics starting with `$` are not matched by a leading wildcard
+            ("$SYS/broker/uptime", "#", false),
+            ("$SYS/broker", "+/broker", false),
+            ("$SYS/broker/uptime", "$SYS/#", true),
+            ("$SYS/broker", "$SYS/+", true),
+            // Exact names and topics are not covered by themselves
+            ("lab/temp", "lab/temp", false),
+            ("lab/#", "lab/#", false),
+            ("lab/temp", "lab/humidity", false),
+            ("lab/temp/", "lab/temp", false),
+        ];
+
+        for (topic, filter, expected) in cases {
+            assert_eq!(
+                is_covered_by(topic, filter),
+                expected,
+                "is_covered_by({:?}, {:?})",
+                topic,
+                filter
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn wildcard_unsubscribe_keeps_covered_topics() {
+        let (subscriber, mut event_loop) =
+            subscriber(&["lab/#", "lab/room1/temp", "lab/room2/temp", "office/temp"]).await;
+
+        let removed = subscriber.unsubscribe_with("lab/#", false).await.unwrap();
+
+        assert_eq!(removed, vec!["lab/#"]);
+        assert_eq!(
+            sorted_topics(&subscriber).await,
+            vec!["lab/room1/temp", "lab/room2/temp", "office/temp"]
+        );
+        // The covered topics had no broker subscription of their own until now
+        let mut requests = sent_requests(&mut event_loop);
+        requests.sort();
+        assert_eq!(
+            requests,
+            vec![
+                "subscribe lab/room1/temp",
+                "subscribe lab/room2/temp",
+                "unsubscribe lab/#",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn wildcard_unsubscribe_removes_covered_topics() {
+        let (subscriber, mut event_loop) =
+            subscriber(&["lab/#", "lab/room1/temp", "lab/room2/temp", "office/temp"]).await;
+
+        let mut removed = subscriber.unsubscribe_with("lab/#", true).await.unwrap();
+        removed.sort();
+
+        assert_eq!(removed, vec!["lab/#", "lab/room1/temp", "lab/room2/temp"]);
+        assert_eq!(sorted_topics(&subscriber).await, vec!["office/temp"]);
+        assert_eq!(sent_requests(&mut event_loop), vec!["unsubscribe lab/#"]);
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_matching_removes_matched_topics() {
+        let (subscriber, mut event_loop) = subscriber(&[
+            "lab/room1/temp",
+            "lab/room2/temp",
+            "lab/room1/humidity",
+            "office/temp",
+        ])
+        .await;
+
+        let mut removed = subscriber.unsubscribe_matching("lab/+/temp").await.unwrap();
+        removed.sort();
+
+        assert_eq!(removed, vec!["lab/room1/temp", "lab/room2/temp"]);
+        assert_eq!(
+            sorted_topics(&subscriber).await,
+            vec!["lab/room1/humidity", "office/temp"]
+        );
+        let mut requests = sent_requests(&mut event_loop);
+        requests.sort();
+        assert_eq!(
+            requests,
+            vec!["unsubscribe lab/room1/temp", "unsubscribe lab/room2/temp"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_matching_keeps_topics_covered_by_remaining_wildcards() {
+        let (subscriber, mut event_loop) = subscriber(&[
+            "lab/#",
+            "lab/+/temp",
+            "lab/room1/temp",
+            "lab/room1/humidity",
+        ])
+        .await;
+
+        let mut removed = subscriber.unsubscribe_matching("lab/+/temp").await.unwrap();
+        removed.sort();
+
+        assert_eq!(removed, vec!["lab/+/temp", "lab/room1/temp"]);
+        assert_eq!(
+            sorted_topics(&subscriber).await,
+            vec!["lab/#", "lab/room1/humidity"]
+        );
+        // Everything removed was covered by `lab/#`, which stays subscribed
+        assert!(sent_requests(&mut event_loop).is_empty());
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_of_untracked_wildcard_does_nothing() {
+        let (subscriber, mut event_loop) = subscriber(&["lab/room1/temp"]).await;
+
+        let removed = subscriber.unsubscribe_with("lab/#", true).await.unwrap();
+
+        assert!(removed.is_empty());
+        assert_eq!(sorted_topics(&subscriber).await, vec!["lab/room1/temp"]);
+        assert!(sent_requests(&mut event_loop).is_empty());
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_matching_rejects_invalid_filters() {
+        let (subscriber, _event_loop) = subscriber(&["lab/room1/temp"]).await;
+
+        assert!(matches!(
+            subscriber.unsubscribe_matching("lab/#/temp").await,
+            Err(SpineError::InvalidTopic(_))
+        ));
+        assert_eq!(sorted_topics(&subscriber).await, vec!["lab/room1/temp"]);
+    }
+
+    #[tokio::test]
+    async fn repeated_reconnects_share_one_resubscribe_task() {
+        let (subscriber, mut event_loop) = subscriber(&["lab/#", "office/temp"]).await;
+        let subscriber = Arc::new(subscriber);
+        let tasks = tokio::runtime::Handle::current()
+            .metrics()
+            .num_alive_tasks();
+
+        for _ in 0..5 {
+            subscriber.update_connection_status(false);
+            subscriber.update_connection_status(true);
+            subscriber.request_resubscribe();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(
+            tokio::runtime::Handle::current()
+                .metrics()
+                .num_alive_tasks(),
+            tasks + 1
+        );
+        // The requests made before the task ran are coalesced into a single run
+        let mut requests = sent_requests(&mut event_loop);
+        requests.sort();
+        assert_eq!(requests, vec!["subscribe lab/#", "subscribe office/temp"]);
+
+        // A later reconnect is handled by the same task
+        subscriber.update_connection_status(false);
+        subscriber.update_connection_status(true);
+        subscriber.request_resubscribe();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(
+            tokio::runtime::Handle::current()
+                .metrics()
+                .num_alive_tasks(),
+            tasks + 1
+        );
+        assert_eq!(sent_requests(&mut event_loop).len(), 2);
+    }
+}
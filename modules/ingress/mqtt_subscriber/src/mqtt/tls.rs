@@ -0,0 +1,152 @@
+//! TLS configuration for the MQTT connection
+
+use rumqttc::tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use rumqttc::tokio_rustls::rustls::client::WebPkiServerVerifier;
+use rumqttc::tokio_rustls::rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms,
+};
+use rumqttc::tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rumqttc::tokio_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+};
+use rumqttc::TlsConfiguration;
+use std::sync::Arc;
+
+/// Create a TLS configuration that accepts any server certificate
+///
+/// Only meant for development brokers with self-signed certificates: the connection is
+/// still encrypted, but the broker's identity is not verified.
+pub fn insecure_tls_config() -> TlsConfiguration {
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(NoCertificateVerification {
+            algorithms: ring::default_provider().signature_verification_algorithms,
+        }))
+        .with_no_client_auth();
+
+    TlsConfiguration::Rustls(Arc::new(config))
+}
+
+/// Create a TLS configuration verifying the server certificate for `server_name`
+///
+/// Used when connecting through the local end of the proxy tunnel: the client connects to
+/// the loopback address, but the certificate must still be valid for the broker's hostname.
+/// The certificate chain is verified against the system roots, like the default
+/// configuration.
+pub fn proxied_tls_config(server_name: &str) -> Result<TlsConfiguration, String> {
+    let server_name = ServerName::try_from(server_name.to_string())
+        .map_err(|e| format!("Invalid TLS server name {:?}: {}", server_name, e))?;
+
+    let mut roots = RootCertStore::empty();
+    let certs = rustls_native_certs::load_native_certs()
+        .map_err(|e| format!("Failed to load the system root certificates: {}", e))?;
+    roots.add_parsable_certificates(certs);
+    let verifier = WebPkiServerVerifier::builder(Arc::new(roots))
+        .build()
+        .map_err(|e| format!("Failed to create the TLS certificate verifier: {}", e))?;
+
+    let config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(FixedServerNameVerification {
+            inner: verifier,
+            server_name,
+        }))
+        .with_no_client_auth();
+
+    Ok(TlsConfiguration::Rustls(Arc::new(config)))
+}
+
+/// Certificate verifier checking the certificate for a fixed server name
+///
+/// The name the client connected to is ignored, everything else is verified as usual.
+#[derive(Debug)]
+struct FixedServerNameVerification {
+    inner: Arc<WebPkiServerVerifier>,
+    server_name: ServerName<'static>,
+}
+
+impl ServerCertVerifier for FixedServerNameVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            &self.server_name,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Certificate verifier skipping the certificate chain and hostname checks
+///
+/// Handshake signatures are still checked, so the server must own the presented certificate.
+#[derive(Debug)]
+struct NoCertificateVerification {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,123 @@
+//! Safe handling of topic names with non-printable characters
+
+use rumqttc::valid_topic;
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Topic that messages with a malformed topic name are processed under
+pub const MALFORMED_TOPIC: &str = "__malformed__";
+
+/// Check whether a received topic name is malformed
+///
+/// MQTT forbids empty topic names, null characters and wildcards in the topic of a publish,
+/// but misbehaving bridges still send them.
+pub fn is_malformed_topic(topic: &str) -> bool {
+    topic.is_empty() || topic.contains('\0') || !valid_topic(topic)
+}
+
+/// Check whether a topic contains control characters
+///
+/// MQTT allows most of them in topic names, but some buggy devices send topics with stray
+/// control characters that would corrupt logs and Kafka keys.
+pub fn has_control_chars(topic: &str) -> bool {
+    topic.chars().any(char::is_control)
+}
+
+/// Escape the control characters in a topic (as `\u{1b}`) for use in logs and Kafka keys
+///
+/// Returns the topic unchanged if it has no control characters.
+pub fn sanitize_topic(topic: &str) -> Cow<'_, str> {
+    if !has_control_chars(topic) {
+        return Cow::Borrowed(topic);
+    }
+
+    let mut sanitized = String::with_capacity(topic.len() + 8);
+    for c in topic.chars() {
+        if c.is_control() {
+            let _ = write!(sanitized, "\\u{{{:x}}}", c as u32);
+        } else {
+            sanitized.push(c);
+        }
+    }
+    Cow::Owned(sanitized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_topic_escapes_control_characters() {
+        assert_eq!(sanitize_topic("lab/\u{1b}[31mtemp"), "lab/\\u{1b}[31mtemp");
+        assert_eq!(sanitize_topic("lab/temp\n"), "lab/temp\\u{a}");
+        assert_eq!(sanitize_topic("lab/\u{7f}/\u{0}"), "lab/\\u{7f}/\\u{0}");
+    }
+
+    #[test]
+    fn sanitize_topic_borrows_clean_topics() {
+        assert!(matches!(sanitize_topic("lab/room1/temp"), Cow::Borrowed(_)));
+        assert!(matches!(sanitize_topic("lab/räume/temp"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn has_control_chars_detects_stray_characters() {
+        assert!(has_control_chars("lab/\u{7}temp"));
+        assert!(!has_control_chars("lab/temp"));
+    }
+
+    #[test]
+    fn is_malformed_topic_rejects_pathological_topics() {
+        let long_wildcard = format!("{}/#", "level/".repeat(10_000));
+        let cases = [
+            ("", true),
+            ("\0", true),
+            ("lab/\0/temp", true),
+            ("lab/temp\0", true),
+            ("lab/+/temp", true),
+            ("lab/#", true),
+            ("#", true),
+            ("lab/temp+", true),
+            (long_wildcard.as_str(), true),
+        ];
+
+        for (topic, expected) in cases {
+            assert_eq!(is_malformed_topic(topic), expected, "{:?}", topic);
+        }
+    }
+
+    #[test]
+    fn is_malformed_topic_accepts_unusual_but_valid_topics() {
+        let long_topic = "level/".repeat(10_000) + "temp";
+        let cases = [
+            "/",
+            "//",
+            "/lab/temp",
+            "lab/temp/",
+            "lab//temp",
+            " ",
+            "lab/room 1/temp",
+            "$SYS/broker/uptime",
+            "labor/räume/temperatur",
+            "研究室/温度",
+            "sensors/🌡️",
+            "lab/\u{1b}[31mtemp",
+            long_topic.as_str(),
+        ];
+
+        for topic in cases {
+            assert!(!is_malformed_topic(topic), "{:?}", topic);
+        }
+    }
+
+    #[test]
+    fn sanitize_topic_keeps_non_ascii_and_long_topics() {
+        let long_topic = "level/".repeat(10_000) + "\u{0}";
+
+        assert_eq!(sanitize_topic("研究室/温度"), "研究室/温度");
+        assert_eq!(sanitize_topic("sensors/🌡️\u{7}"), "sensors/🌡️\\u{7}");
+        assert_eq!(
+            sanitize_topic(&long_topic),
+            "level/".repeat(10_000) + "\\u{0}"
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! Per-topic service classes used for the metrics rollups
+
+use rumqttc::matches;
+
+use crate::models::TopicClass;
+
+/// Topic filter to class rules, first match wins
+pub struct TopicClasses {
+    rules: Vec<(String, TopicClass)>,
+}
+
+impl TopicClasses {
+    /// Create the rules from `(topic filter, class)` pairs
+    pub fn new(rules: Vec<(String, TopicClass)>) -> Self {
+        Self { rules }
+    }
+
+    /// Get the class of a topic, normal if no rule matches
+    pub fn class_for(&self, topic: &str) -> TopicClass {
+        self.rules
+            .iter()
+            .find(|(filter, _)| matches(topic, filter))
+            .map_or(TopicClass::Normal, |(_, class)| *class)
+    }
+}
@@ -0,0 +1,122 @@
+//! Correlation IDs propagated from MQTT payloads to Kafka for end-to-end tracing
+
+use serde_json::Value;
+
+use crate::processor::message_id::random_uuid;
+
+/// Where the correlation ID set by upstream systems is read from
+///
+/// The client speaks MQTT 3.1.1, which has no `Correlation Data` property, so payload fields
+/// are the only source so far. A property source would be another variant here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorrelationIdSource {
+    /// Field of a JSON payload, `CORRELATION_ID_FIELD`
+    PayloadField(String),
+}
+
+impl CorrelationIdSource {
+    /// Check if the source needs the payload parsed as JSON
+    pub fn needs_json(&self) -> bool {
+        match self {
+            CorrelationIdSource::PayloadField(_) => true,
+        }
+    }
+
+    /// Read the upstream correlation ID of a message, if it has one
+    ///
+    /// String and number values are used as they are, any other value (or an empty string)
+    /// counts as missing.
+    pub fn extract(&self, payload_json: Option<&Value>) -> Option<String> {
+        match self {
+            CorrelationIdSource::PayloadField(field) => {
+                match payload_json.and_then(|value| value.get(field)) {
+                    Some(Value::String(id)) if !id.is_empty() => Some(id.clone()),
+                    Some(Value::Number(id)) => Some(id.to_string()),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Take the correlation ID from the source, or generate one if it is missing
+///
+/// Returns `None` only if no ID could be generated either.
+pub fn correlation_id(
+    source: &CorrelationIdSource,
+    payload_json: Option<&Value>,
+) -> Option<String> {
+    source.extract(payload_json).or_else(random_uuid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field(name: &str) -> CorrelationIdSource {
+        CorrelationIdSource::PayloadField(name.to_string())
+    }
+
+    /// Check that `id` is formatted as a version 4 UUID
+    fn assert_uuid_v4(id: &str) {
+        let groups: Vec<&str> = id.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|g| g.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12],
+            "{}",
+            id
+        );
+        assert!(id.chars().all(|c| c == '-' || c.is_ascii_hexdigit()));
+        assert!(groups[2].starts_with('4'));
+    }
+
+    #[test]
+    fn string_field_is_used_as_is() {
+        let payload = json!({"trace_id": "abc-123", "temp": 21.5});
+
+        assert_eq!(
+            correlation_id(&field("trace_id"), Some(&payload)),
+            Some("abc-123".to_string())
+        );
+    }
+
+    #[test]
+    fn number_field_is_used_as_string() {
+        let payload = json!({"trace_id": 42});
+
+        assert_eq!(
+            correlation_id(&field("trace_id"), Some(&payload)),
+            Some("42".to_string())
+        );
+    }
+
+    #[test]
+    fn other_field_values_fall_back_to_uuid() {
+        for value in [
+            json!(""),
+            json!(null),
+            json!(true),
+            json!([1]),
+            json!({"id": "x"}),
+        ] {
+            let payload = json!({ "trace_id": value });
+
+            let id = correlation_id(&field("trace_id"), Some(&payload)).unwrap();
+
+            assert_uuid_v4(&id);
+        }
+    }
+
+    #[test]
+    fn missing_field_falls_back_to_uuid() {
+        let payload = json!({"temp": 21.5});
+
+        let first = correlation_id(&field("trace_id"), Some(&payload)).unwrap();
+        let second = correlation_id(&field("trace_id"), None).unwrap();
+
+        assert_uuid_v4(&first);
+        assert_uuid_v4(&second);
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,25 @@
+//! Per-topic delivery guarantees towards the output sinks
+
+use rumqttc::matches;
+
+use crate::models::DeliveryGuarantee;
+
+/// Topic filter to delivery guarantee rules, first match wins
+pub struct DeliveryGuarantees {
+    rules: Vec<(String, DeliveryGuarantee)>,
+}
+
+impl DeliveryGuarantees {
+    /// Create the rules from `(topic filter, guarantee)` pairs
+    pub fn new(rules: Vec<(String, DeliveryGuarantee)>) -> Self {
+        Self { rules }
+    }
+
+    /// Get the guarantee for a topic, best effort if no rule matches
+    pub fn guarantee_for(&self, topic: &str) -> DeliveryGuarantee {
+        self.rules
+            .iter()
+            .find(|(filter, _)| matches(topic, filter))
+            .map_or(DeliveryGuarantee::BestEffort, |(_, guarantee)| *guarantee)
+    }
+}
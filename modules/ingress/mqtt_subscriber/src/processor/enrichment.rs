@@ -0,0 +1,148 @@
+//! Message enrichment from a static lookup table keyed by sensor ID
+
+use log::{error, info};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Metadata fields for a single sensor
+pub type SensorMetadata = Map<String, Value>;
+
+/// Lookup table of sensor metadata, loaded from a CSV or JSON file
+///
+/// CSV files must have a header row with a `sensor_id` column; all other columns become
+/// metadata fields. JSON files contain either an object keyed by sensor ID or an array of
+/// objects with a `sensor_id` field.
+pub struct EnrichmentTable {
+    path: String,
+    entries: RwLock<HashMap<String, SensorMetadata>>,
+}
+
+impl EnrichmentTable {
+    /// Load the table from the given file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let entries = read_table(path)?;
+        info!(
+            "Loaded enrichment table {} with {} sensors",
+            path,
+            entries.len()
+        );
+
+        Ok(Self {
+            path: path.to_string(),
+            entries: RwLock::new(entries),
+        })
+    }
+
+    /// Re-read the table from its file, keeping the current entries if that fails
+    pub fn reload(&self) {
+        match read_table(&self.path) {
+            Ok(entries) => {
+                info!(
+                    "Reloaded enrichment table {} with {} sensors",
+                    self.path,
+                    entries.len()
+                );
+                *self.entries.write().unwrap() = entries;
+            }
+            Err(e) => error!(
+                "Failed to reload enrichment table, keeping old entries: {}",
+                e
+            ),
+        }
+    }
+
+    /// Get the metadata for a sensor, if it is in the table
+    pub fn lookup(&self, sensor_id: &str) -> Option<SensorMetadata> {
+        self.entries.read().unwrap().get(sensor_id).cloned()
+    }
+}
+
+/// Reload the table whenever the process receives SIGHUP
+pub fn reload_on_sighup(table: Arc<EnrichmentTable>) {
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!("Failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading enrichment table");
+            table.reload();
+        }
+    });
+}
+
+/// Read and parse a table file based on its extension
+fn read_table(path: &str) -> Result<HashMap<String, SensorMetadata>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read enrichment table {}: {}", path, e))?;
+
+    if path.ends_with(".json") {
+        parse_json_table(&content)
+    } else {
+        parse_csv_table(&content)
+    }
+    .map_err(|e| format!("Invalid enrichment table {}: {}", path, e))
+}
+
+/// Parse a CSV table with a `sensor_id` column
+fn parse_csv_table(content: &str) -> Result<HashMap<String, SensorMetadata>, String> {
+    let mut reader = csv::Reader::from_reader(content.as_bytes());
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let id_column = headers
+        .iter()
+        .position(|header| header == "sensor_id")
+        .ok_or("missing `sensor_id` column")?;
+
+    let mut entries = HashMap::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let Some(sensor_id) = record.get(id_column) else {
+            continue;
+        };
+
+        let metadata = headers
+            .iter()
+            .zip(record.iter())
+            .enumerate()
+            .filter(|(column, _)| *column != id_column)
+            .map(|(_, (header, value))| (header.to_string(), Value::String(value.to_string())))
+            .collect();
+        entries.insert(sensor_id.to_string(), metadata);
+    }
+
+    Ok(entries)
+}
+
+/// Parse a JSON table, either an object keyed by sensor ID or an array of objects
+fn parse_json_table(content: &str) -> Result<HashMap<String, SensorMetadata>, String> {
+    let table: Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+
+    match table {
+        Value::Object(by_id) => by_id
+            .into_iter()
+            .map(|(sensor_id, metadata)| match metadata {
+                Value::Object(metadata) => Ok((sensor_id, metadata)),
+                _ => Err(format!("metadata for {} is not an object", sensor_id)),
+            })
+            .collect(),
+        Value::Array(rows) => rows
+            .into_iter()
+            .map(|row| {
+                let Value::Object(mut metadata) = row else {
+                    return Err("array entries must be objects".to_string());
+                };
+                match metadata.remove("sensor_id") {
+                    Some(Value::String(sensor_id)) => Ok((sensor_id, metadata)),
+                    _ => Err("array entry without a string `sensor_id`".to_string()),
+                }
+            })
+            .collect(),
+        _ => Err("expected an object or an array".to_string()),
+    }
+}
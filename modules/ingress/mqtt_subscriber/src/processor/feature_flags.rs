@@ -0,0 +1,111 @@
+//! Runtime behaviors toggled by a central feature-flag service
+
+use arc_swap::ArcSwap;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::config::FeatureFlagConfig;
+
+/// Behaviors that can be switched off at runtime, all enabled by default
+///
+/// A flag only disables a configured behavior, it doesn't enable one that isn't configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FeatureFlags {
+    /// Log payload samples at `PAYLOAD_SAMPLE_LOG_RATE`
+    pub payload_sampling: bool,
+    /// Send failed messages to the dead-letter topic
+    pub dead_lettering: bool,
+    /// Wrap `json` records in the `SensorData` envelope, otherwise forward them as `raw`
+    pub envelope: bool,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        Self {
+            payload_sampling: true,
+            dead_lettering: true,
+            envelope: true,
+        }
+    }
+}
+
+/// Start polling the feature-flag service, if configured
+///
+/// Returns the current flags, read once per message so a change applies between messages.
+/// Until the service answers, and while it is unreachable or returns invalid flags, the
+/// defaults or the last flags received are kept.
+pub fn start_feature_flags(config: Option<FeatureFlagConfig>) -> Arc<ArcSwap<FeatureFlags>> {
+    let flags = Arc::new(ArcSwap::from_pointee(FeatureFlags::default()));
+    let Some(config) = config else {
+        return flags;
+    };
+    let client = match reqwest::Client::builder().timeout(config.timeout).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!(
+                "Failed to create feature-flag client, using the defaults: {}",
+                e
+            );
+            return flags;
+        }
+    };
+
+    info!(
+        "Polling feature flags from {} every {} seconds",
+        config.url,
+        config.interval.as_secs()
+    );
+
+    let current = Arc::clone(&flags);
+    tokio::spawn(async move {
+        let mut interval_timer = tokio::time::interval(config.interval);
+        // Only log the first failure of an outage
+        let mut reachable = true;
+
+        loop {
+            interval_timer.tick().await;
+
+            match fetch_flags(&client, &config.url).await {
+                Ok(flags) => {
+                    if !reachable {
+                        info!("Feature-flag service reachable again");
+                        reachable = true;
+                    }
+                    if flags != **current.load() {
+                        info!("Feature flags changed: {:?}", flags);
+                        current.store(Arc::new(flags));
+                    } else {
+                        debug!("Feature flags unchanged");
+                    }
+                }
+                Err(e) => {
+                    if reachable {
+                        warn!(
+                            "Failed to fetch feature flags, keeping {:?}: {}",
+                            **current.load(),
+                            e
+                        );
+                        reachable = false;
+                    }
+                }
+            }
+        }
+    });
+
+    flags
+}
+
+/// Fetch the flags, a JSON object where missing flags are enabled
+async fn fetch_flags(client: &reqwest::Client, url: &str) -> Result<FeatureFlags, String> {
+    let response = client.get(url).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "feature-flag service returned {}",
+            response.status()
+        ));
+    }
+    let body = response.bytes().await.map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map_err(|e| format!("invalid feature flags: {}", e))
+}
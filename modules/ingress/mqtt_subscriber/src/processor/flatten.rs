@@ -0,0 +1,45 @@
+//! Flattening of nested JSON payloads into dot-delimited keys
+
+use serde_json::{Map, Value};
+
+/// Flatten a JSON object or array payload, e.g. `{"a": {"b": [1, 2]}}` becomes
+/// `{"a.b.0": 1, "a.b.1": 2}`
+///
+/// Returns `None` if the payload is not a JSON object or array, so it can be forwarded as is.
+pub fn flatten_json(payload: &[u8]) -> Option<Vec<u8>> {
+    let value: Value = serde_json::from_slice(payload).ok()?;
+    if !value.is_object() && !value.is_array() {
+        return None;
+    }
+
+    let mut flattened = Map::new();
+    flatten_into(&mut flattened, None, value);
+    serde_json::to_vec(&flattened).ok()
+}
+
+/// Add the leaves of `value` to `flattened`, prefixing their keys with `prefix`
+fn flatten_into(flattened: &mut Map<String, Value>, prefix: Option<&str>, value: Value) {
+    let key = |name: &str| match prefix {
+        Some(prefix) => format!("{}.{}", prefix, name),
+        None => name.to_string(),
+    };
+
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            for (name, value) in fields {
+                flatten_into(flattened, Some(&key(&name)), value);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (index, value) in items.into_iter().enumerate() {
+                flatten_into(flattened, Some(&key(&index.to_string())), value);
+            }
+        }
+        // Scalars and empty containers are kept as leaves
+        leaf => {
+            if let Some(prefix) = prefix {
+                flattened.insert(prefix.to_string(), leaf);
+            }
+        }
+    }
+}
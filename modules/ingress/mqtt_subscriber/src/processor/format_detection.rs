@@ -0,0 +1,85 @@
+//! Detection of the encoding of binary payloads by sniffing their bytes
+
+use serde::de::IgnoredAny;
+
+use crate::models::PayloadFormat;
+
+/// Maximum number of top-level protobuf fields checked before a payload is accepted
+const MAX_PROTOBUF_FIELDS: usize = 1_000;
+
+/// Detect the encoding of a payload, `Binary` if it is none of the known formats
+///
+/// Valid JSON is detected first, then self-described CBOR, a payload that parses as a
+/// sequence of protobuf fields, and CBOR starting with a map or array.
+pub fn detect_format(payload: &[u8]) -> PayloadFormat {
+    if is_json(payload) {
+        PayloadFormat::Json
+    } else if payload.starts_with(&[0xd9, 0xd9, 0xf7]) {
+        PayloadFormat::Cbor
+    } else if is_protobuf(payload) {
+        PayloadFormat::Protobuf
+    } else if matches!(payload.first(), Some(0x80..=0xbf)) {
+        PayloadFormat::Cbor
+    } else {
+        PayloadFormat::Binary
+    }
+}
+
+/// Check if a payload is a JSON document, only parsing it if it starts like one
+fn is_json(payload: &[u8]) -> bool {
+    let starts_like_json = payload
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| {
+            matches!(
+                byte,
+                b'{' | b'[' | b'"' | b'-' | b'0'..=b'9' | b't' | b'f' | b'n'
+            )
+        });
+    starts_like_json && serde_json::from_slice::<IgnoredAny>(payload).is_ok()
+}
+
+/// Check if a payload is a sequence of well-formed protobuf fields up to its last byte
+fn is_protobuf(payload: &[u8]) -> bool {
+    let mut rest = payload;
+    let mut fields = 0;
+    while !rest.is_empty() && fields < MAX_PROTOBUF_FIELDS {
+        let Some(key) = read_varint(&mut rest) else {
+            return false;
+        };
+        if key >> 3 == 0 {
+            return false;
+        }
+        let skipped = match key & 0x7 {
+            // Varint
+            0 => read_varint(&mut rest).map(|_| 0),
+            // 64-bit
+            1 => Some(8),
+            // Length-delimited
+            2 => read_varint(&mut rest).and_then(|len| usize::try_from(len).ok()),
+            // 32-bit
+            5 => Some(4),
+            // Groups are deprecated, other wire types are invalid
+            _ => None,
+        };
+        match skipped {
+            Some(len) if len <= rest.len() => rest = &rest[len..],
+            _ => return false,
+        }
+        fields += 1;
+    }
+    !payload.is_empty()
+}
+
+/// Read a base 128 varint from the start of `bytes`, advancing past it
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
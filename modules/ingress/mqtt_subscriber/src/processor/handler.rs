@@ -1,143 +1,1599 @@
 //! Message processing handlers
 
-use log::{debug, error, info};
-use rumqttc::{Event, EventLoop, Packet};
+use arc_swap::ArcSwap;
+use bytes::Bytes;
+use log::{debug, error, info, warn};
+use rumqttc::mqttbytes::Error as MqttBytesError;
+use rumqttc::{ConnectionError, Event, EventLoop, Outgoing, Packet, Publish, QoS, StateError};
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::RwLock;
 
+use crate::config::{generate_client_id, with_client_id, ProcessorConfig};
 use crate::kafka::producer::KafkaProducer;
-use crate::metrics::MessageMetrics;
-use crate::models::{MqttMessage, SensorData};
+use crate::metrics::{LifetimeMetrics, LoadGauges, MessageMetrics, MetricsSampler, TopicMetrics};
+use crate::models::{
+    DeadLetterReason, DeliveryGuarantee, MqttMessage, OutputRecord, PayloadFormat, ProcessingError,
+    SensorData, SerializationFormat,
+};
 use crate::mqtt::subscriber::MqttSubscriber;
+use crate::mqtt::topic::{is_malformed_topic, sanitize_topic, MALFORMED_TOPIC};
+use crate::processor::correlation_id::{correlation_id, CorrelationIdSource};
+use crate::processor::enrichment::{reload_on_sighup, EnrichmentTable};
+use crate::processor::feature_flags::{start_feature_flags, FeatureFlags};
+use crate::processor::flatten::flatten_json;
+use crate::processor::format_detection::detect_format;
+use crate::processor::json_depth::exceeds_depth;
+use crate::processor::message_id::MessageIdStrategy;
+use crate::processor::queue::{Lane, MessageQueue};
+use crate::processor::redelivery::RedeliveryTracker;
+use crate::processor::reorder::ReorderBuffer;
+use crate::processor::rules::ProcessingRules;
+use crate::processor::sensor_id::SensorIdTemplate;
+use crate::processor::validation::ValidationClient;
+#[cfg(feature = "wasm")]
+use crate::processor::wasm::WasmTransform;
+use crate::sink::MessageSink;
+
+/// How often the depth of the processing queue is sampled
+const QUEUE_DEPTH_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the message rate of the scaling hint is sampled
+const THROUGHPUT_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Result of successfully processing a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingOutcome {
+    /// The message was delivered to the output sinks, with the time from its measurement
+    /// to the completed delivery if the payload carried a measurement time
+    Delivered {
+        end_to_end_latency: Option<Duration>,
+        /// Whether the message arrived too late for the reorder buffer to keep it in order
+        late: bool,
+    },
+    /// The message had an empty payload and was dropped on purpose
+    DroppedEmpty,
+}
+
+/// Shared handles needed to process a single message
+pub struct ProcessorContext {
+    mqtt_subscriber: Arc<MqttSubscriber>,
+    kafka_producer: Arc<KafkaProducer>,
+    sink: Arc<dyn MessageSink>,
+    metrics: Arc<RwLock<MessageMetrics>>,
+    load: Arc<LoadGauges>,
+    // Counters every message updates, also those not sampled for the windowed metrics
+    lifetime_metrics: Arc<LifetimeMetrics>,
+    topic_metrics: Arc<TopicMetrics>,
+    metrics_sampler: MetricsSampler,
+    #[cfg(feature = "wasm")]
+    wasm_transform: Option<WasmTransform>,
+    enrichment_table: Option<Arc<EnrichmentTable>>,
+    drop_empty_payloads: bool,
+    rules: Arc<ArcSwap<ProcessingRules>>,
+    feature_flags: Arc<ArcSwap<FeatureFlags>>,
+    sensor_timestamp_field: Option<String>,
+    sensor_id_from_topic: Option<SensorIdTemplate>,
+    flatten_json: bool,
+    detect_payload_format: bool,
+    message_id_strategy: MessageIdStrategy,
+    correlation_id_source: Option<CorrelationIdSource>,
+    payload_sample_log_rate: f64,
+    max_json_depth: usize,
+    validation: Option<ValidationClient>,
+    redeliveries: Option<RedeliveryTracker>,
+    reorder: Option<Arc<ReorderBuffer>>,
+    max_redeliveries: u32,
+    delivery_retries: u32,
+    delivery_retry_backoff: Duration,
+}
 
 /// Start the MQTT message processor
+///
+/// With `config.workers` set to 0 every message is processed in its own spawned task.
+/// Otherwise a fixed pool of worker tasks consumes messages from a bounded queue, and the
+/// queue's drop policy decides what happens when it is full.
+///
+/// Reconnects are handled by polling the same event loop again, so the queue and workers
+/// are created once and kept for the lifetime of the service rather than per connection.
+///
+/// The topic-based `rules` are read for every message, so they can be swapped at runtime.
 pub async fn start_message_processor(
     mut event_loop: EventLoop,
     mqtt_subscriber: Arc<MqttSubscriber>,
     kafka_producer: Arc<KafkaProducer>,
+    sink: Arc<dyn MessageSink>,
     metrics: Arc<RwLock<MessageMetrics>>,
+    config: ProcessorConfig,
+    rules: Arc<ArcSwap<ProcessingRules>>,
 ) {
     info!("Starting MQTT event loop and message processor");
 
+    // Load the optional WASM transformation module
+    #[cfg(feature = "wasm")]
+    let wasm_transform = match config.wasm.as_ref().map(WasmTransform::load).transpose() {
+        Ok(wasm_transform) => wasm_transform,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    #[cfg(not(feature = "wasm"))]
+    if config.wasm.is_some() {
+        log::warn!(
+            "WASM_TRANSFORM_PATH is set, but the service was built without the `wasm` feature"
+        );
+    }
+
+    // Load the optional enrichment table, reloaded on SIGHUP
+    let enrichment_table = match config
+        .enrichment_table
+        .as_deref()
+        .map(EnrichmentTable::load)
+    {
+        Some(Ok(table)) => {
+            let table = Arc::new(table);
+            reload_on_sighup(Arc::clone(&table));
+            Some(table)
+        }
+        Some(Err(e)) => {
+            error!("{}", e);
+            return;
+        }
+        None => None,
+    };
+
+    // Create the optional validation service client
+    let validation = match config.validation.map(ValidationClient::new).transpose() {
+        Ok(validation) => validation,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    // Poll the optional feature-flag service for runtime toggles
+    let feature_flags = start_feature_flags(config.feature_flags);
+
+    // Count the failed attempts of unacknowledged messages to stop redelivery loops
+    let redeliveries = match (config.max_redeliveries, mqtt_subscriber.manual_ack()) {
+        (0, _) => None,
+        (max_redeliveries, true) => Some(RedeliveryTracker::new(max_redeliveries)),
+        (_, false) => {
+            warn!("MAX_REDELIVERIES only takes effect with MQTT_MANUAL_ACK=true");
+            None
+        }
+    };
+
+    // Hold records to deliver them in timestamp order, if enabled
+    let reorder = config
+        .reorder_window
+        .map(|window| ReorderBuffer::new(Arc::clone(&sink), window));
+
+    let (load, lifetime_metrics, topic_metrics, metrics_sample_rate) = {
+        let metrics = metrics.read().await;
+        (
+            metrics.load(),
+            metrics.lifetime(),
+            metrics.topics(),
+            metrics.sample_rate(),
+        )
+    };
+    sample_throughput(Arc::clone(&load));
+    if metrics_sample_rate > 1 {
+        info!(
+            "Recording one in {} messages in the windowed metrics",
+            metrics_sample_rate
+        );
+    }
+
+    let context = Arc::new(ProcessorContext {
+        mqtt_subscriber: Arc::clone(&mqtt_subscriber),
+        kafka_producer,
+        sink,
+        metrics,
+        load,
+        lifetime_metrics,
+        topic_metrics,
+        metrics_sampler: MetricsSampler::new(metrics_sample_rate),
+        #[cfg(feature = "wasm")]
+        wasm_transform,
+        enrichment_table,
+        drop_empty_payloads: config.drop_empty_payloads,
+        rules,
+        feature_flags,
+        sensor_timestamp_field: config.sensor_timestamp_field,
+        sensor_id_from_topic: config.sensor_id_from_topic,
+        flatten_json: config.flatten_json,
+        detect_payload_format: config.detect_payload_format,
+        message_id_strategy: config.message_id_strategy,
+        correlation_id_source: config.correlation_id_source,
+        payload_sample_log_rate: config.payload_sample_log_rate,
+        max_json_depth: config.max_json_depth,
+        validation,
+        redeliveries,
+        reorder,
+        max_redeliveries: config.max_redeliveries,
+        delivery_retries: config.delivery_retries,
+        delivery_retry_backoff: config.delivery_retry_backoff,
+    });
+
+    // Start the worker pool if configured
+    let worker_queue = if config.workers > 0 {
+        info!(
+            "Starting {} processor workers (queue capacity: {}, drop policy: {})",
+            config.workers,
+            config.queue_capacity,
+            config.queue_drop_policy.as_str()
+        );
+        let queue = Arc::new(MessageQueue::new(
+            config.queue_capacity,
+            config.queue_drop_policy,
+        ));
+        start_workers(Arc::clone(&context), Arc::clone(&queue), config.workers);
+        sample_queue_depth(&context, Arc::clone(&queue)).await;
+        Some(queue)
+    } else {
+        info!("Processing each message in its own task");
+        if !config.priority_topics.is_empty() {
+            warn!("PRIORITY_TOPICS only takes effect with PROCESSOR_WORKERS > 0");
+        }
+        if config.qos_lanes {
+            warn!("QOS_LANES only takes effect with PROCESSOR_WORKERS > 0");
+        }
+        None
+    };
+    let qos_lanes = config.qos_lanes;
+    // Queued messages are sampled on their own, independently of the processed ones
+    let queue_sampler = MetricsSampler::new(metrics_sample_rate);
+
+    // Time the last ping was sent, while waiting for its response
+    let mut ping_sent_at: Option<Instant> = None;
+    // Connection failures since the last successful connect
+    let mut consecutive_failures: u32 = 0;
+    // Whether a connection was established before, to resubscribe on reconnects
+    let mut connected_before = false;
+    // Whether the current connection was accepted, to tell dropped connections from refused ones
+    let mut connected = false;
+    // Times established connections were dropped, to detect client ID conflicts
+    let mut dropped_connections: VecDeque<Instant> = VecDeque::new();
+    // Whether the loop ended because the client disconnected on shutdown
+    let mut disconnected = false;
+
     // Process events in a loop
     loop {
         match event_loop.poll().await {
             Ok(notification) => {
                 match notification {
                     Event::Incoming(Packet::Publish(publish)) => {
+                        if let Some(packet_capture) = mqtt_subscriber.packet_capture() {
+                            packet_capture.record(&publish);
+                        }
+
+                        // Process messages with a malformed topic under a common topic, so the
+                        // name doesn't end up in the metrics or Kafka
+                        let malformed_topic = is_malformed_topic(&publish.topic);
+                        if malformed_topic {
+                            warn!(
+                                "Received message with malformed topic '{}', processing it as {}",
+                                sanitize_topic(&publish.topic),
+                                MALFORMED_TOPIC
+                            );
+                            context.metrics.write().await.record_bad_topic();
+                        } else if let Some(topic_discovery) = mqtt_subscriber.topic_discovery() {
+                            topic_discovery.observe(&publish.topic);
+                        }
+                        if publish.qos == QoS::ExactlyOnce {
+                            mqtt_subscriber.qos2_received(publish.pkid);
+                        }
+
                         // Log message details
                         debug!(
                             "Received message on '{}' ({} bytes)",
-                            publish.topic,
+                            sanitize_topic(&publish.topic),
                             publish.payload.len()
                         );
 
                         // Create message object
                         let message = MqttMessage {
-                            topic: publish.topic.clone(),
-                            payload: publish.payload.to_vec(),
+                            topic: if malformed_topic {
+                                MALFORMED_TOPIC.to_string()
+                            } else {
+                                publish.topic.clone()
+                            },
+                            payload: publish.payload.clone(),
                             qos: publish.qos,
                             retain: publish.retain,
                             received_at: Instant::now(),
                             timestamp: SystemTime::now(),
                         };
 
-                        // Clone references for the new task
-                        let metrics_clone = Arc::clone(&metrics);
-                        let kafka_producer_clone = Arc::clone(&kafka_producer);
-
-                        // Spawn a new task to process the message asynchronously
-                        tokio::spawn(async move {
-                            // Record message receipt in metrics first
-                            let message_size = message.payload.len();
-                            {
-                                let mut metrics_guard = metrics_clone.write().await;
-                                metrics_guard
-                                    .record_message_received(message_size, message.timestamp);
-                            }
-
-                            // Clone metrics_clone again before passing it to process_message
-                            let metrics_for_processing = Arc::clone(&metrics_clone);
-
-                            // Track whether the message was successfully delivered to Kafka
-                            let mut delivered_to_kafka = false;
-                            // Start timing the processing
-                            let processing_start = Instant::now();
-                            // Process the message in a separate task
-                            match process_message(&message, &kafka_producer_clone).await {
-                                Ok(_) => {
-                                    delivered_to_kafka = true;
+                        match &worker_queue {
+                            // Hand the message over to the worker pool
+                            Some(queue) => {
+                                let lane = if context.rules.load().is_priority(&message.topic) {
+                                    Lane::High
+                                } else if qos_lanes {
+                                    Lane::for_qos(message.qos)
+                                } else {
+                                    Lane::Normal
+                                };
+                                // A dropped message keeps the sampling decision of its push
+                                let queue_weight = queue_sampler.sample();
+                                if let Some(weight) = queue_weight {
+                                    context
+                                        .metrics
+                                        .write()
+                                        .await
+                                        .weighted(weight)
+                                        .record_message_queued(lane);
                                 }
-                                Err(e) => {
-                                    error!("{}", e);
-                                }
-                            }
 
-                            let processing_duration = processing_start.elapsed();
-
-                            // Update metrics
-                            {
-                                let mut metrics_guard = metrics_for_processing.write().await;
-                                metrics_guard.record_message_processed(processing_duration);
-                                if !delivered_to_kafka {
-                                    metrics_guard.record_processing_error();
-                                    metrics_guard.record_message_dropped();
+                                if let Some((dropped, _)) =
+                                    queue.push((message, publish), lane).await
+                                {
+                                    debug!(
+                                        "Processing queue full, dropped message from {} ({})",
+                                        sanitize_topic(&dropped.topic),
+                                        queue.policy().as_str()
+                                    );
+                                    record_queue_drop(&context, &dropped, lane, queue_weight).await;
                                 }
                             }
-                        });
+                            // Spawn a new task to process the message asynchronously
+                            None => {
+                                let context = Arc::clone(&context);
+                                tokio::spawn(async move {
+                                    handle_message(&context, message, publish).await;
+                                });
+                            }
+                        }
                     }
-                    Event::Incoming(Packet::ConnAck(_)) => {
+                    Event::Incoming(Packet::ConnAck(connack)) => {
                         // Update the connection status
                         mqtt_subscriber.update_connection_status(true);
+                        consecutive_failures = 0;
+                        connected = true;
+
+                        // Handshakes of a discarded session are never completed
+                        if !connack.session_present {
+                            let abandoned = mqtt_subscriber.clear_inflight_qos2();
+                            if abandoned > 0 {
+                                warn!(
+                                    "Broker discarded the session, abandoning {} incomplete QoS 2 handshakes",
+                                    abandoned
+                                );
+                            }
+                        }
+
+                        // Only a persistent session keeps the subscriptions across reconnects.
+                        // Resubscribe in a separate task, the requests are sent by this loop.
+                        if connected_before && !connack.session_present {
+                            mqtt_subscriber.request_resubscribe();
+                        }
+                        connected_before = true;
+                    }
+                    Event::Incoming(Packet::SubAck(suback)) => {
+                        let subscriber = Arc::clone(&mqtt_subscriber);
+                        tokio::spawn(async move {
+                            subscriber.handle_suback(suback).await;
+                        });
+                    }
+                    Event::Incoming(Packet::PingResp) => {
+                        // Measure the ping round trip
+                        if let Some(sent_at) = ping_sent_at.take() {
+                            let latency = sent_at.elapsed();
+                            debug!("MQTT ping latency: {:?}", latency);
+                            context.metrics.write().await.record_ping_latency(latency);
+                        }
                     }
                     Event::Incoming(packet) => {
                         debug!("Received MQTT control packet: {:?}", packet);
                     }
+                    Event::Outgoing(Outgoing::PingReq) => {
+                        if ping_sent_at.is_some() {
+                            warn!("No MQTT ping response received within keep alive");
+                            context.metrics.write().await.record_ping_timeout();
+                        }
+                        ping_sent_at = Some(Instant::now());
+                    }
+                    Event::Outgoing(Outgoing::Subscribe(pkid)) => {
+                        mqtt_subscriber.subscribe_sent(pkid);
+                    }
+                    Event::Outgoing(Outgoing::Disconnect) => {
+                        info!("Disconnected from the MQTT broker");
+                        mqtt_subscriber.update_connection_status(false);
+                        disconnected = true;
+                        break;
+                    }
+                    Event::Outgoing(Outgoing::PubComp(pkid)) => {
+                        if let Some(duration) = mqtt_subscriber.qos2_completed(pkid) {
+                            debug!("QoS 2 handshake of packet {} took {:?}", pkid, duration);
+                            context.metrics.write().await.record_qos2_completed();
+                        }
+                    }
                     Event::Outgoing(packet) => {
                         debug!("Sent MQTT packet: {:?}", packet);
                     }
                 }
             }
-            Err(_) => {
+            Err(e) => {
+                // The client can't decode a publish with a topic that isn't UTF-8 and drops the
+                // connection, the broker redelivers it to a persistent session
+                if matches!(
+                    e,
+                    ConnectionError::MqttState(StateError::Deserialization(
+                        MqttBytesError::TopicNotUtf8
+                    ))
+                ) {
+                    error!("Broker sent a message with a topic that is not valid UTF-8, the connection was dropped");
+                    context.metrics.write().await.record_bad_topic();
+                }
+
+                // A connection error while waiting for a ping response means it never arrived
+                if ping_sent_at.take().is_some() {
+                    warn!("No MQTT ping response received before the connection failed");
+                    context.metrics.write().await.record_ping_timeout();
+                }
+
                 // Update the MQTT subscriber connection status
                 mqtt_subscriber.update_connection_status(false);
+                mqtt_subscriber.clear_inflight_subscribes();
+
+                // MQTT 3.1.1 brokers close the connection without a reason when another client
+                // connects with the same ID, so repeatedly dropped connections point to a
+                // duplicate client ID
+                let (conflict_threshold, conflict_window) =
+                    mqtt_subscriber.client_id_conflict_detection();
+                if std::mem::take(&mut connected) && conflict_threshold > 0 {
+                    let now = Instant::now();
+                    dropped_connections.push_back(now);
+                    while dropped_connections
+                        .front()
+                        .is_some_and(|dropped_at| now.duration_since(*dropped_at) > conflict_window)
+                    {
+                        dropped_connections.pop_front();
+                    }
+                    if dropped_connections.len() >= conflict_threshold as usize {
+                        error!(
+                            "!!! MQTT connection was dropped {} times within {} seconds after being accepted. Another client is likely connected with the same client ID {}; set a unique MQTT_CLIENT_ID per instance !!!",
+                            dropped_connections.len(),
+                            conflict_window.as_secs(),
+                            event_loop.mqtt_options.client_id()
+                        );
+                        context.metrics.write().await.record_client_id_conflict();
+                        dropped_connections.clear();
+                    }
+                }
+
+                // Leave the connection down for inspection if reconnecting is disabled
+                if !mqtt_subscriber.auto_reconnect() {
+                    error!(
+                        "MQTT connection failed and auto-reconnect is disabled, not reconnecting: {}",
+                        e
+                    );
+                    mqtt_subscriber.mark_stopped();
+                    break;
+                }
+
+                // Retry with a new client ID if the broker keeps refusing this one, e.g.
+                // because it still holds a stale session for it
+                consecutive_failures += 1;
+                let fresh_client_after = mqtt_subscriber.fresh_client_after();
+                if fresh_client_after > 0 && consecutive_failures >= fresh_client_after {
+                    let client_id = generate_client_id();
+                    warn!(
+                        "MQTT connection failed {} times in a row, reconnecting as {} instead of {}",
+                        consecutive_failures,
+                        client_id,
+                        event_loop.mqtt_options.client_id()
+                    );
+                    event_loop.mqtt_options = with_client_id(&event_loop.mqtt_options, client_id);
+                    context.metrics.write().await.record_fresh_client();
+                    consecutive_failures = 0;
+                }
+
+                // Reconnect on the next poll, resubscribing once the broker accepted it
                 tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+
+    // Let the service exit after disconnecting on shutdown
+    if disconnected {
+        return;
+    }
+
+    // Otherwise only reached with auto-reconnect disabled: keep the service (and its API)
+    // running in the failed state instead of exiting
+    std::future::pending::<()>().await;
+}
+
+/// Start a fixed pool of worker tasks consuming from a shared bounded queue
+fn start_workers(
+    context: Arc<ProcessorContext>,
+    queue: Arc<MessageQueue<(MqttMessage, Publish)>>,
+    workers: usize,
+) {
+    for _ in 0..workers {
+        let context = Arc::clone(&context);
+        let queue = Arc::clone(&queue);
+
+        tokio::spawn(async move {
+            loop {
+                let (message, publish) = queue.pop().await;
+                handle_message(&context, message, publish).await;
+            }
+        });
+    }
+}
+
+/// Sample the depth of the processing queue every second for the readiness check
+async fn sample_queue_depth(
+    context: &ProcessorContext,
+    queue: Arc<MessageQueue<(MqttMessage, Publish)>>,
+) {
+    let queue_depth = context.metrics.read().await.queue_depth();
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(QUEUE_DEPTH_SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+            queue_depth.record(queue.depth());
+        }
+    });
+}
+
+/// Sample the message rate for the scaling hint in the background
+fn sample_throughput(load: Arc<LoadGauges>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(THROUGHPUT_SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+            load.sample();
+        }
+    });
+}
+
+/// Randomly decide whether to sample a message, with probability `rate`
+fn sample(rate: f64) -> bool {
+    let mut bytes = [0u8; 8];
+    if getrandom::getrandom(&mut bytes).is_err() {
+        return false;
+    }
+    (u64::from_le_bytes(bytes) as f64 / u64::MAX as f64) < rate
+}
+
+/// Process a message and record the outcome in the metrics
+async fn handle_message(context: &ProcessorContext, message: MqttMessage, publish: Publish) {
+    let _in_flight = context.load.start_message();
+
+    // Record message receipt in metrics first
+    let message_size = message.payload.len();
+    let (class, guarantee) = {
+        let rules = context.rules.load();
+        (
+            rules.topic_classes.class_for(&message.topic),
+            rules.delivery_guarantees.guarantee_for(&message.topic),
+        )
+    };
+    // Read the flags once, so a change doesn't apply halfway through a message
+    let flags = **context.feature_flags.load();
+    // Only sampled messages are counted in the windows, the others only advance them and
+    // update the counters that don't need the metrics lock
+    let sample_weight = context.metrics_sampler.sample();
+    match sample_weight {
+        Some(weight) => {
+            let mut metrics_guard = context.metrics.write().await;
+            let mut metrics_guard = metrics_guard.weighted(weight);
+            metrics_guard.record_message_received(message_size, message.timestamp);
+            metrics_guard.record_class_received(class);
+            metrics_guard.record_topic_received(&message.topic, message.timestamp);
+            metrics_guard.record_qos_received(message.qos);
+        }
+        None => {
+            context
+                .metrics
+                .write()
+                .await
+                .record_message_seen(message.timestamp);
+            context
+                .lifetime_metrics
+                .record_message_received(message_size, message.timestamp);
+            context
+                .topic_metrics
+                .record_message_received(&message.topic, message.timestamp);
+        }
+    }
+
+    if flags.payload_sampling
+        && context.payload_sample_log_rate > 0.0
+        && sample(context.payload_sample_log_rate)
+    {
+        info!(
+            "Sampled payload on {} ({} bytes): {}",
+            sanitize_topic(&message.topic),
+            message_size,
+            String::from_utf8_lossy(&message.payload).escape_debug()
+        );
+    }
+
+    // Track whether the message was successfully handled (delivered or dropped on purpose)
+    let mut delivered = false;
+    let mut dropped_empty = false;
+    let mut dead_letter_reason = None;
+    // Whether the message was processed but the sinks failed to deliver it, rather than dropped
+    let mut delivery_failed = false;
+    // Start timing the processing
+    let processing_start = Instant::now();
+    // Whether the broker would redeliver a failed message forever, see `MAX_REDELIVERIES`
+    let mut redeliveries_exhausted = false;
+
+    // Process the message
+    match process_message(&message, guarantee, flags, sample_weight, context).await {
+        Ok(outcome) => {
+            delivered = true;
+            if let Some(redeliveries) = &context.redeliveries {
+                redeliveries.record_success(&message.topic, &message.payload);
+            }
+            dropped_empty = outcome == ProcessingOutcome::DroppedEmpty;
+            if let (
+                ProcessingOutcome::Delivered {
+                    end_to_end_latency,
+                    late,
+                },
+                Some(weight),
+            ) = (outcome, sample_weight)
+            {
+                let mut metrics_guard = context.metrics.write().await;
+                let mut metrics_guard = metrics_guard.weighted(weight);
+                metrics_guard.record_guarantee_delivered(guarantee);
+                if let Some(latency) = end_to_end_latency {
+                    metrics_guard.record_end_to_end_latency(latency);
+                }
+                if late {
+                    metrics_guard.record_message_late();
+                }
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            delivery_failed = e.reason.is_delivery_failure();
+
+            // Unacknowledged messages come back from the broker, so they are only
+            // dead-lettered once they used up their redeliveries
+            let redelivered = match &context.redeliveries {
+                Some(redeliveries) if message.qos != QoS::AtMostOnce => {
+                    redeliveries_exhausted =
+                        redeliveries.record_failure(&message.topic, &message.payload);
+                    if redeliveries_exhausted {
+                        warn!(
+                            "Giving up on message from {} after {} redeliveries",
+                            sanitize_topic(&message.topic),
+                            context.max_redeliveries
+                        );
+                    }
+                    !redeliveries_exhausted
+                }
+                _ => false,
+            };
+
+            // Keep the original payload in the dead-letter topic for later inspection
+            if !redelivered && flags.dead_lettering {
+                dead_letter_reason = Some(e.reason);
+                if let Err(e) = context
+                    .kafka_producer
+                    .send_dead_letter(&message, e.reason)
+                    .await
+                {
+                    error!(
+                        "Failed to dead-letter message from {}: {}",
+                        sanitize_topic(&message.topic),
+                        e
+                    );
+                }
+            }
+
+            // Report the error with a payload sample to the errors topic for analytics
+            if let Err(send_error) = context.kafka_producer.send_error_event(&message, &e).await {
+                error!(
+                    "Failed to send error event for message from {}: {}",
+                    sanitize_topic(&message.topic),
+                    send_error
+                );
+            }
+        }
+    }
+
+    let processing_duration = processing_start.elapsed();
+
+    // In manual ack mode, only ack once the message is delivered (or given up on) so the
+    // broker redelivers anything we failed to forward
+    if (delivered || redeliveries_exhausted) && context.mqtt_subscriber.manual_ack() {
+        if let Err(e) = context.mqtt_subscriber.ack(&publish).await {
+            error!("{}", e);
+        }
+    }
 
-                // Try to reconnect and resubscribe to MQTT topics
-                mqtt_subscriber.resubscribe_to_topics().await;
+    // Update metrics
+    let Some(weight) = sample_weight else {
+        context.lifetime_metrics.record_message_processed();
+        if !delivered {
+            context.lifetime_metrics.record_processing_error();
+            context
+                .topic_metrics
+                .record_processing_error(&message.topic);
+            if delivery_failed {
+                context.lifetime_metrics.record_delivery_failed();
+            } else {
+                context.lifetime_metrics.record_message_dropped();
+                context.topic_metrics.record_message_dropped(&message.topic);
+            }
+        }
+        return;
+    };
+    {
+        let mut metrics_guard = context.metrics.write().await;
+        let mut metrics_guard = metrics_guard.weighted(weight);
+        metrics_guard.record_message_processed(processing_duration);
+        if !delivered {
+            metrics_guard.record_processing_error();
+            metrics_guard.record_class_error(class);
+            metrics_guard.record_topic_error(&message.topic);
+            metrics_guard.record_guarantee_failed(guarantee);
+            if delivery_failed {
+                metrics_guard.record_delivery_failed();
+            } else {
+                metrics_guard.record_message_dropped();
+                metrics_guard.record_class_dropped(class);
+                metrics_guard.record_topic_dropped(&message.topic);
+            }
+        }
+        if dropped_empty {
+            metrics_guard.record_message_empty();
+        }
+        if let Some(reason) = dead_letter_reason {
+            metrics_guard.record_message_dead_lettered(reason);
+            if reason == DeadLetterReason::TooDeep {
+                metrics_guard.record_message_too_deep();
             }
         }
     }
 }
 
 /// Process a single MQTT message
+///
+/// `sample_weight` is the number of messages this one stands for in the windowed metrics,
+/// `None` if it is not recorded in them (see `METRICS_SAMPLE_RATE`).
 pub async fn process_message(
     message: &MqttMessage,
-    kafka_producer: &Arc<KafkaProducer>,
-) -> Result<(), String> {
+    guarantee: DeliveryGuarantee,
+    flags: FeatureFlags,
+    sample_weight: Option<usize>,
+    context: &ProcessorContext,
+) -> Result<ProcessingOutcome, ProcessingError> {
+    // Drop empty payloads (e.g. retained message clears) before they reach the sinks
+    if context.drop_empty_payloads && message.payload.is_empty() {
+        debug!(
+            "Dropping message with empty payload on {}",
+            sanitize_topic(&message.topic)
+        );
+        return Ok(ProcessingOutcome::DroppedEmpty);
+    }
+
+    // Run the optional WASM transformation on the raw payload
+    #[cfg(feature = "wasm")]
+    let payload = match &context.wasm_transform {
+        Some(wasm_transform) => wasm_transform
+            .transform(&message.topic, &message.payload)
+            .map(Bytes::from)
+            .map_err(|e| {
+                ProcessingError::new(
+                    DeadLetterReason::TransformFailed,
+                    format!(
+                        "Failed to transform message on {}: {}",
+                        sanitize_topic(&message.topic),
+                        e
+                    ),
+                )
+            })?,
+        None => message.payload.clone(),
+    };
+    #[cfg(not(feature = "wasm"))]
+    let payload = message.payload.clone();
+
+    let rules = context.rules.load();
+
+    // Forward payloads detected as anything but JSON as they are, they can't be wrapped
+    let detected_format = context
+        .detect_payload_format
+        .then(|| detect_format(&payload));
+    if let (Some(detected_format), Some(weight)) = (detected_format, sample_weight) {
+        context
+            .metrics
+            .write()
+            .await
+            .weighted(weight)
+            .record_format_detected(detected_format);
+    }
+    let format = match detected_format {
+        Some(PayloadFormat::Json) | None if flags.envelope => {
+            rules.serialization_rules.format_for(&message.topic)
+        }
+        _ => SerializationFormat::Raw,
+    };
+
+    // Only parse the payload as JSON if a feature needs its fields
+    let needs_json = match format {
+        SerializationFormat::Json | SerializationFormat::Protobuf => {
+            context.enrichment_table.is_some()
+                || context.sensor_timestamp_field.is_some()
+                || context.sensor_id_from_topic.is_some()
+        }
+        SerializationFormat::Raw => false,
+    } || rules.topic_template.is_some()
+        || context
+            .correlation_id_source
+            .as_ref()
+            .is_some_and(CorrelationIdSource::needs_json);
+
+    // Reject adversarially nested payloads before any of them is parsed
+    let parses_json = needs_json
+        || context.flatten_json
+        || !rules.unit_conversions.is_empty()
+        || !rules.transform_pipeline.is_empty()
+        || context.validation.is_some();
+    if parses_json && context.max_json_depth > 0 && exceeds_depth(&payload, context.max_json_depth)
+    {
+        return Err(ProcessingError::new(
+            DeadLetterReason::TooDeep,
+            format!(
+                "Payload on {} is nested deeper than {} levels",
+                sanitize_topic(&message.topic),
+                context.max_json_depth
+            ),
+        ));
+    }
+
+    // Flatten nested JSON for tabular consumers, other payloads are kept as they are
+    let payload = match context.flatten_json {
+        true => flatten_json(&payload).map(Bytes::from).unwrap_or(payload),
+        false => payload,
+    };
+
+    // Normalize units of the configured fields, payloads without them are kept as they are
+    let payload = match rules.unit_conversions.is_empty() {
+        true => payload,
+        false => rules
+            .unit_conversions
+            .apply(&payload)
+            .map(Bytes::from)
+            .unwrap_or(payload),
+    };
+
+    // Run the configured transformation steps in order on JSON object payloads
+    let payload = match rules.transform_pipeline.is_empty() {
+        true => payload,
+        false => rules
+            .transform_pipeline
+            .apply(&payload)
+            .map(Bytes::from)
+            .unwrap_or(payload),
+    };
+
+    // Only forward payloads the validation service accepts
+    if let Some(validation) = &context.validation {
+        validation.validate(&message.topic, &payload).await?;
+    }
+
+    let payload_json = needs_json
+        .then(|| serde_json::from_slice::<serde_json::Value>(&payload).ok())
+        .flatten();
+
+    // Route large payloads to their own topic, others to the topic named by the payload,
+    // falling back to the topic mapped from the MQTT topic and then to the sensor data topic
+    let large_payload_topic = rules
+        .size_routing
+        .as_ref()
+        .filter(|routing| message.payload.len() > routing.threshold)
+        .map(|routing| routing.topic.clone());
+    let topic = large_payload_topic
+        .or_else(|| {
+            let template = rules.topic_template.as_ref()?;
+            let topic = template.resolve(payload_json.as_ref()?);
+            if topic.is_none() {
+                debug!(
+                    "Missing topic template field on {}, using the fallback topic",
+                    sanitize_topic(&message.topic)
+                );
+            }
+            topic
+        })
+        .or_else(|| {
+            let mapping = rules.topic_mapping.as_ref()?;
+            Some(mapping.resolve(&message.topic))
+        });
+
+    // Measurement time from the payload, only used for `SensorData` records
+    let sensor_timestamp = match format {
+        SerializationFormat::Json | SerializationFormat::Protobuf => context
+            .sensor_timestamp_field
+            .as_ref()
+            .and_then(|field| parse_sensor_timestamp(payload_json.as_ref()?.get(field)?)),
+        SerializationFormat::Raw => None,
+    };
+
+    // Identify the original message, so redeliveries of it get the same content hash
+    let message_id = context.message_id_strategy.generate(
+        &message.topic,
+        &message.payload,
+        sensor_timestamp.unwrap_or(message.timestamp),
+    );
+
+    // Continue the upstream trace, or start one for messages without a correlation ID
+    let correlation_id = context
+        .correlation_id_source
+        .as_ref()
+        .and_then(|source| correlation_id(source, payload_json.as_ref()));
+
+    let record = match format {
+        SerializationFormat::Json | SerializationFormat::Protobuf => {
+            let sensor_data = build_sensor_data(
+                message,
+                payload,
+                payload_json.as_ref(),
+                sensor_timestamp,
+                context,
+            )?;
+            let payload = context
+                .kafka_producer
+                .serialize(&sensor_data, format)
+                .map_err(|e| {
+                    ProcessingError::new(
+                        DeadLetterReason::SerializationFailed,
+                        format!(
+                            "Failed to serialize message from {}: {}",
+                            sanitize_topic(&message.topic),
+                            e
+                        ),
+                    )
+                })?;
+            OutputRecord {
+                payload: Bytes::from(payload),
+                format,
+                timestamp: sensor_data.sensor_timestamp,
+                mqtt_topic: message.topic.clone(),
+                topic,
+                message_id,
+                correlation_id,
+                detected_format,
+                sensor_data: Some(Arc::new(sensor_data)),
+            }
+        }
+        SerializationFormat::Raw => OutputRecord {
+            payload,
+            format,
+            timestamp: message.timestamp,
+            mqtt_topic: message.topic.clone(),
+            topic,
+            message_id,
+            correlation_id,
+            detected_format,
+            sensor_data: None,
+        },
+    };
+
+    // Deliver to the configured output sinks, retrying for at-least-once topics
+    let late = deliver(message, &record, guarantee, sample_weight, context).await?;
+    if late {
+        debug!(
+            "Message from {} arrived after its reorder window",
+            sanitize_topic(&message.topic)
+        );
+    }
+    debug!("Successfully sent message to {}", context.sink.name());
+
+    // A sensor clock ahead of ours counts as no latency
+    let end_to_end_latency = sensor_timestamp.map(|sensor_timestamp| {
+        SystemTime::now()
+            .duration_since(sensor_timestamp)
+            .unwrap_or_default()
+    });
+    Ok(ProcessingOutcome::Delivered {
+        end_to_end_latency,
+        late,
+    })
+}
+
+/// Deliver a record to the output sinks, through the reorder buffer if enabled
+///
+/// Failed deliveries of at-least-once topics are retried with a doubling backoff before the
+/// error is returned. Returns whether the record arrived too late for the reorder buffer.
+async fn deliver(
+    message: &MqttMessage,
+    record: &OutputRecord,
+    guarantee: DeliveryGuarantee,
+    sample_weight: Option<usize>,
+    context: &ProcessorContext,
+) -> Result<bool, ProcessingError> {
+    let mut retries = 0;
+    let mut backoff = context.delivery_retry_backoff;
+    loop {
+        let result = match &context.reorder {
+            Some(reorder) => reorder.send(record).await,
+            None => context.sink.send(record).await.map(|_| false),
+        };
+        match result {
+            Err(e)
+                if guarantee == DeliveryGuarantee::AtLeastOnce
+                    && retries < context.delivery_retries
+                    && e.reason.is_delivery_failure() =>
+            {
+                retries += 1;
+                warn!(
+                    "Delivery of message from {} failed, retry {}/{} in {} ms: {}",
+                    sanitize_topic(&message.topic),
+                    retries,
+                    context.delivery_retries,
+                    backoff.as_millis(),
+                    e
+                );
+                if let Some(weight) = sample_weight {
+                    context
+                        .metrics
+                        .write()
+                        .await
+                        .weighted(weight)
+                        .record_delivery_retry();
+                }
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Record a message dropped by a full processing queue
+///
+/// Only sampled drops take the metrics lock, the others only update the counters that
+/// don't need it.
+async fn record_queue_drop(
+    context: &ProcessorContext,
+    dropped: &MqttMessage,
+    lane: Lane,
+    sample_weight: Option<usize>,
+) {
+    let Some(weight) = sample_weight else {
+        context
+            .lifetime_metrics
+            .record_message_received(dropped.payload.len(), dropped.timestamp);
+        context.lifetime_metrics.record_message_dropped();
+        context
+            .topic_metrics
+            .record_message_received(&dropped.topic, dropped.timestamp);
+        context.topic_metrics.record_message_dropped(&dropped.topic);
+        return;
+    };
+
+    let class = context.rules.load().topic_classes.class_for(&dropped.topic);
+    let mut metrics_guard = context.metrics.write().await;
+    let mut metrics_guard = metrics_guard.weighted(weight);
+    metrics_guard.record_message_received(dropped.payload.len(), dropped.timestamp);
+    metrics_guard.record_message_dropped();
+    metrics_guard.record_queue_drop(lane);
+    metrics_guard.record_class_received(class);
+    metrics_guard.record_qos_received(dropped.qos);
+    metrics_guard.record_class_dropped(class);
+    metrics_guard.record_topic_received(&dropped.topic, dropped.timestamp);
+    metrics_guard.record_topic_dropped(&dropped.topic);
+}
+
+/// Wrap a payload in an (enriched) `SensorData` object
+fn build_sensor_data(
+    message: &MqttMessage,
+    payload: Bytes,
+    payload_json: Option<&serde_json::Value>,
+    sensor_timestamp: Option<SystemTime>,
+    context: &ProcessorContext,
+) -> Result<SensorData, ProcessingError> {
     // TODO: Add logic to validate message and populate message with additional fields
-    let sensor_data = SensorData {
-        sensor_id: message.topic.clone(),
-        message: String::from_utf8(message.payload.clone()).unwrap(),
-        sensor_timestamp: message.timestamp,
+    let payload = std::str::from_utf8(&payload)
+        .map(str::to_owned)
+        .map_err(|e| {
+            ProcessingError::new(
+                DeadLetterReason::InvalidPayload,
+                format!(
+                    "Invalid UTF-8 payload on {}: {}",
+                    sanitize_topic(&message.topic),
+                    e
+                ),
+            )
+        })?;
+    // With topic levels configured, a `sensor_id` field in the payload takes precedence over
+    // the levels, and the whole topic is used if the topic doesn't have the levels
+    let payload_sensor_id = payload_json.and_then(|value| value.get("sensor_id")?.as_str());
+    let sensor_id = match &context.sensor_id_from_topic {
+        Some(template) => payload_sensor_id
+            .map(str::to_string)
+            .or_else(|| template.extract(&message.topic))
+            .unwrap_or_else(|| message.topic.clone()),
+        None => message.topic.clone(),
+    };
+    let mut sensor_data = SensorData {
+        sensor_id,
+        message: payload,
+        // Use the measurement time from the payload if present, the receive time otherwise
+        sensor_timestamp: sensor_timestamp.unwrap_or(message.timestamp),
+        metadata: None,
     };
 
-    // Send to Kafka with graceful error handling
-    match kafka_producer.send_sensor_data(sensor_data).await {
-        Ok(_) => {
-            // Message sent successfully
-            debug!("Successfully sent message to Kafka");
-            return Ok(());
+    // Merge the sensor's metadata from the enrichment table, keyed by the payload's
+    // `sensor_id` field if present
+    if let Some(enrichment_table) = &context.enrichment_table {
+        let sensor_id = payload_sensor_id.unwrap_or(&sensor_data.sensor_id);
+        sensor_data.metadata = enrichment_table.lookup(sensor_id);
+    }
+
+    Ok(sensor_data)
+}
+
+/// Parse a payload timestamp, either milliseconds since the epoch or an RFC 3339 string
+fn parse_sensor_timestamp(value: &serde_json::Value) -> Option<SystemTime> {
+    match value {
+        serde_json::Value::Number(millis) => {
+            Some(SystemTime::UNIX_EPOCH + Duration::from_millis(millis.as_u64()?))
         }
-        Err(e) => {
-            // TODO: Add additional logic to store non-delivered messages in e.g. temporary storage
+        serde_json::Value::String(time) => chrono::DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(SystemTime::from),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        load_kafka_configs, load_metrics_configs, load_mqtt_configs, load_processor_configs,
+    };
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// Sink keeping the delivered records, or failing every delivery
+    struct RecordingSink {
+        records: Mutex<Vec<OutputRecord>>,
+        fail: bool,
+    }
+
+    impl RecordingSink {
+        fn new(fail: bool) -> Arc<Self> {
+            Arc::new(Self {
+                records: Mutex::new(Vec::new()),
+                fail,
+            })
+        }
+
+        fn records(&self) -> Vec<OutputRecord> {
+            self.records.lock().unwrap().clone()
+        }
+    }
 
-            // Return the error so it can be handled by the caller
-            if kafka_producer.is_connected() {
-                return Err(format!("Failed to send to Kafka: {}", e));
+    #[async_trait]
+    impl MessageSink for RecordingSink {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn send(&self, record: &OutputRecord) -> Result<(), ProcessingError> {
+            if self.fail {
+                return Err(ProcessingError::new(
+                    DeadLetterReason::KafkaFailed,
+                    "sink unavailable",
+                ));
             }
-            return Err("Skipped sending to Kafka (known disconnected)".to_string());
+            self.records.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+
+    /// Create a processing context delivering to `sink`, without a broker connection
+    fn context(config: ProcessorConfig, sink: Arc<RecordingSink>) -> ProcessorContext {
+        let kafka_config = load_kafka_configs();
+        let rules = ProcessingRules::new(&config, &kafka_config).unwrap();
+        let (mqtt_subscriber, _event_loop) = MqttSubscriber::new(&load_mqtt_configs());
+        let metrics = MessageMetrics::new(&load_metrics_configs());
+
+        ProcessorContext {
+            mqtt_subscriber: Arc::new(mqtt_subscriber),
+            kafka_producer: Arc::new(KafkaProducer::unconnected(&kafka_config, Vec::new())),
+            sink,
+            load: metrics.load(),
+            lifetime_metrics: metrics.lifetime(),
+            topic_metrics: metrics.topics(),
+            metrics_sampler: MetricsSampler::new(metrics.sample_rate()),
+            metrics: Arc::new(RwLock::new(metrics)),
+            #[cfg(feature = "wasm")]
+            wasm_transform: None,
+            enrichment_table: None,
+            drop_empty_payloads: config.drop_empty_payloads,
+            rules: Arc::new(ArcSwap::from_pointee(rules)),
+            feature_flags: Arc::new(ArcSwap::from_pointee(FeatureFlags::default())),
+            sensor_timestamp_field: config.sensor_timestamp_field,
+            sensor_id_from_topic: config.sensor_id_from_topic,
+            flatten_json: config.flatten_json,
+            detect_payload_format: config.detect_payload_format,
+            message_id_strategy: config.message_id_strategy,
+            correlation_id_source: config.correlation_id_source,
+            payload_sample_log_rate: config.payload_sample_log_rate,
+            max_json_depth: config.max_json_depth,
+            validation: None,
+            redeliveries: None,
+            reorder: None,
+            max_redeliveries: config.max_redeliveries,
+            delivery_retries: config.delivery_retries,
+            delivery_retry_backoff: config.delivery_retry_backoff,
+        }
+    }
+
+    fn processor_config() -> ProcessorConfig {
+        let mut config = load_processor_configs();
+        config.detect_payload_format = false;
+        config
+    }
+
+    fn mqtt_message(topic: &str, payload: &'static [u8]) -> MqttMessage {
+        MqttMessage {
+            topic: topic.to_string(),
+            payload: Bytes::from_static(payload),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            received_at: Instant::now(),
+            timestamp: SystemTime::now(),
         }
     }
+
+    async fn process(
+        message: &MqttMessage,
+        context: &ProcessorContext,
+    ) -> Result<ProcessingOutcome, ProcessingError> {
+        process_message(
+            message,
+            DeliveryGuarantee::BestEffort,
+            FeatureFlags::default(),
+            Some(1),
+            context,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn accepted_message_is_delivered_in_envelope() {
+        let sink = RecordingSink::new(false);
+        let context = context(processor_config(), Arc::clone(&sink));
+        let message = mqtt_message("lab/room1/temp", br#"{"temp":21.5}"#);
+
+        let outcome = process(&message, &context).await.unwrap();
+
+        assert_eq!(
+            outcome,
+            ProcessingOutcome::Delivered {
+                end_to_end_latency: None,
+                late: false,
+            }
+        );
+        let records = sink.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].mqtt_topic, "lab/room1/temp");
+        assert_eq!(records[0].format, SerializationFormat::Json);
+        let sensor_data: SensorData = serde_json::from_slice(&records[0].payload).unwrap();
+        assert_eq!(sensor_data.sensor_id, "lab/room1/temp");
+        assert_eq!(sensor_data.message, r#"{"temp":21.5}"#);
+    }
+
+    #[tokio::test]
+    async fn invalid_payload_is_dead_lettered() {
+        let sink = RecordingSink::new(false);
+        let context = context(processor_config(), Arc::clone(&sink));
+        let message = mqtt_message("lab/room1/temp", b"\xff\xfe");
+
+        let error = process(&message, &context).await.unwrap_err();
+
+        assert_eq!(error.reason, DeadLetterReason::InvalidPayload);
+        assert!(sink.records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn too_deep_payload_is_dead_lettered() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.flatten_json = true;
+        config.max_json_depth = 2;
+        let context = context(config, Arc::clone(&sink));
+        let message = mqtt_message("lab/room1/temp", br#"{"a":{"b":{"c":1}}}"#);
+
+        let error = process(&message, &context).await.unwrap_err();
+
+        assert_eq!(error.reason, DeadLetterReason::TooDeep);
+        assert!(sink.records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_dead_lettered() {
+        let sink = RecordingSink::new(true);
+        let context = context(processor_config(), Arc::clone(&sink));
+        let message = mqtt_message("lab/room1/temp", br#"{"temp":21.5}"#);
+
+        let error = process(&message, &context).await.unwrap_err();
+
+        assert_eq!(error.reason, DeadLetterReason::KafkaFailed);
+    }
+
+    #[tokio::test]
+    async fn empty_payload_is_filtered() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.drop_empty_payloads = true;
+        let context = context(config, Arc::clone(&sink));
+        let message = mqtt_message("lab/room1/temp", b"");
+
+        let outcome = process(&message, &context).await.unwrap();
+
+        assert_eq!(outcome, ProcessingOutcome::DroppedEmpty);
+        assert!(sink.records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn empty_payload_is_forwarded_unless_dropped() {
+        let sink = RecordingSink::new(false);
+        let context = context(processor_config(), Arc::clone(&sink));
+        let message = mqtt_message("lab/room1/temp", b"");
+
+        let outcome = process(&message, &context).await.unwrap();
+
+        assert!(matches!(outcome, ProcessingOutcome::Delivered { .. }));
+        assert_eq!(sink.records().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn serialization_failure_is_dead_lettered() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.sensor_timestamp_field = Some("time".to_string());
+        let context = context(config, Arc::clone(&sink));
+        // JSON can't represent a measurement time before the epoch
+        let message = mqtt_message("lab/room1/temp", br#"{"time":"1969-12-31T23:59:59Z"}"#);
+
+        let error = process(&message, &context).await.unwrap_err();
+
+        assert_eq!(error.reason, DeadLetterReason::SerializationFailed);
+        assert!(error.message.contains("lab/room1/temp"));
+        assert!(sink.records().is_empty());
+    }
+
+    #[test]
+    fn sensor_timestamp_is_parsed_from_millis_and_rfc3339() {
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+
+        assert_eq!(
+            parse_sensor_timestamp(&serde_json::json!(1_700_000_000_123u64)),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_sensor_timestamp(&serde_json::json!("2023-11-14T22:13:20.123Z")),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_sensor_timestamp(&serde_json::json!("2023-11-15T00:13:20.123+02:00")),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn unparseable_sensor_timestamp_is_ignored() {
+        for value in [
+            serde_json::json!("yesterday"),
+            serde_json::json!("2023-11-14 22:13:20"),
+            serde_json::json!(-1),
+            serde_json::json!(1.5),
+            serde_json::json!(null),
+            serde_json::json!(true),
+            serde_json::json!({"ms": 1_700_000_000_123u64}),
+        ] {
+            assert_eq!(parse_sensor_timestamp(&value), None, "{}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_sensor_timestamp_falls_back_to_receive_time() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.sensor_timestamp_field = Some("time".to_string());
+        let context = context(config, Arc::clone(&sink));
+
+        for payload in [br#"{"temp":21.5}"#.as_slice(), br#"{"time":"soon"}"#] {
+            let mut message = mqtt_message("lab/room1/temp", b"");
+            message.payload = Bytes::copy_from_slice(payload);
+
+            let outcome = process(&message, &context).await.unwrap();
+
+            assert_eq!(
+                outcome,
+                ProcessingOutcome::Delivered {
+                    end_to_end_latency: None,
+                    late: false,
+                }
+            );
+            assert_eq!(sink.records().last().unwrap().timestamp, message.timestamp);
+        }
+    }
+
+    #[tokio::test]
+    async fn future_sensor_timestamp_counts_as_no_latency() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.sensor_timestamp_field = Some("time".to_string());
+        let context = context(config, Arc::clone(&sink));
+        let message = mqtt_message("lab/room1/temp", br#"{"time":"2999-01-01T00:00:00Z"}"#);
+
+        let outcome = process(&message, &context).await.unwrap();
+
+        assert_eq!(
+            outcome,
+            ProcessingOutcome::Delivered {
+                end_to_end_latency: Some(Duration::ZERO),
+                late: false,
+            }
+        );
+        let records = sink.records();
+        assert!(records[0].timestamp > SystemTime::now());
+    }
+
+    #[tokio::test]
+    async fn deeply_nested_payload_is_dead_lettered_before_parsing() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.sensor_timestamp_field = Some("time".to_string());
+        config.max_json_depth = 64;
+        let context = context(config, Arc::clone(&sink));
+        let mut message = mqtt_message("lab/room1/temp", b"");
+        message.payload = Bytes::from(format!("{}{}", "[".repeat(100_000), "]".repeat(100_000)));
+
+        let error = process(&message, &context).await.unwrap_err();
+
+        assert_eq!(error.reason, DeadLetterReason::TooDeep);
+        assert!(sink.records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sensor_id_comes_from_payload_then_topic_level() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.sensor_id_from_topic = Some(SensorIdTemplate::parse("3").unwrap());
+        let context = context(config, Arc::clone(&sink));
+
+        let cases = [
+            (
+                "lab/room1/sensor42/temp",
+                br#"{"temp":21.5}"#.as_slice(),
+                "sensor42",
+            ),
+            ("lab/room1/sensor42/temp", br#"{"sensor_id":"s-7"}"#, "s-7"),
+            ("lab/room1", br#"{"temp":21.5}"#, "lab/room1"),
+        ];
+        for (topic, payload, expected) in cases {
+            let mut message = mqtt_message(topic, b"");
+            message.payload = Bytes::copy_from_slice(payload);
+
+            process(&message, &context).await.unwrap();
+
+            let record = sink.records().pop().unwrap();
+            let sensor_data: SensorData = serde_json::from_slice(&record.payload).unwrap();
+            assert_eq!(sensor_data.sensor_id, expected, "{}", topic);
+        }
+    }
+
+    #[tokio::test]
+    async fn redelivered_message_keeps_its_message_id() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.message_id_strategy = MessageIdStrategy::ContentHash;
+        config.sensor_timestamp_field = Some("time".to_string());
+        let context = context(config, Arc::clone(&sink));
+
+        let payload = br#"{"time":1700000000123,"temp":21.5}"#;
+        let first = mqtt_message("lab/room1/temp", payload);
+        let mut redelivery = mqtt_message("lab/room1/temp", payload);
+        redelivery.timestamp = first.timestamp + Duration::from_secs(5);
+        process(&first, &context).await.unwrap();
+        process(&redelivery, &context).await.unwrap();
+
+        let records = sink.records();
+        assert!(records[0].message_id.is_some());
+        assert_eq!(records[0].message_id, records[1].message_id);
+    }
+
+    #[tokio::test]
+    async fn kafka_timestamp_is_the_sensor_time_or_the_receive_time() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.sensor_timestamp_field = Some("time".to_string());
+        let mut context = context(config, Arc::clone(&sink));
+        let mut kafka_config = load_kafka_configs();
+        kafka_config.use_sensor_timestamp = true;
+        context.kafka_producer = Arc::new(KafkaProducer::unconnected(&kafka_config, Vec::new()));
+
+        let measured = mqtt_message("lab/room1/temp", br#"{"time":1700000000123}"#);
+        let unmeasured = mqtt_message("lab/room1/temp", br#"{"temp":21.5}"#);
+        process(&measured, &context).await.unwrap();
+        process(&unmeasured, &context).await.unwrap();
+
+        let records = sink.records();
+        let receive_millis = unmeasured
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        assert_eq!(
+            context.kafka_producer.record_timestamp(&records[0]),
+            Some(1_700_000_000_123)
+        );
+        assert_eq!(
+            context.kafka_producer.record_timestamp(&records[1]),
+            Some(receive_millis)
+        );
+    }
+
+    #[tokio::test]
+    async fn correlation_id_is_propagated_from_the_payload_field() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.correlation_id_source =
+            Some(CorrelationIdSource::PayloadField("trace_id".to_string()));
+        let context = context(config, Arc::clone(&sink));
+
+        let traced = mqtt_message("lab/room1/temp", br#"{"trace_id":"trace-42"}"#);
+        let untraced = mqtt_message("lab/room1/temp", br#"{"temp":21.5}"#);
+        process(&traced, &context).await.unwrap();
+        process(&untraced, &context).await.unwrap();
+
+        let records = sink.records();
+        assert_eq!(records[0].correlation_id.as_deref(), Some("trace-42"));
+        // Messages without one start a trace with a generated ID
+        let generated = records[1].correlation_id.as_deref().unwrap();
+        assert_eq!(generated.len(), 36);
+        assert_ne!(generated, "trace-42");
+    }
+
+    #[tokio::test]
+    async fn correlation_id_is_not_set_without_a_source() {
+        let sink = RecordingSink::new(false);
+        let mut config = processor_config();
+        config.correlation_id_source = None;
+        let context = context(config, Arc::clone(&sink));
+        let message = mqtt_message("lab/room1/temp", br#"{"trace_id":"trace-42"}"#);
+
+        process(&message, &context).await.unwrap();
+
+        assert_eq!(sink.records()[0].correlation_id, None);
+    }
+
+    #[tokio::test]
+    async fn queue_drops_are_counted_once_in_the_lifetime_counters() {
+        let context = context(processor_config(), RecordingSink::new(false));
+        let message = mqtt_message("lab/room1/temp", br#"{"temp":21.5}"#);
+
+        // Unsampled drops skip the windows, sampled ones stand for `weight` messages there
+        record_queue_drop(&context, &message, Lane::Normal, None).await;
+        record_queue_drop(&context, &message, Lane::Normal, Some(3)).await;
+
+        let lifetime = context.lifetime_metrics.totals();
+        assert_eq!(lifetime.messages_received, 2);
+        assert_eq!(lifetime.messages_dropped, 2);
+        let topics = context.topic_metrics.totals();
+        let counts = &topics.topics["lab/room1/temp"];
+        assert_eq!(counts.messages_received, 2);
+        assert_eq!(counts.messages_dropped, 2);
+    }
 }
@@ -0,0 +1,89 @@
+//! Nesting depth limit for JSON payloads
+
+/// Check whether a JSON payload nests objects and arrays deeper than `max_depth`
+///
+/// Scans the raw bytes without parsing, so adversarially nested payloads are rejected before
+/// they reach a parser. Brackets inside strings are ignored. Payloads that are not valid JSON
+/// are only checked for their bracket nesting.
+pub fn exceeds_depth(payload: &[u8], max_depth: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for &byte in payload {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Payload of `depth` nested arrays around a number
+    fn nested(depth: usize) -> Vec<u8> {
+        let mut payload = "[".repeat(depth).into_bytes();
+        payload.push(b'1');
+        payload.extend("]".repeat(depth).into_bytes());
+        payload
+    }
+
+    #[test]
+    fn depth_up_to_limit_is_accepted() {
+        assert!(!exceeds_depth(b"1", 0));
+        assert!(!exceeds_depth(br#"{"a":{"b":[1,2]}}"#, 3));
+        assert!(!exceeds_depth(&nested(64), 64));
+        assert!(exceeds_depth(&nested(65), 64));
+        assert!(exceeds_depth(br#"{"a":{"b":[1,2]}}"#, 2));
+    }
+
+    #[test]
+    fn sibling_containers_do_not_add_up() {
+        let payload = br#"{"a":{"x":1},"b":{"y":2},"c":[[1],[2],[3]]}"#;
+
+        assert!(!exceeds_depth(payload, 3));
+    }
+
+    #[test]
+    fn brackets_inside_strings_are_ignored() {
+        let payload = br#"{"note":"[[[[{{{{","escaped":"\"[[[[\\","tail":[1]}"#;
+
+        assert!(!exceeds_depth(payload, 2));
+    }
+
+    #[test]
+    fn pathological_nesting_is_rejected_without_recursion() {
+        // Far deeper than any recursive parser's stack would allow
+        let payload = nested(1_000_000);
+
+        assert!(exceeds_depth(&payload, 64));
+        assert!(!exceeds_depth(&payload, 1_000_000));
+    }
+
+    #[test]
+    fn unbalanced_payloads_are_checked_by_nesting() {
+        assert!(exceeds_depth(&"[".repeat(100).into_bytes(), 64));
+        assert!(!exceeds_depth(&"]".repeat(100).into_bytes(), 1));
+        assert!(!exceeds_depth(b"]]][[", 2));
+    }
+}
@@ -0,0 +1,177 @@
+//! Per-message IDs for deduplication by downstream consumers
+
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+/// How the `message-id` of a record is generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageIdStrategy {
+    /// SHA-256 of the topic, payload and timestamp, the same for redelivered messages
+    ContentHash,
+    /// Random UUID (v4), unique for every processed message
+    Uuid,
+    /// No message ID
+    None,
+}
+
+impl MessageIdStrategy {
+    /// Parse a strategy name as used in the configuration
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "content-hash" => Some(MessageIdStrategy::ContentHash),
+            "uuid" => Some(MessageIdStrategy::Uuid),
+            "none" => Some(MessageIdStrategy::None),
+            _ => None,
+        }
+    }
+
+    /// Generate the ID of a message, None with the `none` strategy
+    pub fn generate(&self, topic: &str, payload: &[u8], timestamp: SystemTime) -> Option<String> {
+        match self {
+            MessageIdStrategy::ContentHash => Some(content_hash(topic, payload, timestamp)),
+            MessageIdStrategy::Uuid => random_uuid(),
+            MessageIdStrategy::None => None,
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 of the topic, payload and timestamp in milliseconds since the epoch
+fn content_hash(topic: &str, payload: &[u8], timestamp: SystemTime) -> String {
+    let millis = timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    // Length-prefix the variable fields so different splits can't collide
+    let mut hasher = Sha256::new();
+    hasher.update((topic.len() as u64).to_be_bytes());
+    hasher.update(topic.as_bytes());
+    hasher.update((payload.len() as u64).to_be_bytes());
+    hasher.update(payload);
+    hasher.update(millis.to_be_bytes());
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Random version 4 UUID, None if the system's random source fails
+pub fn random_uuid() -> Option<String> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).ok()?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40; // Version 4
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(millis: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        let id = MessageIdStrategy::ContentHash.generate(
+            "lab/room1/temp",
+            br#"{"temp":21.5}"#,
+            at(1_700_000_000_123),
+        );
+
+        // Consumers compare IDs across restarts and releases, so the algorithm must not change
+        assert_eq!(
+            id.as_deref(),
+            Some("db212955b40928493e32669c730be1e462950e87ac3844048555e2132d74eb8c")
+        );
+    }
+
+    #[test]
+    fn content_hash_is_the_same_for_redeliveries() {
+        let strategy = MessageIdStrategy::ContentHash;
+        let first = strategy.generate("lab/temp", b"21.5", at(1_700_000_000_123));
+
+        assert_eq!(
+            strategy.generate("lab/temp", b"21.5", at(1_700_000_000_123)),
+            first
+        );
+        // Only whole milliseconds are hashed
+        assert_eq!(
+            strategy.generate(
+                "lab/temp",
+                b"21.5",
+                at(1_700_000_000_123) + Duration::from_micros(999)
+            ),
+            first
+        );
+    }
+
+    #[test]
+    fn content_hash_differs_for_different_messages() {
+        let strategy = MessageIdStrategy::ContentHash;
+        let ids = [
+            strategy.generate("lab/temp", b"21.5", at(1_000)),
+            strategy.generate("lab/humidity", b"21.5", at(1_000)),
+            strategy.generate("lab/temp", b"21.6", at(1_000)),
+            strategy.generate("lab/temp", b"21.5", at(1_001)),
+            // Moving bytes between the topic and the payload changes the ID
+            strategy.generate("lab/temp2", b"1.5", at(1_000)),
+        ];
+
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(id.as_ref().map(String::len), Some(64));
+            for other in &ids[i + 1..] {
+                assert_ne!(id, other);
+            }
+        }
+    }
+
+    #[test]
+    fn uuid_is_unique_per_message() {
+        let strategy = MessageIdStrategy::Uuid;
+        let first = strategy.generate("lab/temp", b"21.5", at(1_000)).unwrap();
+        let second = strategy.generate("lab/temp", b"21.5", at(1_000)).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(first.len(), 36);
+        assert_eq!(&first[14..15], "4");
+    }
+
+    #[test]
+    fn none_generates_no_id() {
+        assert_eq!(
+            MessageIdStrategy::None.generate("lab/temp", b"21.5", at(1_000)),
+            None
+        );
+    }
+
+    #[test]
+    fn strategies_are_parsed_by_name() {
+        assert_eq!(
+            MessageIdStrategy::parse("content-hash"),
+            Some(MessageIdStrategy::ContentHash)
+        );
+        assert_eq!(
+            MessageIdStrategy::parse("uuid"),
+            Some(MessageIdStrategy::Uuid)
+        );
+        assert_eq!(
+            MessageIdStrategy::parse("none"),
+            Some(MessageIdStrategy::None)
+        );
+        assert_eq!(MessageIdStrategy::parse("sha256"), None);
+    }
+}
@@ -1,3 +1,24 @@
 //! Message processing functionality
 
+pub mod classes;
+pub mod correlation_id;
+pub mod delivery;
+pub mod enrichment;
+pub mod feature_flags;
+pub mod flatten;
+pub mod format_detection;
 pub mod handler;
+pub mod json_depth;
+pub mod message_id;
+pub mod pipeline;
+pub mod protobuf;
+pub mod queue;
+pub mod redelivery;
+pub mod reorder;
+pub mod rules;
+pub mod sensor_id;
+pub mod serialization;
+pub mod units;
+pub mod validation;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,101 @@
+//! Declarative pipeline of transformation steps over JSON payloads
+
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::processor::units::UnitConversion;
+
+/// A single transformation of the top-level fields of a JSON object
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TransformStep {
+    /// Move a field to a new name, replacing any field already there
+    Rename { from: String, to: String },
+    /// Remove a field
+    DropField { field: String },
+    /// Convert a numeric field with a built-in unit conversion
+    ConvertUnit {
+        field: String,
+        #[serde(deserialize_with = "deserialize_conversion")]
+        conversion: UnitConversion,
+    },
+    /// Set a field to a constant value, replacing any field already there
+    AddField { field: String, value: Value },
+}
+
+/// Deserialize a unit conversion by the name used in the configuration
+fn deserialize_conversion<'de, D>(deserializer: D) -> Result<UnitConversion, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    UnitConversion::parse(&name)
+        .ok_or_else(|| de::Error::custom(format!("unknown unit conversion {}", name)))
+}
+
+impl TransformStep {
+    /// Apply the step to the fields of a JSON object
+    fn apply(&self, fields: &mut serde_json::Map<String, Value>) {
+        match self {
+            TransformStep::Rename { from, to } => {
+                if let Some(value) = fields.remove(from) {
+                    fields.insert(to.clone(), value);
+                }
+            }
+            TransformStep::DropField { field } => {
+                fields.remove(field);
+            }
+            TransformStep::ConvertUnit { field, conversion } => {
+                // Non-numeric fields are left alone, as are results JSON can't represent
+                if let Some(field_value) = fields.get_mut(field) {
+                    if let Some(number) = field_value
+                        .as_f64()
+                        .and_then(|number| serde_json::Number::from_f64(conversion.convert(number)))
+                    {
+                        *field_value = Value::Number(number);
+                    }
+                }
+            }
+            TransformStep::AddField { field, value } => {
+                fields.insert(field.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Ordered list of transformation steps applied to every JSON object payload
+pub struct TransformPipeline {
+    steps: Vec<TransformStep>,
+}
+
+impl TransformPipeline {
+    /// Parse a pipeline from a JSON array of steps, an empty string being an empty pipeline
+    pub fn parse(pipeline: &str) -> Result<Self, String> {
+        if pipeline.trim().is_empty() {
+            return Ok(Self { steps: Vec::new() });
+        }
+
+        let steps = serde_json::from_str(pipeline)
+            .map_err(|e| format!("Invalid TRANSFORM_PIPELINE: {}", e))?;
+        Ok(Self { steps })
+    }
+
+    /// Check if there are no steps to apply
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Apply all steps in order to a JSON object payload
+    ///
+    /// Returns `None` if the payload is not a JSON object, so it can be forwarded as is.
+    pub fn apply(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        let mut value: Value = serde_json::from_slice(payload).ok()?;
+        let fields = value.as_object_mut()?;
+
+        for step in &self.steps {
+            step.apply(fields);
+        }
+
+        serde_json::to_vec(&value).ok()
+    }
+}
@@ -0,0 +1,50 @@
+//! Protobuf encoding of `SensorData` records, following `proto/sensor_data.proto`
+
+use prost::Message;
+use serde_json::Value;
+use std::time::SystemTime;
+
+use crate::models::SensorData;
+
+/// Message types generated from `proto/sensor_data.proto` by the build script
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/spine.ingress.rs"));
+}
+
+/// `SensorData` message of `proto/sensor_data.proto`
+pub use generated::SensorData as SensorDataProto;
+
+impl From<&SensorData> for SensorDataProto {
+    fn from(data: &SensorData) -> Self {
+        let sensor_timestamp_ms = data
+            .sensor_timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        // String values are taken as they are, others as their JSON encoding
+        let metadata = data
+            .metadata
+            .iter()
+            .flatten()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                (key.clone(), value)
+            })
+            .collect();
+
+        Self {
+            sensor_id: data.sensor_id.clone(),
+            message: data.message.clone(),
+            sensor_timestamp_ms,
+            metadata,
+        }
+    }
+}
+
+/// Encode a `SensorData` record as a protobuf `SensorData` message
+pub fn encode_sensor_data(data: &SensorData) -> Vec<u8> {
+    SensorDataProto::from(data).encode_to_vec()
+}
@@ -0,0 +1,168 @@
+//! Bounded message queue feeding the processor workers
+
+use rumqttc::QoS;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// What to do with a new message when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Wait until a worker frees up space
+    Block,
+    /// Drop the oldest queued message to make room for the new one
+    DropOldest,
+    /// Drop the new message
+    DropNewest,
+}
+
+impl DropPolicy {
+    /// Name of the policy as used in the configuration
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropPolicy::Block => "block",
+            DropPolicy::DropOldest => "drop_oldest",
+            DropPolicy::DropNewest => "drop_newest",
+        }
+    }
+}
+
+/// Priority lane of a queued message, drained in declaration order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lane {
+    /// Drained before any other message
+    High,
+    /// QoS 2 messages, with QoS lanes enabled
+    Qos2,
+    /// QoS 1 messages, with QoS lanes enabled
+    Qos1,
+    /// Default lane
+    Normal,
+}
+
+/// All lanes in the order they are drained
+const LANES: [Lane; 4] = [Lane::High, Lane::Qos2, Lane::Qos1, Lane::Normal];
+
+impl Lane {
+    /// Get the lane for a message of the given QoS, with QoS 0 in the normal lane
+    pub fn for_qos(qos: QoS) -> Self {
+        match qos {
+            QoS::ExactlyOnce => Lane::Qos2,
+            QoS::AtLeastOnce => Lane::Qos1,
+            QoS::AtMostOnce => Lane::Normal,
+        }
+    }
+
+    /// Name of the lane as used in metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Lane::High => "high",
+            Lane::Qos2 => "qos2",
+            Lane::Qos1 => "qos1",
+            Lane::Normal => "normal",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Lane::High => 0,
+            Lane::Qos2 => 1,
+            Lane::Qos1 => 2,
+            Lane::Normal => 3,
+        }
+    }
+
+    /// Drop policy of the lane: QoS 1 and 2 messages are never dropped by the queue
+    fn policy(&self, policy: DropPolicy) -> DropPolicy {
+        match self {
+            Lane::Qos2 | Lane::Qos1 => DropPolicy::Block,
+            Lane::High | Lane::Normal => policy,
+        }
+    }
+}
+
+/// A bounded FIFO queue with priority lanes, shared between the event loop and the workers
+///
+/// Each lane holds up to `capacity` messages and applies the drop policy on its own, except
+/// the QoS 1 and 2 lanes which always block. Workers always take from the highest priority
+/// non-empty lane first.
+pub struct MessageQueue<T> {
+    lanes: Mutex<[VecDeque<T>; 4]>,
+    capacity: usize,
+    policy: DropPolicy,
+    item_available: Notify,
+    space_available: [Notify; 4],
+}
+
+impl<T> MessageQueue<T> {
+    /// Create a new queue holding up to `capacity` messages per lane
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            lanes: Mutex::new(std::array::from_fn(|_| VecDeque::new())),
+            capacity,
+            policy,
+            item_available: Notify::new(),
+            space_available: std::array::from_fn(|_| Notify::new()),
+        }
+    }
+
+    /// Get the drop policy of the queue
+    pub fn policy(&self) -> DropPolicy {
+        self.policy
+    }
+
+    /// Get the number of queued messages across both lanes
+    pub fn depth(&self) -> usize {
+        self.lanes.lock().unwrap().iter().map(VecDeque::len).sum()
+    }
+
+    /// Add a message to a lane, applying the drop policy if the lane is full
+    ///
+    /// Returns the message that was dropped to respect the capacity, if any.
+    pub async fn push(&self, item: T, lane: Lane) -> Option<T> {
+        let mut item = Some(item);
+
+        loop {
+            {
+                let mut lanes = self.lanes.lock().unwrap();
+                let items = &mut lanes[lane.index()];
+                if items.len() < self.capacity {
+                    items.push_back(item.take().unwrap());
+                    self.item_available.notify_one();
+                    return None;
+                }
+
+                match lane.policy(self.policy) {
+                    DropPolicy::Block => {}
+                    DropPolicy::DropOldest => {
+                        let dropped = items.pop_front();
+                        items.push_back(item.take().unwrap());
+                        self.item_available.notify_one();
+                        return dropped;
+                    }
+                    DropPolicy::DropNewest => return item,
+                }
+            }
+
+            // Lane is full and the policy is to block
+            self.space_available[lane.index()].notified().await;
+        }
+    }
+
+    /// Take the oldest message of the highest priority lane, waiting until one is available
+    pub async fn pop(&self) -> T {
+        loop {
+            {
+                let mut lanes = self.lanes.lock().unwrap();
+                for lane in LANES {
+                    if let Some(item) = lanes[lane.index()].pop_front() {
+                        self.space_available[lane.index()].notify_one();
+                        return item;
+                    }
+                }
+            }
+
+            self.item_available.notified().await;
+        }
+    }
+}
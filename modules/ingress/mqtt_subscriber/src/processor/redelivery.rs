@@ -0,0 +1,66 @@
+//! Failed delivery attempts of messages the broker redelivers in manual ack mode
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Maximum number of failing messages tracked, the counts are reset once it is reached
+const MAX_TRACKED_MESSAGES: usize = 10_000;
+
+/// Failed attempts per message, identified by a hash of its topic and payload
+///
+/// In manual ack mode a failed message stays unacknowledged and the broker redelivers it, so
+/// a message that can never be delivered would loop forever. Counting the attempts lets the
+/// processor give up on it after `MAX_REDELIVERIES` redeliveries.
+pub struct RedeliveryTracker {
+    max_redeliveries: u32,
+    failures: Mutex<HashMap<[u8; 32], u32>>,
+}
+
+impl RedeliveryTracker {
+    /// Create a tracker giving up after `max_redeliveries` redeliveries
+    pub fn new(max_redeliveries: u32) -> Self {
+        Self {
+            max_redeliveries,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a failed attempt, returning true once the message used up its redeliveries
+    ///
+    /// A message given up on is forgotten, so a later copy of it gets its own attempts.
+    pub fn record_failure(&self, topic: &str, payload: &[u8]) -> bool {
+        let key = message_key(topic, payload);
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_TRACKED_MESSAGES && !failures.contains_key(&key) {
+            failures.clear();
+        }
+
+        let attempts = failures.entry(key).or_insert(0);
+        *attempts += 1;
+        if *attempts > self.max_redeliveries {
+            failures.remove(&key);
+            return true;
+        }
+        false
+    }
+
+    /// Forget the failed attempts of a message once it was delivered
+    pub fn record_success(&self, topic: &str, payload: &[u8]) {
+        let mut failures = self.failures.lock().unwrap();
+        // Skip hashing in the common case of nothing failing
+        if failures.is_empty() {
+            return;
+        }
+        failures.remove(&message_key(topic, payload));
+    }
+}
+
+/// SHA-256 of the topic and payload, the same for every redelivery of a message
+fn message_key(topic: &str, payload: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update((topic.len() as u64).to_be_bytes());
+    hasher.update(topic.as_bytes());
+    hasher.update(payload);
+    hasher.finalize().into()
+}
@@ -0,0 +1,154 @@
+//! Reordering of output records by their timestamp before delivery
+
+use futures::future::join_all;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::oneshot;
+
+use crate::models::{DeadLetterReason, OutputRecord, ProcessingError};
+use crate::sink::MessageSink;
+
+/// Position of a record in a buffer: its timestamp, then its arrival order
+type RecordKey = (SystemTime, u64);
+
+/// A record waiting in the buffer, with the channel its delivery result is reported on
+struct PendingRecord {
+    record: OutputRecord,
+    delivered: oneshot::Sender<Result<(), ProcessingError>>,
+}
+
+/// Records of a single destination topic waiting to be delivered
+#[derive(Default)]
+struct TopicBuffer {
+    records: BTreeMap<RecordKey, PendingRecord>,
+    /// Keys with the time they were buffered until, in arrival order
+    deadlines: VecDeque<(Instant, RecordKey)>,
+    /// Timestamp of the last delivered record, older records arrive too late
+    watermark: Option<SystemTime>,
+}
+
+/// Buffer that holds records for a time window and hands them to the sinks sorted by timestamp
+///
+/// Records are buffered per destination topic. Once a record has been held for the window, it
+/// is delivered together with all buffered records with an earlier timestamp, so each topic
+/// receives its records in timestamp order. Records older than the last delivered record of
+/// their topic are late and delivered right away.
+pub struct ReorderBuffer {
+    sink: Arc<dyn MessageSink>,
+    window: Duration,
+    buffers: Mutex<HashMap<Option<String>, TopicBuffer>>,
+    next_seq: AtomicU64,
+}
+
+impl ReorderBuffer {
+    /// Create a new buffer delivering to `sink` and start flushing it
+    pub fn new(sink: Arc<dyn MessageSink>, window: Duration) -> Arc<Self> {
+        let buffer = Arc::new(Self {
+            sink,
+            window,
+            buffers: Mutex::new(HashMap::new()),
+            next_seq: AtomicU64::new(0),
+        });
+
+        let flushed = Arc::clone(&buffer);
+        tokio::spawn(async move {
+            // Check often enough that records are held at most a tenth longer than the window
+            let mut interval = tokio::time::interval((window / 10).max(Duration::from_millis(1)));
+            loop {
+                interval.tick().await;
+                flushed.flush();
+            }
+        });
+
+        buffer
+    }
+
+    /// Deliver a record once it has been held for the window
+    ///
+    /// Returns whether the record arrived too late to be delivered in order.
+    pub async fn send(&self, record: &OutputRecord) -> Result<bool, ProcessingError> {
+        let receiver = {
+            let mut buffers = self.buffers.lock().unwrap();
+            let buffer = buffers.entry(record.topic.clone()).or_default();
+            if buffer
+                .watermark
+                .is_some_and(|watermark| record.timestamp < watermark)
+            {
+                None
+            } else {
+                let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+                let key = (record.timestamp, seq);
+                let (sender, receiver) = oneshot::channel();
+                buffer.records.insert(
+                    key,
+                    PendingRecord {
+                        record: record.clone(),
+                        delivered: sender,
+                    },
+                );
+                buffer
+                    .deadlines
+                    .push_back((Instant::now() + self.window, key));
+                Some(receiver)
+            }
+        };
+
+        match receiver {
+            Some(receiver) => {
+                receiver.await.map_err(|_| {
+                    ProcessingError::new(
+                        DeadLetterReason::KafkaFailed,
+                        "Reorder buffer dropped the record before delivery",
+                    )
+                })??;
+                Ok(false)
+            }
+            None => {
+                self.sink.send(record).await?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Deliver the records that were held for the window, with all records sorted before them
+    fn flush(&self) {
+        let now = Instant::now();
+        let mut buffers = self.buffers.lock().unwrap();
+
+        for buffer in buffers.values_mut() {
+            let mut last_key = None;
+            while let Some(&(deadline, key)) = buffer.deadlines.front() {
+                if deadline > now {
+                    break;
+                }
+                buffer.deadlines.pop_front();
+                last_key = last_key.max(Some(key));
+            }
+
+            let Some((timestamp, seq)) = last_key else {
+                continue;
+            };
+            let later = buffer.records.split_off(&(timestamp, seq + 1));
+            let due = std::mem::replace(&mut buffer.records, later);
+            if due.is_empty() {
+                continue;
+            }
+            buffer.watermark = buffer.watermark.max(Some(timestamp));
+
+            // Start the deliveries in timestamp order, then report each result to its sender
+            let sink = Arc::clone(&self.sink);
+            tokio::spawn(async move {
+                let (records, senders): (Vec<_>, Vec<_>) = due
+                    .into_values()
+                    .map(|pending| (pending.record, pending.delivered))
+                    .unzip();
+                let results = join_all(records.iter().map(|record| sink.send(record))).await;
+                for (sender, result) in senders.into_iter().zip(results) {
+                    let _ = sender.send(result);
+                }
+            });
+        }
+    }
+}
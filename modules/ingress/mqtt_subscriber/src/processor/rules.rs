@@ -0,0 +1,69 @@
+//! Topic-based processing rules that can be reloaded at runtime
+
+use rumqttc::matches;
+
+use crate::config::{KafkaConfig, ProcessorConfig};
+use crate::kafka::topic_template::{MqttTopicMapping, TopicTemplate};
+use crate::processor::classes::TopicClasses;
+use crate::processor::delivery::DeliveryGuarantees;
+use crate::processor::pipeline::TransformPipeline;
+use crate::processor::serialization::SerializationRules;
+use crate::processor::units::UnitConversions;
+
+/// Routing of payloads larger than a threshold to a separate Kafka topic
+pub struct SizeRouting {
+    /// Payloads of more than this many bytes are routed to `topic`
+    pub threshold: usize,
+    pub topic: String,
+}
+
+/// Routing and transformation rules, swapped as a whole on a configuration reload
+pub struct ProcessingRules {
+    pub serialization_rules: SerializationRules,
+    pub topic_classes: TopicClasses,
+    pub delivery_guarantees: DeliveryGuarantees,
+    pub unit_conversions: UnitConversions,
+    pub transform_pipeline: TransformPipeline,
+    pub topic_template: Option<TopicTemplate>,
+    pub topic_mapping: Option<MqttTopicMapping>,
+    pub size_routing: Option<SizeRouting>,
+    priority_topics: Vec<String>,
+}
+
+impl ProcessingRules {
+    /// Build the rules from the processor and Kafka configuration
+    ///
+    /// Fails if the transformation pipeline is invalid.
+    pub fn new(processor: &ProcessorConfig, kafka: &KafkaConfig) -> Result<Self, String> {
+        Ok(Self {
+            serialization_rules: SerializationRules::new(
+                processor.serialization_rules.clone(),
+                kafka.serialization,
+            ),
+            topic_classes: TopicClasses::new(processor.topic_classes.clone()),
+            delivery_guarantees: DeliveryGuarantees::new(processor.delivery_guarantees.clone()),
+            unit_conversions: UnitConversions::new(processor.unit_conversions.clone()),
+            transform_pipeline: TransformPipeline::parse(&processor.transform_pipeline)?,
+            topic_template: kafka.topic_template.clone().map(TopicTemplate::new),
+            topic_mapping: kafka.topic_from_mqtt.then(|| {
+                MqttTopicMapping::new(kafka.topic_prefix.clone(), kafka.topic_separator.clone())
+            }),
+            size_routing: kafka
+                .topic_large_payloads
+                .clone()
+                .filter(|_| kafka.large_payload_threshold > 0)
+                .map(|topic| SizeRouting {
+                    threshold: kafka.large_payload_threshold,
+                    topic,
+                }),
+            priority_topics: processor.priority_topics.clone(),
+        })
+    }
+
+    /// Check if a topic belongs in the high priority lane
+    pub fn is_priority(&self, topic: &str) -> bool {
+        self.priority_topics
+            .iter()
+            .any(|filter| matches(topic, filter))
+    }
+}
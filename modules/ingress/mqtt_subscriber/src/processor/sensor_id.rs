@@ -0,0 +1,131 @@
+//! Sensor IDs derived from the levels of the MQTT topic
+
+/// Template building a sensor ID from MQTT topic levels, e.g. `{3}` takes `sensor42` from
+/// `lab/room1/sensor42/temp` and `{2}-{3}` gives `room1-sensor42`
+///
+/// Levels are numbered from 1.
+#[derive(Debug, Clone)]
+pub struct SensorIdTemplate {
+    template: String,
+}
+
+impl SensorIdTemplate {
+    /// Parse a template, a plain level number `N` is the same as `{N}`
+    ///
+    /// Returns an error if a placeholder is not a level number or the template has none.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let template = if template.parse::<usize>().is_ok() {
+            format!("{{{}}}", template)
+        } else {
+            template.to_string()
+        };
+
+        let mut placeholders = 0;
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = start
+                + rest[start..]
+                    .find('}')
+                    .ok_or_else(|| format!("unclosed placeholder in {:?}", template))?;
+            match rest[start + 1..end].parse::<usize>() {
+                Ok(level) if level > 0 => placeholders += 1,
+                _ => {
+                    return Err(format!(
+                        "placeholder {:?} is not a topic level (1, 2, ...)",
+                        &rest[start..=end]
+                    ))
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        if placeholders == 0 {
+            return Err(format!("{:?} has no topic level placeholder", template));
+        }
+
+        Ok(Self { template })
+    }
+
+    /// Fill the placeholders with the levels of a topic
+    ///
+    /// Returns `None` if the topic has fewer levels than referenced or a referenced level is
+    /// empty.
+    pub fn extract(&self, topic: &str) -> Option<String> {
+        let levels: Vec<&str> = topic.split('/').collect();
+        let mut sensor_id = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}')?;
+            sensor_id.push_str(&rest[..start]);
+
+            let level = rest[start + 1..end].parse::<usize>().ok()?;
+            match levels.get(level.checked_sub(1)?) {
+                Some(value) if !value.is_empty() => sensor_id.push_str(value),
+                _ => return None,
+            }
+            rest = &rest[end + 1..];
+        }
+        sensor_id.push_str(rest);
+
+        Some(sensor_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(template: &str, topic: &str) -> Option<String> {
+        SensorIdTemplate::parse(template).unwrap().extract(topic)
+    }
+
+    #[test]
+    fn levels_are_extracted_by_number() {
+        let topic = "lab/room1/sensor42/temp";
+
+        assert_eq!(extract("3", topic).as_deref(), Some("sensor42"));
+        assert_eq!(extract("{1}", topic).as_deref(), Some("lab"));
+        assert_eq!(extract("{4}", topic).as_deref(), Some("temp"));
+        assert_eq!(extract("{2}-{3}", topic).as_deref(), Some("room1-sensor42"));
+        assert_eq!(
+            extract("sensor:{3}/{3}", topic).as_deref(),
+            Some("sensor:sensor42/sensor42")
+        );
+    }
+
+    #[test]
+    fn out_of_range_levels_extract_nothing() {
+        assert_eq!(extract("{5}", "lab/room1/sensor42/temp"), None);
+        assert_eq!(extract("{2}-{9}", "lab/room1/sensor42/temp"), None);
+        assert_eq!(extract("{2}", "lab"), None);
+        assert_eq!(extract("{3}", "__malformed__"), None);
+        assert_eq!(extract("{99999999999}", "lab/room1"), None);
+    }
+
+    #[test]
+    fn empty_levels_extract_nothing() {
+        assert_eq!(extract("{1}", "/lab/room1"), None);
+        assert_eq!(extract("{2}", "lab//sensor42"), None);
+        assert_eq!(extract("{3}", "lab/room1/"), None);
+        assert_eq!(extract("{1}", ""), None);
+    }
+
+    #[test]
+    fn wildcard_characters_are_taken_literally() {
+        assert_eq!(extract("{2}", "lab/+/temp").as_deref(), Some("+"));
+        assert_eq!(extract("{3}", "lab/room1/#").as_deref(), Some("#"));
+        assert_eq!(
+            extract("{1}-{2}", "$SYS/broker").as_deref(),
+            Some("$SYS-broker")
+        );
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for template in [
+            "", "sensor", "0", "{0}", "{-1}", "{x}", "{+}", "{#}", "{1", "{}",
+        ] {
+            assert!(SensorIdTemplate::parse(template).is_err(), "{:?}", template);
+        }
+    }
+}
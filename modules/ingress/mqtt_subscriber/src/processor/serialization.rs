@@ -0,0 +1,26 @@
+//! Per-topic selection of the serialization format of output records
+
+use rumqttc::matches;
+
+use crate::models::SerializationFormat;
+
+/// Topic filter to serialization format rules, first match wins
+pub struct SerializationRules {
+    rules: Vec<(String, SerializationFormat)>,
+    default: SerializationFormat,
+}
+
+impl SerializationRules {
+    /// Create the rules from `(topic filter, format)` pairs and the format of other topics
+    pub fn new(rules: Vec<(String, SerializationFormat)>, default: SerializationFormat) -> Self {
+        Self { rules, default }
+    }
+
+    /// Get the format for a topic, the default format if no rule matches
+    pub fn format_for(&self, topic: &str) -> SerializationFormat {
+        self.rules
+            .iter()
+            .find(|(filter, _)| matches(topic, filter))
+            .map_or(self.default, |(_, format)| *format)
+    }
+}
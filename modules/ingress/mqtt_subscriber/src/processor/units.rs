@@ -0,0 +1,84 @@
+//! Unit conversion of numeric payload fields
+
+use serde_json::Value;
+
+/// Built-in unit conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitConversion {
+    FahrenheitToCelsius,
+    CelsiusToFahrenheit,
+    KelvinToCelsius,
+    PsiToKpa,
+    HpaToKpa,
+    BarToKpa,
+}
+
+impl UnitConversion {
+    /// Parse a conversion name as used in the configuration
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "f_to_c" => Some(UnitConversion::FahrenheitToCelsius),
+            "c_to_f" => Some(UnitConversion::CelsiusToFahrenheit),
+            "k_to_c" => Some(UnitConversion::KelvinToCelsius),
+            "psi_to_kpa" => Some(UnitConversion::PsiToKpa),
+            "hpa_to_kpa" => Some(UnitConversion::HpaToKpa),
+            "bar_to_kpa" => Some(UnitConversion::BarToKpa),
+            _ => None,
+        }
+    }
+
+    /// Convert a value
+    pub fn convert(&self, value: f64) -> f64 {
+        match self {
+            UnitConversion::FahrenheitToCelsius => (value - 32.0) * 5.0 / 9.0,
+            UnitConversion::CelsiusToFahrenheit => value * 9.0 / 5.0 + 32.0,
+            UnitConversion::KelvinToCelsius => value - 273.15,
+            UnitConversion::PsiToKpa => value * 6.894_757,
+            UnitConversion::HpaToKpa => value / 10.0,
+            UnitConversion::BarToKpa => value * 100.0,
+        }
+    }
+}
+
+/// Payload field to unit conversion rules
+pub struct UnitConversions {
+    rules: Vec<(String, UnitConversion)>,
+}
+
+impl UnitConversions {
+    /// Create the rules from `(field, conversion)` pairs
+    pub fn new(rules: Vec<(String, UnitConversion)>) -> Self {
+        Self { rules }
+    }
+
+    /// Check if there are no conversions to apply
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Convert the configured top-level numeric fields of a JSON object payload
+    ///
+    /// Returns `None` if the payload is not a JSON object or no field was converted, so it
+    /// can be forwarded as is.
+    pub fn apply(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        let mut value: Value = serde_json::from_slice(payload).ok()?;
+        let fields = value.as_object_mut()?;
+
+        let mut converted = false;
+        for (field, conversion) in &self.rules {
+            let Some(field_value) = fields.get_mut(field) else {
+                continue;
+            };
+            // Non-numeric fields are left alone, as are results JSON can't represent
+            if let Some(number) = field_value
+                .as_f64()
+                .and_then(|number| serde_json::Number::from_f64(conversion.convert(number)))
+            {
+                *field_value = Value::Number(number);
+                converted = true;
+            }
+        }
+
+        converted.then(|| serde_json::to_vec(&value).ok()).flatten()
+    }
+}
@@ -0,0 +1,157 @@
+//! Payload validation by an external HTTP validation service
+
+use log::warn;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::ValidationConfig;
+use crate::models::{DeadLetterReason, ProcessingError};
+
+/// Maximum number of cached verdicts, the cache is cleared once it is full
+const MAX_CACHED_VERDICTS: usize = 10_000;
+
+/// Client of the validation service with a short-lived cache of its verdicts
+///
+/// Verdicts for JSON payloads are cached by topic and payload shape (the field names and
+/// value types, ignoring the values), so messages with the same structure only cause one
+/// request per `VALIDATION_CACHE_TTL_SECS`. Other payloads are validated one by one.
+pub struct ValidationClient {
+    client: reqwest::Client,
+    config: ValidationConfig,
+    /// Whether payloads of a topic and shape were valid, with the time of the verdict
+    verdicts: Mutex<HashMap<String, (bool, Instant)>>,
+}
+
+impl ValidationClient {
+    /// Create a new validation client
+    pub fn new(config: ValidationConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| format!("Failed to create validation client: {}", e))?;
+
+        Ok(Self {
+            client,
+            config,
+            verdicts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Validate a payload, failing for invalid payloads and, unless failing open, for
+    /// payloads the validation service couldn't be asked about
+    pub async fn validate(&self, topic: &str, payload: &[u8]) -> Result<(), ProcessingError> {
+        let cache_key = serde_json::from_slice::<Value>(payload).ok().map(|value| {
+            let mut key = format!("{}\n", topic);
+            shape(&value, &mut key);
+            key
+        });
+
+        let cached = cache_key.as_ref().and_then(|key| {
+            let verdicts = self.verdicts.lock().unwrap();
+            let (valid, checked_at) = verdicts.get(key)?;
+            (checked_at.elapsed() < self.config.cache_ttl).then_some(*valid)
+        });
+
+        let valid = match cached {
+            Some(valid) => valid,
+            None => match self.request(topic, payload).await {
+                Ok(valid) => {
+                    if let Some(key) = cache_key {
+                        let mut verdicts = self.verdicts.lock().unwrap();
+                        if verdicts.len() >= MAX_CACHED_VERDICTS {
+                            verdicts.clear();
+                        }
+                        verdicts.insert(key, (valid, Instant::now()));
+                    }
+                    valid
+                }
+                Err(e) if self.config.fail_open => {
+                    warn!(
+                        "Validation service unavailable, forwarding unvalidated: {}",
+                        e
+                    );
+                    return Ok(());
+                }
+                Err(e) => {
+                    return Err(ProcessingError::new(
+                        DeadLetterReason::ValidationUnavailable,
+                        format!("Validation service unavailable: {}", e),
+                    ))
+                }
+            },
+        };
+
+        match valid {
+            true => Ok(()),
+            false => Err(ProcessingError::new(
+                DeadLetterReason::ValidationFailed,
+                "Payload rejected by the validation service",
+            )),
+        }
+    }
+
+    /// Ask the validation service about a payload
+    ///
+    /// 2xx responses mean valid and 4xx responses invalid. Failed requests and other
+    /// responses count as the service being unavailable.
+    async fn request(&self, topic: &str, payload: &[u8]) -> Result<bool, String> {
+        let response = self
+            .client
+            .post(&self.config.url)
+            .header("x-mqtt-topic", topic)
+            .body(payload.to_vec())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(true)
+        } else if status.is_client_error() {
+            Ok(false)
+        } else {
+            Err(format!("validation service responded with {}", status))
+        }
+    }
+}
+
+/// Append the shape of a JSON value to `out`: field names and value types without the values
+///
+/// Array elements are reduced to their distinct shapes, so arrays of different length share a
+/// shape.
+fn shape(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push('n'),
+        Value::Bool(_) => out.push('b'),
+        Value::Number(_) => out.push('d'),
+        Value::String(_) => out.push('s'),
+        Value::Array(items) => {
+            let item_shapes: BTreeSet<String> = items
+                .iter()
+                .map(|item| {
+                    let mut item_shape = String::new();
+                    shape(item, &mut item_shape);
+                    item_shape
+                })
+                .collect();
+            out.push('[');
+            for item_shape in item_shapes {
+                out.push_str(&item_shape);
+                out.push(',');
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            out.push('{');
+            for (name, field) in fields {
+                out.push_str(&serde_json::to_string(name).unwrap_or_default());
+                out.push(':');
+                shape(field, out);
+                out.push(',');
+            }
+            out.push('}');
+        }
+    }
+}
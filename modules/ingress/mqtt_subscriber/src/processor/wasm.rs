@@ -0,0 +1,128 @@
+//! Sandboxed payload transformation with a user-provided WASM module
+//!
+//! The module must export:
+//! - `memory`: the linear memory used to exchange data
+//! - `alloc(len: i32) -> i32`: allocate `len` bytes and return their offset
+//! - `transform(topic_ptr: i32, topic_len: i32, payload_ptr: i32, payload_len: i32) -> i64`:
+//!   transform the payload and return the output location packed as `(ptr << 32) | len`
+//!
+//! Every call runs in a fresh instance with bounded memory and execution time, so a
+//! misbehaving module can neither keep state between messages nor stall the processor.
+
+use log::info;
+use std::time::Duration;
+use wasmtime::{
+    Config, Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use crate::config::WasmConfig;
+
+/// Interval at which the engine epoch advances, the granularity of the time limit
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// A loaded WASM transformation module
+pub struct WasmTransform {
+    engine: Engine,
+    instance_pre: InstancePre<StoreLimits>,
+    memory_limit_bytes: usize,
+    deadline_ticks: u64,
+}
+
+impl WasmTransform {
+    /// Load and validate the WASM module at the configured path
+    pub fn load(config: &WasmConfig) -> Result<Self, String> {
+        let mut engine_config = Config::new();
+        engine_config.epoch_interruption(true);
+        let engine = Engine::new(&engine_config)
+            .map_err(|e| format!("Failed to create WASM engine: {}", e))?;
+
+        let module = Module::from_file(&engine, &config.path)
+            .map_err(|e| format!("Failed to load WASM module {}: {}", config.path, e))?;
+
+        // No host functions are provided, the module only sees its own memory
+        let linker = Linker::new(&engine);
+        let instance_pre = linker
+            .instantiate_pre(&module)
+            .map_err(|e| format!("Failed to prepare WASM module {}: {}", config.path, e))?;
+
+        // Advance the epoch in the background to enforce the time limit
+        let ticker_engine = engine.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(EPOCH_TICK);
+            ticker_engine.increment_epoch();
+        });
+
+        let deadline_ticks = (config.timeout.as_millis() / EPOCH_TICK.as_millis()).max(1) as u64;
+
+        info!(
+            "Loaded WASM transform {} (memory limit: {} bytes, timeout: {} ms)",
+            config.path,
+            config.memory_limit_bytes,
+            config.timeout.as_millis()
+        );
+
+        Ok(Self {
+            engine,
+            instance_pre,
+            memory_limit_bytes: config.memory_limit_bytes,
+            deadline_ticks,
+        })
+    }
+
+    /// Run the module's `transform` function on a message payload
+    pub fn transform(&self, topic: &str, payload: &[u8]) -> Result<Vec<u8>, String> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.memory_limit_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_epoch_deadline(self.deadline_ticks);
+
+        let instance = self
+            .instance_pre
+            .instantiate(&mut store)
+            .map_err(|e| format!("Failed to instantiate WASM module: {}", e))?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("WASM module does not export `memory`")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(|e| format!("Invalid WASM `alloc` export: {}", e))?;
+        let transform = instance
+            .get_typed_func::<(i32, i32, i32, i32), i64>(&mut store, "transform")
+            .map_err(|e| format!("Invalid WASM `transform` export: {}", e))?;
+
+        // Copy the inputs into the module's memory
+        let mut write_input = |data: &[u8]| -> Result<(i32, i32), String> {
+            let len = i32::try_from(data.len()).map_err(|_| "WASM input too large")?;
+            let ptr = alloc
+                .call(&mut store, len)
+                .map_err(|e| format!("WASM `alloc` failed: {}", e))?;
+            memory
+                .write(&mut store, ptr as u32 as usize, data)
+                .map_err(|e| format!("Failed to write WASM input: {}", e))?;
+            Ok((ptr, len))
+        };
+        let (topic_ptr, topic_len) = write_input(topic.as_bytes())?;
+        let (payload_ptr, payload_len) = write_input(payload)?;
+
+        let packed = transform
+            .call(&mut store, (topic_ptr, topic_len, payload_ptr, payload_len))
+            .map_err(|e| format!("WASM `transform` failed: {}", e))?;
+
+        // Read the output back from the module's memory
+        let output_ptr = (packed as u64 >> 32) as usize;
+        let output_len = (packed as u64 & 0xffff_ffff) as usize;
+        if output_len > memory.data_size(&store) {
+            return Err("WASM output exceeds module memory".to_string());
+        }
+        let mut output = vec![0; output_len];
+        memory
+            .read(&store, output_ptr, &mut output)
+            .map_err(|e| format!("Failed to read WASM output: {}", e))?;
+
+        Ok(output)
+    }
+}
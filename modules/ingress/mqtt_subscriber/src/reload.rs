@@ -0,0 +1,95 @@
+//! Live reload of the reloadable configuration on SIGHUP
+
+use arc_swap::ArcSwap;
+use log::{error, info, warn};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::config::{load_config, reload_env_file, Config};
+use crate::logging;
+use crate::metrics::TopicMetrics;
+use crate::mqtt::subscriber::MqttSubscriber;
+use crate::processor::rules::ProcessingRules;
+
+/// Settings that only take effect on a restart, compared on reload to warn about changes
+#[derive(Debug, PartialEq)]
+pub struct RestartSettings {
+    mqtt_broker: (String, u16),
+    kafka_broker: String,
+    api_port: u16,
+}
+
+impl RestartSettings {
+    /// Capture the restart-only settings of a configuration
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            mqtt_broker: config.mqtt.mqtt_options.broker_address(),
+            kafka_broker: config.kafka.broker.clone(),
+            api_port: config.api.port,
+        }
+    }
+}
+
+/// Subscribe to the topics listed in the configuration
+pub async fn subscribe_topics(subscriber: &MqttSubscriber, topics: &[String]) {
+    if let Err(e) = subscriber.subscribe_many(topics).await {
+        error!("Failed to subscribe to the configured topics: {}", e);
+    }
+}
+
+/// Re-read the configuration whenever the process receives SIGHUP and apply the reloadable
+/// parts (processing rules, configured topics and the log filter) without reconnecting
+pub fn reload_config_on_sighup(
+    subscriber: Arc<MqttSubscriber>,
+    rules: Arc<ArcSwap<ProcessingRules>>,
+    topic_metrics: Arc<TopicMetrics>,
+    restart_settings: RestartSettings,
+    topics: Vec<String>,
+) {
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!("Failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+
+        let mut topics: HashSet<String> = topics.into_iter().collect();
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading configuration");
+            if let Err(e) = reload_env_file() {
+                warn!("{}, reloading from the current environment", e);
+            }
+            logging::reload();
+
+            let config = load_config();
+            if RestartSettings::from_config(&config) != restart_settings {
+                warn!("Broker addresses and the API port can't be changed without a restart, ignoring their new values");
+            }
+            match ProcessingRules::new(&config.processor, &config.kafka) {
+                Ok(new_rules) => rules.store(Arc::new(new_rules)),
+                Err(e) => error!("{}, keeping the current processing rules", e),
+            }
+
+            // Only touch the configured topics, subscriptions made through the API are kept
+            let reloaded_topics: HashSet<String> = config.mqtt.topics.into_iter().collect();
+            for topic in topics.difference(&reloaded_topics) {
+                match subscriber.unsubscribe(topic).await {
+                    Ok(_) => info!(
+                        "Unsubscribed from topic removed from MQTT_TOPICS: {}",
+                        topic
+                    ),
+                    Err(e) => error!("Failed to unsubscribe from {}: {}", topic, e),
+                }
+            }
+            let added_topics: Vec<String> = reloaded_topics.difference(&topics).cloned().collect();
+            subscribe_topics(&subscriber, &added_topics).await;
+            topics = reloaded_topics;
+            topic_metrics.retain_subscribed(&subscriber.get_topics().await);
+
+            info!("Configuration reloaded");
+        }
+    });
+}
@@ -0,0 +1,49 @@
+//! Graceful shutdown on SIGTERM and SIGINT
+
+use log::{error, info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::mqtt::subscriber::MqttSubscriber;
+
+/// How long the event loop gets to send the DISCONNECT packet before the process exits anyway
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Disconnect from the broker when the process receives SIGTERM or SIGINT
+///
+/// A clean DISCONNECT tells the broker the client left on purpose, so it doesn't publish the
+/// last will. The message processor stops once the packet is sent, which ends the process.
+/// If the client is not connected, or the packet isn't sent within `DISCONNECT_TIMEOUT`, the
+/// process exits without it.
+pub fn disconnect_on_shutdown(subscriber: Arc<MqttSubscriber>) {
+    tokio::spawn(async move {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                return;
+            }
+        };
+        tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+            _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+        }
+
+        if !subscriber.is_connected() {
+            info!("MQTT client is not connected, exiting without disconnecting");
+            std::process::exit(0);
+        }
+        if let Err(e) = subscriber.disconnect().await {
+            error!("{}, exiting without disconnecting", e);
+            std::process::exit(0);
+        }
+
+        tokio::time::sleep(DISCONNECT_TIMEOUT).await;
+        warn!(
+            "MQTT DISCONNECT not sent within {} seconds, exiting anyway",
+            DISCONNECT_TIMEOUT.as_secs()
+        );
+        std::process::exit(0);
+    });
+}
@@ -0,0 +1,54 @@
+//! Sink delivering each message to several sinks at once
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::warn;
+use std::sync::Arc;
+
+use super::MessageSink;
+use crate::models::{OutputRecord, ProcessingError};
+
+/// Sink sending every message to all of its sinks concurrently
+///
+/// Delivery succeeds if all required sinks succeed; failures of optional sinks are only
+/// logged.
+/// A retried delivery is sent to every sink again, including those that already succeeded.
+pub struct FanOutSink {
+    sinks: Vec<(Arc<dyn MessageSink>, bool)>,
+}
+
+impl FanOutSink {
+    /// Create a fan-out sink from `(sink, required)` pairs
+    pub fn new(sinks: Vec<(Arc<dyn MessageSink>, bool)>) -> Self {
+        Self { sinks }
+    }
+}
+
+#[async_trait]
+impl MessageSink for FanOutSink {
+    fn name(&self) -> &str {
+        "fan-out"
+    }
+
+    async fn send(&self, record: &OutputRecord) -> Result<(), ProcessingError> {
+        let results = join_all(self.sinks.iter().map(|(sink, _)| sink.send(record))).await;
+
+        let mut first_error = None;
+        for ((sink, required), result) in self.sinks.iter().zip(results) {
+            let Err(e) = result else {
+                continue;
+            };
+
+            if *required {
+                first_error.get_or_insert(e);
+            } else {
+                warn!("Optional sink {} failed: {}", sink.name(), e);
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
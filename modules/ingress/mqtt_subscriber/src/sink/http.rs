@@ -0,0 +1,206 @@
+//! Sink posting messages to an HTTP webhook
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::debug;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use super::MessageSink;
+use crate::config::WebhookConfig;
+use crate::models::{DeadLetterReason, OutputRecord, ProcessingError, SerializationFormat};
+
+/// Number of recent deliveries the success rate is calculated over
+const HEALTH_HISTORY: usize = 100;
+
+/// Base delay between delivery attempts, doubled after every retry
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Sink POSTing each record to a webhook URL
+pub struct HttpSink {
+    client: reqwest::Client,
+    url: String,
+    retries: u32,
+    permits: Semaphore,
+    /// Outcomes of the most recent deliveries (oldest first)
+    recent_deliveries: Mutex<VecDeque<bool>>,
+}
+
+impl HttpSink {
+    /// Create a new webhook sink
+    pub fn new(config: &WebhookConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| format!("Failed to create webhook client: {}", e))?;
+
+        Ok(Self {
+            client,
+            url: config.url.clone(),
+            retries: config.retries,
+            permits: Semaphore::new(config.concurrency),
+            recent_deliveries: Mutex::new(VecDeque::with_capacity(HEALTH_HISTORY)),
+        })
+    }
+
+    /// Get the fraction of recent deliveries that succeeded, if any were made
+    pub fn success_rate(&self) -> Option<f64> {
+        let recent_deliveries = self.recent_deliveries.lock().unwrap();
+        if recent_deliveries.is_empty() {
+            return None;
+        }
+
+        let succeeded = recent_deliveries.iter().filter(|&&ok| ok).count();
+        Some(succeeded as f64 / recent_deliveries.len() as f64)
+    }
+
+    /// Remember the outcome of a delivery for the success rate
+    fn record_delivery(&self, succeeded: bool) {
+        let mut recent_deliveries = self.recent_deliveries.lock().unwrap();
+        if recent_deliveries.len() == HEALTH_HISTORY {
+            recent_deliveries.pop_front();
+        }
+        recent_deliveries.push_back(succeeded);
+    }
+
+    /// Make a single POST request, treating non-2xx responses as failures
+    async fn post(&self, content_type: &str, body: Bytes) -> Result<(), String> {
+        let response = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(format!("webhook responded with {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageSink for HttpSink {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn send(&self, record: &OutputRecord) -> Result<(), ProcessingError> {
+        let (content_type, body) = request_body(record).map_err(|e| {
+            ProcessingError::new(
+                DeadLetterReason::WebhookFailed,
+                format!("Failed to encode the webhook request: {}", e),
+            )
+        })?;
+
+        // Limit the number of requests in flight
+        let _permit = self.permits.acquire().await.unwrap();
+
+        let mut attempt = 0;
+        let result = loop {
+            match self.post(content_type, body.clone()).await {
+                Ok(_) => break Ok(()),
+                Err(e) if attempt < self.retries => {
+                    debug!("Webhook delivery failed, retrying: {}", e);
+                    tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.record_delivery(result.is_ok());
+        result.map_err(|e| {
+            ProcessingError::new(
+                DeadLetterReason::WebhookFailed,
+                format!("Failed to send to webhook: {}", e),
+            )
+        })
+    }
+}
+
+/// Get the content type and body of the request for a record
+///
+/// Webhook consumers always get `SensorData` records as JSON, so records encoded as protobuf
+/// for Kafka are encoded again. JSON and raw records are sent as they are.
+fn request_body(record: &OutputRecord) -> Result<(&'static str, Bytes), serde_json::Error> {
+    match (&record.format, &record.sensor_data) {
+        (SerializationFormat::Protobuf, Some(sensor_data)) => Ok((
+            SerializationFormat::Json.content_type(),
+            Bytes::from(serde_json::to_vec(sensor_data.as_ref())?),
+        )),
+        _ => Ok((record.content_type(), record.payload.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{PayloadFormat, SensorData};
+    use crate::processor::protobuf::encode_sensor_data;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    fn record(format: SerializationFormat, payload: Bytes) -> OutputRecord {
+        OutputRecord {
+            payload,
+            format,
+            timestamp: SystemTime::now(),
+            mqtt_topic: "lab/room1/temp".to_string(),
+            topic: None,
+            message_id: None,
+            correlation_id: None,
+            detected_format: None,
+            sensor_data: None,
+        }
+    }
+
+    fn sensor_data() -> SensorData {
+        SensorData {
+            sensor_id: "lab/room1/temp".to_string(),
+            message: r#"{"temp":21.5}"#.to_string(),
+            sensor_timestamp: SystemTime::UNIX_EPOCH,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn protobuf_records_are_posted_as_json() {
+        let data = sensor_data();
+        let mut record = record(
+            SerializationFormat::Protobuf,
+            Bytes::from(encode_sensor_data(&data)),
+        );
+        record.sensor_data = Some(Arc::new(data));
+
+        let (content_type, body) = request_body(&record).unwrap();
+
+        assert_eq!(content_type, "application/json");
+        let posted: SensorData = serde_json::from_slice(&body).unwrap();
+        assert_eq!(posted.sensor_id, "lab/room1/temp");
+        assert_eq!(posted.message, r#"{"temp":21.5}"#);
+    }
+
+    #[test]
+    fn json_and_raw_records_are_posted_as_they_are() {
+        let json = record(
+            SerializationFormat::Json,
+            Bytes::from(serde_json::to_vec(&sensor_data()).unwrap()),
+        );
+        let mut raw = record(SerializationFormat::Raw, Bytes::from_static(b"\x08\x01"));
+        raw.detected_format = Some(PayloadFormat::Protobuf);
+
+        assert_eq!(
+            request_body(&json).unwrap(),
+            ("application/json", json.payload.clone())
+        );
+        assert_eq!(
+            request_body(&raw).unwrap(),
+            ("application/x-protobuf", raw.payload.clone())
+        );
+    }
+}
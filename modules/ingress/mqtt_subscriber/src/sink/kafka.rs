@@ -0,0 +1,77 @@
+//! Sink delivering messages to the Kafka sensor data topic
+
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use super::MessageSink;
+use crate::kafka::producer::KafkaProducer;
+use crate::models::{DeadLetterReason, OutputRecord, ProcessingError};
+
+/// Sink sending messages to Kafka
+pub struct KafkaSink {
+    kafka_producer: Arc<KafkaProducer>,
+}
+
+impl KafkaSink {
+    /// Create a new Kafka sink on top of the shared producer
+    pub fn new(kafka_producer: Arc<KafkaProducer>) -> Self {
+        Self { kafka_producer }
+    }
+}
+
+#[async_trait]
+impl MessageSink for KafkaSink {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn send(&self, record: &OutputRecord) -> Result<(), ProcessingError> {
+        // TODO: Add additional logic to store non-delivered messages in e.g. temporary storage
+
+        // The producer's errors already say why the message was not sent
+        self.kafka_producer
+            .send_sensor_data(record)
+            .await
+            .map_err(|e| ProcessingError::new(DeadLetterReason::KafkaFailed, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_kafka_configs;
+    use crate::models::SerializationFormat;
+    use bytes::Bytes;
+    use std::time::SystemTime;
+
+    #[tokio::test]
+    async fn producer_errors_are_passed_through_unwrapped() {
+        let producer = Arc::new(KafkaProducer::unconnected(
+            &load_kafka_configs(),
+            Vec::new(),
+        ));
+        let sink = KafkaSink::new(Arc::clone(&producer));
+        let record = OutputRecord {
+            payload: Bytes::from_static(b"{}"),
+            format: SerializationFormat::Json,
+            timestamp: SystemTime::now(),
+            mqtt_topic: "lab/room1/temp".to_string(),
+            topic: None,
+            message_id: None,
+            correlation_id: None,
+            detected_format: None,
+            sensor_data: None,
+        };
+
+        let error = sink.send(&record).await.unwrap_err();
+
+        assert_eq!(error.reason, DeadLetterReason::KafkaFailed);
+        assert_eq!(
+            error.message,
+            format!(
+                "Skipped sending to Kafka (topic {} not available)",
+                producer.sensor_data_topic()
+            )
+        );
+    }
+}
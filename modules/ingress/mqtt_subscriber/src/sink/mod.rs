@@ -0,0 +1,121 @@
+//! Output sinks that processed messages are delivered to
+
+pub mod fanout;
+pub mod http;
+pub mod kafka;
+#[cfg(feature = "s3")]
+pub mod s3;
+
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::config::SinkConfig;
+use crate::kafka::producer::KafkaProducer;
+use crate::models::{OutputRecord, ProcessingError};
+use fanout::FanOutSink;
+use http::HttpSink;
+use kafka::KafkaSink;
+#[cfg(feature = "s3")]
+use s3::S3Sink;
+
+/// A destination for processed messages
+#[async_trait]
+pub trait MessageSink: Send + Sync {
+    /// Name of the sink, used in logs
+    fn name(&self) -> &str;
+
+    /// Deliver a message to the sink
+    async fn send(&self, record: &OutputRecord) -> Result<(), ProcessingError>;
+}
+
+/// The configured output sinks
+pub struct OutputSinks {
+    /// Sink every message is delivered to
+    pub sink: Arc<dyn MessageSink>,
+    /// Webhook sink, if configured, kept for health reporting
+    pub webhook: Option<Arc<HttpSink>>,
+}
+
+/// Check that the configured outputs can be created, without creating them
+pub fn validate_sinks(config: &SinkConfig) -> Result<(), String> {
+    if config.outputs.is_empty() {
+        return Err("No output sinks configured".to_string());
+    }
+
+    for output in &config.outputs {
+        match output.name.as_str() {
+            "kafka" => {}
+            "webhook" if config.webhook.is_none() => {
+                return Err("The webhook sink requires WEBHOOK_URL to be set".to_string())
+            }
+            "webhook" => {}
+            #[cfg(feature = "s3")]
+            "s3" if config.s3.is_none() => {
+                return Err("The s3 sink requires S3_BUCKET to be set".to_string())
+            }
+            #[cfg(feature = "s3")]
+            "s3" => {}
+            #[cfg(not(feature = "s3"))]
+            "s3" => {
+                return Err(
+                    "The s3 sink requires the service to be built with the `s3` feature"
+                        .to_string(),
+                )
+            }
+            name => return Err(format!("Unknown output sink: {}", name)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Create the sinks for the configured outputs
+///
+/// A single output is used directly, multiple outputs are combined into a fan-out sink.
+pub async fn create_sinks(
+    config: &SinkConfig,
+    kafka_producer: &Arc<KafkaProducer>,
+) -> Result<OutputSinks, String> {
+    let mut sinks: Vec<(Arc<dyn MessageSink>, bool)> = Vec::new();
+    let mut webhook = None;
+
+    for output in &config.outputs {
+        let sink: Arc<dyn MessageSink> = match output.name.as_str() {
+            "kafka" => Arc::new(KafkaSink::new(Arc::clone(kafka_producer))),
+            "webhook" => {
+                let webhook_config = config
+                    .webhook
+                    .as_ref()
+                    .ok_or("The webhook sink requires WEBHOOK_URL to be set")?;
+                let sink = Arc::new(HttpSink::new(webhook_config)?);
+                webhook = Some(Arc::clone(&sink));
+                sink
+            }
+            #[cfg(feature = "s3")]
+            "s3" => {
+                let s3_config = config
+                    .s3
+                    .as_ref()
+                    .ok_or("The s3 sink requires S3_BUCKET to be set")?;
+                S3Sink::new(s3_config, kafka_producer.sensor_data_topic()).await
+            }
+            #[cfg(not(feature = "s3"))]
+            "s3" => {
+                return Err(
+                    "The s3 sink requires the service to be built with the `s3` feature"
+                        .to_string(),
+                )
+            }
+            name => return Err(format!("Unknown output sink: {}", name)),
+        };
+        sinks.push((sink, output.required));
+    }
+
+    let sink = match sinks.len() {
+        0 => return Err("No output sinks configured".to_string()),
+        1 => sinks.pop().unwrap().0,
+        _ => Arc::new(FanOutSink::new(sinks)),
+    };
+
+    Ok(OutputSinks { sink, webhook })
+}
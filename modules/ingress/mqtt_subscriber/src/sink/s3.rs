@@ -0,0 +1,219 @@
+//! Sink archiving messages to an S3-compatible object store in batches
+
+use async_trait::async_trait;
+use aws_sdk_s3::config::{BehaviorVersion, Region};
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{debug, error};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::MessageSink;
+use crate::config::S3Config;
+use crate::models::{DeadLetterReason, OutputRecord, ProcessingError};
+
+/// Base delay between upload attempts, doubled after every retry
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Messages of one topic waiting to be uploaded
+struct Batch {
+    /// JSON lines of the messages
+    lines: Vec<u8>,
+    messages: usize,
+    /// Time the first message was added, used in the object key
+    started: DateTime<Utc>,
+}
+
+/// Uploads finished batches, shared with the background upload tasks
+struct Uploader {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    retries: u32,
+    /// Counter making the keys of batches started in the same millisecond unique
+    sequence: AtomicU64,
+}
+
+impl Uploader {
+    /// Compress a batch and upload it, retrying failed uploads with exponential backoff
+    ///
+    /// A batch that still can't be uploaded is logged and dropped.
+    async fn upload(&self, topic: String, batch: Batch) {
+        let key = format!(
+            "{}{}/{}-{}.jsonl.gz",
+            self.prefix,
+            topic,
+            batch.started.format("%Y/%m/%d/%H%M%S%.3f"),
+            self.sequence.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let body = match encoder
+            .write_all(&batch.lines)
+            .and_then(|_| encoder.finish())
+        {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to compress S3 batch {}: {}", key, e);
+                return;
+            }
+        };
+
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .content_type("application/x-ndjson")
+                .content_encoding("gzip")
+                .body(ByteStream::from(body.clone()))
+                .send()
+                .await;
+
+            match result {
+                Ok(_) => {
+                    debug!("Uploaded {} messages to S3 as {}", batch.messages, key);
+                    return;
+                }
+                Err(e) if attempt < self.retries => {
+                    debug!("S3 upload of {} failed, retrying: {}", key, e);
+                    tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to upload {} to S3, dropping {} messages: {}",
+                        key,
+                        batch.messages,
+                        DisplayErrorContext(e)
+                    );
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Sink collecting messages into per-topic batches of gzipped JSON lines and writing them
+/// to an S3 bucket
+///
+/// A batch is uploaded once it reaches the configured size, and all pending batches are
+/// uploaded at the configured interval. Uploads run in the background, so delivery to this
+/// sink only fails for payloads that aren't JSON.
+pub struct S3Sink {
+    uploader: Arc<Uploader>,
+    /// Topic used for records that don't name one
+    default_topic: String,
+    batch_max_bytes: usize,
+    batches: Mutex<HashMap<String, Batch>>,
+}
+
+impl S3Sink {
+    /// Create a new S3 sink and start uploading its batches at the configured interval
+    pub async fn new(config: &S3Config, default_topic: &str) -> Arc<Self> {
+        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(config.region.clone()))
+            .load()
+            .await;
+        let mut s3_config = aws_sdk_s3::config::Builder::from(&sdk_config);
+        if let Some(endpoint) = &config.endpoint {
+            // S3-compatible stores are usually addressed by path rather than subdomain
+            s3_config = s3_config.endpoint_url(endpoint).force_path_style(true);
+        }
+
+        let sink = Arc::new(Self {
+            uploader: Arc::new(Uploader {
+                client: Client::from_conf(s3_config.build()),
+                bucket: config.bucket.clone(),
+                prefix: config.prefix.clone(),
+                retries: config.retries,
+                sequence: AtomicU64::new(0),
+            }),
+            default_topic: default_topic.to_string(),
+            batch_max_bytes: config.batch_max_bytes,
+            batches: Mutex::new(HashMap::new()),
+        });
+
+        let weak_sink = Arc::downgrade(&sink);
+        let batch_interval = config.batch_interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(batch_interval);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match weak_sink.upgrade() {
+                    Some(sink) => sink.flush(),
+                    None => break,
+                }
+            }
+        });
+
+        sink
+    }
+
+    /// Upload all pending batches
+    fn flush(&self) {
+        let batches = std::mem::take(&mut *self.batches.lock().unwrap());
+        for (topic, batch) in batches {
+            self.start_upload(topic, batch);
+        }
+    }
+
+    /// Upload a batch in the background
+    fn start_upload(&self, topic: String, batch: Batch) {
+        let uploader = Arc::clone(&self.uploader);
+        tokio::spawn(async move { uploader.upload(topic, batch).await });
+    }
+}
+
+#[async_trait]
+impl MessageSink for S3Sink {
+    fn name(&self) -> &str {
+        "s3"
+    }
+
+    async fn send(&self, record: &OutputRecord) -> Result<(), ProcessingError> {
+        // Re-encode the payload so every message takes up exactly one line
+        let mut line = serde_json::from_slice::<serde_json::Value>(&record.payload)
+            .and_then(|value| serde_json::to_vec(&value))
+            .map_err(|e| {
+                ProcessingError::new(
+                    DeadLetterReason::InvalidPayload,
+                    format!("The S3 sink only archives JSON payloads: {}", e),
+                )
+            })?;
+        line.push(b'\n');
+
+        let topic = record.topic.as_deref().unwrap_or(&self.default_topic);
+        let full_batch = {
+            let mut batches = self.batches.lock().unwrap();
+            let batch = batches.entry(topic.to_string()).or_insert_with(|| Batch {
+                lines: Vec::new(),
+                messages: 0,
+                started: Utc::now(),
+            });
+            batch.lines.extend_from_slice(&line);
+            batch.messages += 1;
+
+            if batch.lines.len() >= self.batch_max_bytes {
+                batches.remove(topic)
+            } else {
+                None
+            }
+        };
+
+        if let Some(batch) = full_batch {
+            self.start_upload(topic.to_string(), batch);
+        }
+        Ok(())
+    }
+}